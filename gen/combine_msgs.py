@@ -2,7 +2,7 @@ import json
 import os
 
 def combine_json_files(input_folder, output_file):
-    combined_data = {}
+    combined_entries = {}
     for root, dirs, files in os.walk(input_folder):
         for name in files:
             if name.endswith(".msg.23.json"):
@@ -11,14 +11,14 @@ def combine_json_files(input_folder, output_file):
                     # Load JSON data
                     with open(filepath, 'r') as f:
                         data = json.load(f)
-                        # Merge JSON content
-                        combined_data.update(data)
+                        # Merge the entries, the header values differ between files
+                        combined_entries.update(data["entries"])
                 except Exception as e:
                     print(f"Error processing file {filepath}: {e}")
 
     # Write combined data to the output file
     with open(output_file, 'w') as f:
-        json.dump(combined_data, f, indent=4)
+        json.dump({"entries": combined_entries}, f, indent=4)
 
     print(f"Combined JSON saved to {output_file}")
 
@@ -6,13 +6,13 @@ f = open("outputs/skills/skillcommondata.user.3.json")
 skill_common_data = json.load(f)
 
 f = open("outputs/msg/skillcommon.msg.23")
-skill_common_msg_data = json.load(f)
+skill_common_msg_data = json.load(f)["entries"]
 
 f = open("outputs/skills/skilldata.user.3.json")
 skill_data = json.load(f)
 
 f = open("outputs/msg/skill.msg.23")
-skill_msg_data = json.load(f)
+skill_msg_data = json.load(f)["entries"]
 
 @dataclass
 class Skill:
@@ -31,7 +31,7 @@ for s in skill_common_data['values']:
         continue
     id = s['skill_id']
     if id != "NONE":
-        skills[id] = Skill(name['content'][1], explain['content'][1], {})
+        skills[id] = Skill(name['content']['en'], explain['content']['en'], {})
 
 for s in skill_data['values']:
     name = skill_msg_data.get(s['skill_name'])
@@ -42,7 +42,7 @@ for s in skill_data['values']:
     id = s['skill_id']
     if skills.get(id) is not None:
         skill = skills[id]
-        skill.levels[level] = explain['content'][1]
+        skill.levels[level] = explain['content']['en']
 
 page = """
 {{NavigationMHWilds}}
@@ -0,0 +1,157 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse::ParseStream, parse_macro_input, Attribute, Data, DataEnum, DataStruct, DeriveInput,
+    Fields, LitInt, LitStr, Token,
+};
+
+// #[rsz("app.user_data.SkillCommonData.cData")] or #[rsz("...", crc = 0x1234abcd)]
+struct TypeAttr {
+    symbol: LitStr,
+    crc: Option<LitInt>,
+}
+
+fn parse_type_attr(attrs: &[Attribute]) -> syn::Result<Option<TypeAttr>> {
+    let Some(attr) = attrs.iter().find(|attr| attr.path().is_ident("rsz")) else {
+        return Ok(None);
+    };
+    attr.parse_args_with(|input: ParseStream| {
+        let symbol: LitStr = input.parse()?;
+        let mut crc = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() {
+            let key: syn::Ident = input.parse()?;
+            if key != "crc" {
+                return Err(syn::Error::new(key.span(), "expected `crc = ...`"));
+            }
+            input.parse::<Token![=]>()?;
+            crc = Some(input.parse()?);
+        }
+        Ok(Some(TypeAttr { symbol, crc }))
+    })
+}
+
+// #[rsz(name = "_Rare")] on a field, defaults to the field name
+fn parse_field_name(attrs: &[Attribute]) -> syn::Result<Option<LitStr>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("rsz")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+fn derive_struct(input: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let Some(TypeAttr { symbol, crc }) = parse_type_attr(&input.attrs)? else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "FromRsz structs need a #[rsz(\"type.name\")] attribute",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "FromRsz can only be derived for structs with named fields",
+        ));
+    };
+
+    let crc = match crc {
+        Some(crc) => quote!(Some(#crc)),
+        None => quote!(None),
+    };
+
+    let field_values = fields
+        .named
+        .iter()
+        .map(|field| {
+            let field_ident = field.ident.as_ref().unwrap();
+            let name = match parse_field_name(&field.attrs)? {
+                Some(name) => name,
+                None => LitStr::new(&field_ident.to_string(), field_ident.span()),
+            };
+            Ok(quote! {
+                #field_ident: crate::from_rsz::FieldFromRsz::field_from_rsz(
+                    crate::from_rsz::find_field(value, #name, rsz)?,
+                    rsz,
+                ).with_context(|| format!("{}.{}", #symbol, #name))?
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(quote! {
+        impl crate::from_rsz::FromRsz for #ident {
+            const SYMBOL: &'static str = #symbol;
+
+            fn from_rsz(
+                value: &crate::dersz::RszValue,
+                rsz: &crate::dersz::DeRsz,
+            ) -> anyhow::Result<Self> {
+                use anyhow::Context;
+                crate::from_rsz::check_type::<Self>(value, #crc, rsz)?;
+                Ok(#ident {
+                    #(#field_values,)*
+                })
+            }
+        }
+
+        impl crate::from_rsz::FieldFromRsz for #ident {
+            fn field_from_rsz(
+                field: &crate::dersz::RszType,
+                rsz: &crate::dersz::DeRsz,
+            ) -> anyhow::Result<Self> {
+                crate::from_rsz::instance_from_rsz(field, rsz)
+            }
+        }
+    })
+}
+
+fn derive_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    if let Some(variant) = data.variants.iter().find(|v| !matches!(v.fields, Fields::Unit)) {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "FromRsz enums can only have unit variants",
+        ));
+    }
+    let variants = data.variants.iter().map(|v| &v.ident);
+
+    Ok(quote! {
+        impl crate::from_rsz::FieldFromRsz for #ident {
+            fn field_from_rsz(
+                field: &crate::dersz::RszType,
+                rsz: &crate::dersz::DeRsz,
+            ) -> anyhow::Result<Self> {
+                let value = <i64 as crate::from_rsz::FieldFromRsz>::field_from_rsz(field, rsz)?;
+                #(
+                    if value == #ident::#variants as i64 {
+                        return Ok(#ident::#variants);
+                    }
+                )*
+                anyhow::bail!("Unknown value {} for enum {}", value, stringify!(#ident))
+            }
+        }
+    })
+}
+
+// The generated impls name crate::from_rsz, crate::dersz and anyhow by path, so the derive
+// only works inside mhwsgen itself
+#[proc_macro_derive(FromRsz, attributes(rsz))]
+pub fn derive_from_rsz(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let result = match &input.data {
+        Data::Struct(data) => derive_struct(&input, data),
+        Data::Enum(data) => derive_enum(&input, data),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "FromRsz cannot be derived for unions",
+        )),
+    };
+    result.unwrap_or_else(syn::Error::into_compile_error).into()
+}
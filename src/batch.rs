@@ -0,0 +1,168 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// same as the main thread, parsing nested structs recurses
+const WORKER_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// How a list of files went, printed at the end of list mode
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    processed: usize,
+    succeeded: usize,
+    // error kind -> files that failed with it
+    failed: BTreeMap<String, Vec<String>>,
+    // file type -> number of files and time spent on them
+    times: BTreeMap<&'static str, (usize, Duration)>,
+}
+
+impl BatchSummary {
+    pub fn record(&mut self, file: &str, file_type: &'static str, elapsed: Duration, result: anyhow::Result<()>) {
+        self.processed += 1;
+        let (count, total) = self.times.entry(file_type).or_default();
+        *count += 1;
+        *total += elapsed;
+        match result {
+            Ok(()) => self.succeeded += 1,
+            Err(e) => {
+                eprintln!("[ERROR] Error processing file {e:?}");
+                self.failed.entry(error_kind(&e)).or_default().push(file.to_string());
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        let failed = self.processed - self.succeeded;
+        println!("[INFO] {} file(s) processed, {} succeeded, {failed} failed", self.processed, self.succeeded);
+        let mut kinds = self.failed.iter().collect::<Vec<_>>();
+        kinds.sort_by_key(|(_, files)| Reverse(files.len()));
+        for (kind, files) in kinds {
+            println!("[FAILED] {} file(s): {kind}", files.len());
+            for file in files.iter().take(3) {
+                println!("    {file}");
+            }
+            if files.len() > 3 {
+                println!("    ... and {} more", files.len() - 3);
+            }
+        }
+        // time spent in each file, with several jobs this is more than the time taken
+        for (file_type, (count, total)) in &self.times {
+            let per_file = total.as_millis() / *count as u128;
+            println!("[TIME] {file_type}: {count} file(s) in {} ms, {per_file} ms per file", total.as_millis());
+        }
+    }
+}
+
+// Errors are formatted messages, so the kind is the innermost reason with the numbers taken out
+fn error_kind(e: &anyhow::Error) -> String {
+    let message = e.root_cause().to_string();
+    let reason = message.rsplit("Reason: ").next().unwrap_or_default();
+    let line = reason.lines().next().unwrap_or_default();
+    let mut kind = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let in_word = kind.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if c.is_ascii_digit() && !in_word {
+            while chars.peek().is_some_and(|c| c.is_ascii_hexdigit() || *c == 'x') {
+                chars.next();
+            }
+            kind.push('N');
+        } else {
+            kind.push(c);
+        }
+    }
+    kind
+}
+
+// Runs work on the items with `jobs` threads, the results are given to done in the order of the items
+pub fn run_in_order<T: Sync, R: Send>(items: &[T], jobs: usize, work: impl Fn(&T) -> R + Sync, mut done: impl FnMut(R)) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            let sender = sender.clone();
+            let (next, work) = (&next, &work);
+            thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, move || loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    if sender.send((i, work(item))).is_err() {
+                        break;
+                    }
+                })
+                .expect("Error Creating Worker Thread");
+        }
+        drop(sender);
+
+        // results that finished before the ones in front of them wait here
+        let mut pending = BTreeMap::new();
+        let mut next_done = 0;
+        for (i, result) in receiver {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&next_done) {
+                done(result);
+                next_done += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn error_kind_strips_numbers() {
+        let e = anyhow!("File: \"a.user.3\" Reason: Could not parse instance 12 at offset 0x1f0");
+        assert_eq!(error_kind(&e), "Could not parse instance N at offset N");
+        let e = anyhow!("Unknown hash 3f2a1b00 in [10]\nsecond line 5");
+        assert_eq!(error_kind(&e), "Unknown hash N in [N]");
+        // numbers that are part of a name stay
+        let e = anyhow!("No field cData2 in app.Foo0x1f_2");
+        assert_eq!(error_kind(&e), "No field cData2 in app.Foo0x1f_2");
+        let e = Err::<(), _>(anyhow!("Reason: Bad offset 16")).context("File: \"b.user.3\"").unwrap_err();
+        assert_eq!(error_kind(&e), "Bad offset N");
+    }
+
+    #[test]
+    fn summary_groups_errors_by_kind() {
+        let mut summary = BatchSummary::default();
+        summary.record("a", "user", Duration::from_millis(2), Ok(()));
+        summary.record("b", "user", Duration::from_millis(4), Err(anyhow!("Bad offset 1")));
+        summary.record("c", "msg", Duration::from_millis(1), Err(anyhow!("Bad offset 2")));
+        assert_eq!((summary.processed, summary.succeeded), (3, 1));
+        assert_eq!(summary.failed["Bad offset N"], ["b", "c"]);
+        assert_eq!(summary.times["user"], (2, Duration::from_millis(6)));
+    }
+
+    #[test]
+    fn results_come_back_in_order() {
+        let items = (0..20u64).collect::<Vec<_>>();
+        let started = Mutex::new(vec![]);
+        let mut results = vec![];
+        // the first items take the longest, so later ones finish before them
+        run_in_order(&items, 4, |i| {
+            started.lock().unwrap().push(*i);
+            thread::sleep(Duration::from_millis(20u64.saturating_sub(*i * 4)));
+            i * 10
+        }, |r| results.push(r));
+        assert_eq!(results, items.iter().map(|i| i * 10).collect::<Vec<_>>());
+        assert_eq!(started.into_inner().unwrap().len(), items.len());
+
+        // more jobs than items and no items
+        let mut results = vec![];
+        run_in_order(&[1, 2], 8, |i| i + 1, |r| results.push(r));
+        assert_eq!(results, [2, 3]);
+        run_in_order(&[] as &[u32], 4, |_| -> u32 { unreachable!() }, |_| unreachable!());
+    }
+}
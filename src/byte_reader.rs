@@ -1,16 +1,24 @@
 use byteorder::{self, LittleEndian, ReadBytesExt};
-use std::{fs::{self}, io::{Error, ErrorKind, Result}};
+use memmap2::Mmap;
+use std::{borrow::Cow, fs::File, io::{Error, ErrorKind, Result}, path::Path};
+
+// Maps the file instead of reading it, parsers borrow from the map
+pub fn map_file<P: AsRef<Path>>(path: P) -> Result<Mmap> {
+    let file = File::open(path)?;
+    // the game files aren't written to while they are being dumped
+    unsafe { Mmap::map(&file) }
+}
 
+// Reader over a borrowed buffer (a memory map or an archive buffer), or an owned one for decrypted data
 #[derive(Debug)]
-pub struct BytesFile {
-    pub data: Vec<u8>,
+pub struct BytesFile<'data> {
+    pub data: Cow<'data, [u8]>,
     pub index: usize,
 }
 
-impl BytesFile {
-    pub fn new(file_name: String) -> Result<BytesFile> {
-        let data = fs::read(file_name)?;
-        Ok(BytesFile { data, index: 0 })
+impl<'data> BytesFile<'data> {
+    pub fn new<D: Into<Cow<'data, [u8]>>>(data: D) -> BytesFile<'data> {
+        BytesFile { data: data.into(), index: 0 }
     }
 
     pub fn read<T: ReadBytesTyped>(&mut self) -> Result<T> {
@@ -27,35 +35,49 @@ impl BytesFile {
         T::readn::<N>(self)
     }
 
-    pub fn read_bytes_to_vec(&mut self, num: usize) -> Result<Vec<u8>> {
+    // the next num bytes without copying them
+    pub fn read_bytes(&mut self, num: usize) -> Result<&[u8]> {
         if self.index > self.data.len() {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid file Index, greater than data length"));
         }
-        let mut data = vec![0; num];
-        for i in 0..num {
-            let byte = u8::read(self)?;
-            data[i] = byte;
-        }
-        Ok(data)
+        let start = self.index;
+        self.next_bytes(num)?;
+        self.index += num;
+        Ok(&self.data[start..start + num])
+    }
+
+    pub fn read_bytes_to_vec(&mut self, num: usize) -> Result<Vec<u8>> {
+        Ok(self.read_bytes(num)?.to_vec())
     }
 
     pub fn read_utf16(&mut self, from: usize) -> Result<String> {
+        if from > self.data.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid file Index, greater than data length"));
+        }
+        let units = self.data[from..].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]));
         let mut data: Vec<u16> = vec![];
-        self.index = from;
-        loop {
-            if self.index > self.data.len() {
-                return Err(Error::new(ErrorKind::InvalidData, "Invalid file Index, greater than data length"));
-            }
-            let c = u16::read(self)?;
+        let mut terminated = false;
+        for c in units {
             if c == 0 {
+                terminated = true;
                 break;
             }
             data.push(c);
         }
-        let string = String::from_utf16(&data).unwrap();
+        if !terminated {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Read past the end of the data"));
+        }
+        let string = String::from_utf16(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.index = from + (data.len() + 1) * 2;
         Ok(string)
     }
 
+    // the next n bytes, errors instead of panicking at the end of the data
+    fn next_bytes(&self, n: usize) -> Result<&[u8]> {
+        self.data.get(self.index..self.index + n)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Read past the end of the data"))
+    }
+
     pub fn len(&self) -> usize {
         self.data.len()
     }
@@ -66,19 +88,19 @@ impl BytesFile {
 }
 
 pub trait ReadBytesTyped: Sized {
-    fn read(file: &mut BytesFile) -> Result<Self>;
-    fn readn<const N: usize>(file: &mut BytesFile) -> Result<[Self; N]>;
+    fn read(file: &mut BytesFile<'_>) -> Result<Self>;
+    fn readn<const N: usize>(file: &mut BytesFile<'_>) -> Result<[Self; N]>;
 }
 
 impl ReadBytesTyped for u64 {
-    fn read(file: &mut BytesFile) -> Result<u64> {
-        let mut data = &file.data[file.index..file.index + 8];
+    fn read(file: &mut BytesFile<'_>) -> Result<u64> {
+        let mut data = file.next_bytes(8)?;
         let res = data.read_u64::<LittleEndian>()?;
         file.seek(file.index + 8);
         Ok(res)
     }
 
-    fn readn<const N: usize>(file: &mut BytesFile) -> Result<[u64; N]> {
+    fn readn<const N: usize>(file: &mut BytesFile<'_>) -> Result<[u64; N]> {
         let mut data = [0u64; N];
         for i in 0..N {
             data[i] = file.read::<u64>()?;
@@ -88,13 +110,13 @@ impl ReadBytesTyped for u64 {
 }
 
 impl ReadBytesTyped for u32 {
-    fn read(file: &mut BytesFile) -> Result<u32> {
-        let res = (&file.data[file.index..file.index + 4]).read_u32::<LittleEndian>()?;
+    fn read(file: &mut BytesFile<'_>) -> Result<u32> {
+        let res = file.next_bytes(4)?.read_u32::<LittleEndian>()?;
         file.seek(file.index + 4);
         Ok(res)
     }
 
-    fn readn<const N: usize>(file: &mut BytesFile) -> Result<[u32; N]> {
+    fn readn<const N: usize>(file: &mut BytesFile<'_>) -> Result<[u32; N]> {
         let mut data = [0u32; N];
         for i in 0..N {
             data[i] = file.read::<u32>()?;
@@ -104,13 +126,13 @@ impl ReadBytesTyped for u32 {
 }
 
 impl ReadBytesTyped for u16 {
-    fn read(file: &mut BytesFile) -> Result<u16> {
-        let res = (&file.data[file.index..file.index + 2]).read_u16::<LittleEndian>()?;
+    fn read(file: &mut BytesFile<'_>) -> Result<u16> {
+        let res = file.next_bytes(2)?.read_u16::<LittleEndian>()?;
         file.seek(file.index + 2);
         Ok(res)
     }
 
-    fn readn<const N: usize>(file: &mut BytesFile) -> Result<[u16; N]> {
+    fn readn<const N: usize>(file: &mut BytesFile<'_>) -> Result<[u16; N]> {
         let mut data = [0u16; N];
         for i in 0..N {
             data[i] = file.read::<u16>()?;
@@ -120,13 +142,13 @@ impl ReadBytesTyped for u16 {
 }
 
 impl ReadBytesTyped for u8 {
-    fn read(file: &mut BytesFile) -> Result<u8> {
-        let res = file.data[file.index];
+    fn read(file: &mut BytesFile<'_>) -> Result<u8> {
+        let res = file.next_bytes(1)?[0];
         file.seek(file.index + 1);
         Ok(res)
     }
 
-    fn readn<const N: usize>(file: &mut BytesFile) -> Result<[u8; N]> {
+    fn readn<const N: usize>(file: &mut BytesFile<'_>) -> Result<[u8; N]> {
         let mut data = [0u8; N];
         for i in 0..N {
             data[i] = file.read::<u8>()?;
@@ -1,19 +1,85 @@
 use core::str;
 use std::{
-    collections::HashMap, io::{BufReader, Read, Seek}, sync::OnceLock
+    borrow::Cow, cell::RefCell, collections::{HashMap, HashSet}, fmt, io::{Cursor, Read, Seek, Write}, rc::Rc
 };
 
+use crate::diagnostic::ParseDiagnostic;
 use crate::file_ext::*;
+use crate::minifloat::{self, F16_FORMAT, F8_FORMAT};
+use crate::msg::MsgText;
 
 use anyhow::{anyhow, Context};
 use nalgebra_glm::{Mat4x4, Vec2, Vec3, Vec4};
-use serde::{ser::{SerializeSeq, SerializeStruct}, Deserialize, Serialize};
+use serde::{ser::{SerializeMap, SerializeSeq}, Deserialize, Serialize};
 use uuid::Uuid;
 use crate::rsz::TypeDescriptor;
+use crate::typedb::TypeDatabase;
 
-// enums to hold values in a lightweight Rsz Struct
+// utf16 text as it is stored in the rsz data, borrowed from the file and only decoded when it is dumped
+#[derive(Clone, PartialEq, Eq)]
+pub struct Utf16Str<'a>(Cow<'a, [u8]>);
+
+impl<'a> Utf16Str<'a> {
+    // u32 length in utf16 units then the text, checked to be valid utf16 without decoding it
+    fn read(data: &mut Cursor<&'a [u8]>) -> anyhow::Result<Utf16Str<'a>> {
+        let n = data.read_u32()? as usize;
+        let bytes = read_borrowed(data, n * 2)?;
+        if char::decode_utf16(units(bytes)).any(|c| c.is_err()) {
+            return Err(anyhow!("Invalid utf16 string"))
+        }
+        Ok(Utf16Str(Cow::Borrowed(bytes)))
+    }
+
+    // number of utf16 units, the null terminator is part of the text
+    pub fn len(&self) -> usize {
+        self.0.len() / 2
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_owned(self) -> Utf16Str<'static> {
+        Utf16Str(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+fn units(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]))
+}
+
+impl From<&str> for Utf16Str<'static> {
+    fn from(s: &str) -> Self {
+        Utf16Str(Cow::Owned(s.encode_utf16().flat_map(u16::to_le_bytes).collect()))
+    }
+}
+
+impl fmt::Display for Utf16Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        char::decode_utf16(units(&self.0))
+            .try_for_each(|c| fmt::Write::write_char(f, c.unwrap_or(char::REPLACEMENT_CHARACTER)))
+    }
+}
+
+impl fmt::Debug for Utf16Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+// the next n bytes of the data without copying them
+fn read_borrowed<'a>(data: &mut Cursor<&'a [u8]>, n: usize) -> std::io::Result<&'a [u8]> {
+    let start = data.position() as usize;
+    let bytes = start.checked_add(n)
+        .and_then(|end| data.get_ref().get(start..end))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Read past the end of the data"))?;
+    data.set_position((start + n) as u64);
+    Ok(bytes)
+}
+
+// enums to hold values in a lightweight Rsz Struct, strings and raw data borrow from the file
 #[derive(Debug, Clone)]
-pub enum RszType {
+pub enum RszType<'a> {
     // Numbers
     Int8(i8),
     Int16(i16),
@@ -23,6 +89,7 @@ pub enum RszType {
     UInt16(u16),
     UInt32(u32),
     UInt64(u64),
+    // raw bits so they get written back exactly, dumped as floats
     F8(u8),
     F16(u16),
     F32(f32),
@@ -54,19 +121,73 @@ pub enum RszType {
     Rect((u32, u32, u32, u32)),
     Color((u8, u8, u8, u8)),
     Bool(bool),
-    String(String),
+    String(Utf16Str<'a>),
     Guid([u8; 16]),
-    Array(Vec<RszType>),
-    Object(RszStruct<RszField>, u32),
+    Array(Vec<RszType<'a>>),
+    // hash of the field's type and the instance index, the definition is in the type database
+    Object(u32, u32),
     RuntimeType(String),
-    Struct(RszStruct<RszType>),
-    Enum(Box<RszType>, String),
-    OBB,
-    Data(Vec<u8>),
+    Struct(RszValue<'a>),
+    Enum(Box<RszType<'a>>, String),
+    // coord, extent and the rest of the field, which is usually padding
+    OBB((Mat4x4, Vec3), Cow<'a, [u8]>),
+    Data(Cow<'a, [u8]>),
+    // field type the parser doesn't know, kept as raw bytes when parsing leniently
+    Unknown(String, Cow<'a, [u8]>),
 }
 
-impl RszType {
-    fn from_field<F: Read + Seek>(data: &mut F, field: &RszField) -> anyhow::Result<RszType> {
+impl<'a> RszType<'a> {
+    // copies what borrows from the file, for values kept after it is closed
+    pub fn into_owned(self) -> RszType<'static> {
+        use RszType::*;
+        match self {
+            Int8(v) => Int8(v),
+            Int16(v) => Int16(v),
+            Int32(v) => Int32(v),
+            Int64(v) => Int64(v),
+            UInt8(v) => UInt8(v),
+            UInt16(v) => UInt16(v),
+            UInt32(v) => UInt32(v),
+            UInt64(v) => UInt64(v),
+            F8(v) => F8(v),
+            F16(v) => F16(v),
+            F32(v) => F32(v),
+            F64(v) => F64(v),
+            UInt2(v) => UInt2(v),
+            UInt3(v) => UInt3(v),
+            UInt4(v) => UInt4(v),
+            Int2(v) => Int2(v),
+            Int3(v) => Int3(v),
+            Int4(v) => Int4(v),
+            Float2(v) => Float2(v),
+            Float3(v) => Float3(v),
+            Float4(v) => Float4(v),
+            Mat4x4(v) => Mat4x4(v),
+            Vec2(v) => Vec2(v),
+            Vec3(v) => Vec3(v),
+            Vec4(v) => Vec4(v),
+            Quaternion(v) => Quaternion(v),
+            Range(v) => Range(v),
+            RangeI(v) => RangeI(v),
+            AABB(v) => AABB(v),
+            Capsule(v) => Capsule(v),
+            Rect(v) => Rect(v),
+            Color(v) => Color(v),
+            Bool(v) => Bool(v),
+            String(v) => String(v.into_owned()),
+            Guid(v) => Guid(v),
+            Array(values) => Array(values.into_iter().map(RszType::into_owned).collect()),
+            Object(hash, ptr) => Object(hash, ptr),
+            RuntimeType(v) => RuntimeType(v),
+            Struct(value) => Struct(value.into_owned()),
+            Enum(underlying, name) => Enum(Box::new(underlying.into_owned()), name),
+            OBB(v, rest) => OBB(v, Cow::Owned(rest.into_owned())),
+            Data(v) => Data(Cow::Owned(v.into_owned())),
+            Unknown(r#type, v) => Unknown(r#type, Cow::Owned(v.into_owned())),
+        }
+    }
+
+    fn from_field(data: &mut Cursor<&'a [u8]>, field: &RszField, db: &TypeDatabase, lenient: bool) -> anyhow::Result<RszType<'a>> {
         data.seek_align_up(field.align.into()).with_context(|| {
             format!("{:?}", field)
         })?;
@@ -111,14 +232,7 @@ impl RszType {
             "Range" => RszType::Range((data.read_u32()?, data.read_u32()?)),
             "RangeI" => RszType::RangeI((data.read_i32()?, data.read_i32()?)),
 
-            "Data" => {
-                let mut v = Vec::new();
-                //let n = data.read_u32();
-                for _ in 0..field.size {
-                    v.push(data.read_u8()?);
-                }
-                RszType::Data(v)
-            },
+            "Data" => RszType::Data(Cow::Borrowed(read_borrowed(data, field.size as usize)?)),
             "AABB" => {
                 RszType::AABB((data.read_f32vec3()?, data.read_f32vec3()?))
             },
@@ -129,8 +243,11 @@ impl RszType {
                 RszType::Rect((data.read_u32()?, data.read_u32()?, data.read_u32()?, data.read_u32()?))
             },
             "OBB" => {
-                data.seek_relative(field.size.into())?;
-                RszType::OBB
+                if field.size < 76 {
+                    return Err(anyhow!("OBB field too small {:?}", field))
+                }
+                let obb = (data.read_f32m4x4()?, data.read_f32vec3()?);
+                RszType::OBB(obb, Cow::Borrowed(read_borrowed(data, field.size as usize - 76)?))
             },
             "Guid" => {
                 let mut buf = [0; 16];
@@ -140,12 +257,12 @@ impl RszType {
                 RszType::Guid(buf) // make it read ?????? idek what this comment means
             },
             "Bool" => RszType::Bool(data.read_bool()?),
-            "String" | "Resource" => RszType::String(data.read_utf16str()?),
+            "String" | "Resource" => RszType::String(Utf16Str::read(data)?),
             "Struct" => {
                 let x;
-                if let Some(mapped_hash) = RszDump::name_map().get(&field.original_type) {
-                    if let Some(r#struct) = RszDump::rsz_map().get(&mapped_hash) {
-                        let v = RszDump::parse_struct(data, TypeDescriptor{hash: *mapped_hash, crc: r#struct.crc})?;
+                if let Some(mapped_hash) = db.names.get(&field.original_type) {
+                    if let Some(r#struct) = db.structs.get(&mapped_hash) {
+                        let v = RszDump::parse_struct(data, TypeDescriptor{hash: *mapped_hash, crc: r#struct.crc}, db, lenient)?;
                         x = RszType::Struct(v)
                     } else {
                         return Err(anyhow!("Name hash not in hash map {:X}", mapped_hash))
@@ -166,9 +283,9 @@ impl RszType {
             },
             "Object" | "UserData" => {
                 let x;
-                if let Some(mapped_hash) = RszDump::name_map().get(&field.original_type) {
-                    if let Some(r#struct) = RszDump::rsz_map().get(&mapped_hash) {
-                        x = RszType::Object(r#struct.clone(), data.read_u32()?)
+                if let Some(mapped_hash) = db.names.get(&field.original_type) {
+                    if db.structs.contains_key(mapped_hash) {
+                        x = RszType::Object(*mapped_hash, data.read_u32()?)
                     } else {
                         return Err(anyhow!("Name crc not in hash map {:X}", mapped_hash))
                     };
@@ -177,6 +294,10 @@ impl RszType {
                 };
                 x
             },
+            _ if lenient => {
+                let buf = read_borrowed(data, field.size as usize)?;
+                return Ok(RszType::Unknown(field.r#type.clone(), Cow::Borrowed(buf)))
+            },
             _ => {
                 return Err(anyhow!("Type {:?} is not implemented", field.r#type))
             }
@@ -190,9 +311,126 @@ impl RszType {
             Ok(r#type)
         }
     }
+
+    fn write_field<F: Write + Seek>(&self, data: &mut F, field: &RszField, db: &TypeDatabase) -> anyhow::Result<()> {
+        data.write_align_up(field.align.into()).with_context(|| {
+            format!("{:?}", field)
+        })?;
+        match self {
+            RszType::Int8(v) => data.write_i8(*v)?,
+            RszType::Int16(v) => data.write_i16(*v)?,
+            RszType::Int32(v) => data.write_i32(*v)?,
+            RszType::Int64(v) => data.write_i64(*v)?,
+            RszType::UInt8(v) => data.write_u8(*v)?,
+            RszType::UInt16(v) => data.write_u16(*v)?,
+            RszType::UInt32(v) => data.write_u32(*v)?,
+            RszType::UInt64(v) => data.write_u64(*v)?,
+            RszType::F8(v) => data.write_u8(*v)?,
+            RszType::F16(v) => data.write_u16(*v)?,
+            RszType::F32(v) => data.write_f32(*v)?,
+            RszType::F64(v) => data.write_f64(*v)?,
+
+            RszType::UInt2((x, y)) | RszType::Range((x, y)) => {
+                data.write_u32(*x)?;
+                data.write_u32(*y)?;
+            },
+            RszType::UInt3((x, y, z)) => {
+                data.write_u32(*x)?;
+                data.write_u32(*y)?;
+                data.write_u32(*z)?;
+            },
+            RszType::UInt4((x, y, z, w)) | RszType::Rect((x, y, z, w)) => {
+                data.write_u32(*x)?;
+                data.write_u32(*y)?;
+                data.write_u32(*z)?;
+                data.write_u32(*w)?;
+            },
+            RszType::Color((r, g, b, a)) => {
+                data.write_u8(*r)?;
+                data.write_u8(*g)?;
+                data.write_u8(*b)?;
+                data.write_u8(*a)?;
+            },
+            RszType::Int2((x, y)) | RszType::RangeI((x, y)) => {
+                data.write_i32(*x)?;
+                data.write_i32(*y)?;
+            },
+            RszType::Int3((x, y, z)) => {
+                data.write_i32(*x)?;
+                data.write_i32(*y)?;
+                data.write_i32(*z)?;
+            },
+            RszType::Int4((x, y, z, w)) => {
+                data.write_i32(*x)?;
+                data.write_i32(*y)?;
+                data.write_i32(*z)?;
+                data.write_i32(*w)?;
+            },
+            RszType::Vec2(v) => {
+                data.write_f32vec2(v)?;
+                data.write_u64(0)?;
+            },
+            RszType::Vec3(v) => {
+                data.write_f32vec3(v)?;
+                data.write_u32(0)?;
+            },
+            RszType::Vec4(v) => data.write_f32vec4(v)?,
+            RszType::Quaternion((x, y, z, w)) => {
+                data.write_f32(*x)?;
+                data.write_f32(*y)?;
+                data.write_f32(*z)?;
+                data.write_f32(*w)?;
+            },
+            RszType::Float2(v) => data.write_f32vec2(v)?,
+            RszType::Float3(v) => data.write_f32vec3(v)?,
+            RszType::Float4(v) => data.write_f32vec4(v)?,
+            RszType::Mat4x4(v) => data.write_f32m4x4(v)?,
+
+            RszType::OBB((coord, extent), rest) => {
+                if field.size < 76 || rest.len() != field.size as usize - 76 {
+                    return Err(anyhow!("Expected {} bytes after the OBB extent for {:?}, got {}", field.size.saturating_sub(76), field, rest.len()))
+                }
+                data.write_f32m4x4(coord)?;
+                data.write_f32vec3(extent)?;
+                data.write_all(rest)?;
+            },
+            RszType::Unknown(_, v) | RszType::Data(v) => {
+                if v.len() != field.size as usize {
+                    return Err(anyhow!("Expected {} bytes for {:?}, got {}", field.size, field, v.len()))
+                }
+                data.write_all(v)?
+            },
+            RszType::AABB((min, max)) => {
+                data.write_f32vec3(min)?;
+                data.write_f32vec3(max)?;
+            },
+            RszType::Capsule((a, b, c)) => {
+                data.write_f32vec3(a)?;
+                data.write_f32vec3(b)?;
+                data.write_f32vec3(c)?;
+            },
+            RszType::Guid(buf) => data.write_all(buf)?,
+            RszType::Bool(v) => data.write_bool(*v)?,
+            RszType::String(v) => {
+                data.write_u32(v.len().try_into()?)?;
+                data.write_all(v.bytes())?;
+            },
+            RszType::Struct(v) => RszDump::write_struct(data, v, db)?,
+            RszType::RuntimeType(v) => {
+                data.write_u32(v.len().try_into()?)?;
+                data.write_all(v.as_bytes())?;
+            },
+            RszType::Object(_info, ptr) => data.write_u32(*ptr)?,
+            RszType::Enum(underlying, _name) => underlying.write_field(data, field, db)?,
+            RszType::Array(_) => {
+                return Err(anyhow!("Nested array in field {:?}", field))
+            },
+        };
+        Ok(())
+    }
 }
 
-pub struct RszTypeWithInfo<'a>(&'a RszType, &'a Vec<RszValue>);
+pub struct RszTypeWithInfo<'a>(pub &'a RszType<'a>, pub &'a DeRsz<'a>);
 
 impl<'a> Serialize for RszTypeWithInfo<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -200,7 +438,8 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
         S: serde::Serializer 
     {
         let rsz_type = self.0;
-        let structs = self.1;
+        let rsz = self.1;
+        let structs = &rsz.structs;
         use RszType::*;
         return match rsz_type {
             Int8(v) => serializer.serialize_i8(*v), 
@@ -212,9 +451,9 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
             UInt32(v) => serializer.serialize_u32(*v), 
             UInt64(v) => serializer.serialize_u64(*v), 
             Bool(v) => serializer.serialize_bool(*v),
-            String(v) => serializer.serialize_str(v),
-            F8(v) => serializer.serialize_u8(*v), 
-            F16(v) => serializer.serialize_u16(*v), 
+            String(v) => serializer.collect_str(v),
+            F8(v) => serialize_minifloat(F8_FORMAT, *v as u32, serializer),
+            F16(v) => serialize_minifloat(F16_FORMAT, *v as u32, serializer),
             F32(v) => serializer.serialize_f32(*v), 
             F64(v) => serializer.serialize_f64(*v),
             Vec2(v) => v.serialize(serializer),
@@ -236,21 +475,64 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
             Range(v) => v.serialize(serializer),
             RangeI(v) => v.serialize(serializer),
             AABB(v) => v.serialize(serializer),
+            // the rest is only dumped when it isn't padding
+            OBB((coord, extent), rest) if rest.iter().all(|&b| b == 0) => (coord, extent).serialize(serializer),
+            OBB((coord, extent), rest) => (coord, extent, &rest[..]).serialize(serializer),
             Capsule(v) => v.serialize(serializer),
             Rect(v) => v.serialize(serializer),
             Guid(id) => {
                 let id = Uuid::from_bytes_le(*id);
-                serializer.serialize_str(&id.to_string().as_str())
+                match rsz.msg_text.and_then(|msg_text| msg_text.get(&id)) {
+                    // the importer only reads "$guid" back
+                    Some((name, text)) => {
+                        let mut state = serializer.serialize_map(Some(3))?;
+                        state.serialize_entry("$guid", &id.to_string())?;
+                        state.serialize_entry("$msg", name)?;
+                        state.serialize_entry("$text", &text)?;
+                        state.end()
+                    },
+                    None => serializer.serialize_str(&id.to_string().as_str()),
+                }
             },
             RszType::Struct(r#struct) => {
-                let val = RszValueWithInfo(r#struct, structs);
+                let val = RszValueWithInfo(r#struct, rsz, None);
                 val.serialize(serializer)
             },
             RuntimeType(v) => v.serialize(serializer),
+            Object(_info, ptr) if rsz.externs.contains_key(ptr) => {
+                // a link to the other file, the importer turns it back into an extern slot.
+                // Inlined files are dumped in "$value" next to the link, that part isn't imported
+                let inlined = match rsz.inline_externs.get(ptr) {
+                    Some(extern_rsz) => match extern_rsz.root_values().next() {
+                        Some(root) => Some((root, extern_rsz)),
+                        None => return Err(serde::ser::Error::custom("Extern user file has no root")),
+                    },
+                    None => None,
+                };
+                let mut state = serializer.serialize_map(None)?;
+                state.serialize_entry("$extern", &rsz.externs[ptr])?;
+                if let Some(instance) = structs.get(*ptr as usize) {
+                    state.serialize_entry("$type", instance.name(rsz.db))?;
+                }
+                if let Some((root, extern_rsz)) = inlined {
+                    // ids are instance indices, so they only mean something inside one file
+                    extern_rsz.emitted.borrow_mut().clear();
+                    state.serialize_entry("$value", &RszValueWithInfo(root, extern_rsz, None))?;
+                }
+                state.end()
+            },
             Object(_info, ptr) => {
+                let id = (rsz.object_refs && *ptr != 0).then_some(*ptr);
+                if let Some(id) = id {
+                    if !rsz.emitted.borrow_mut().insert(id) {
+                        let mut state = serializer.serialize_map(Some(1))?;
+                        state.serialize_entry("$ref", &id)?;
+                        return state.end();
+                    }
+                }
                 match &structs.get(*ptr as usize) {
                     Some(struct_derefed) => {
-                        let val = RszValueWithInfo(struct_derefed, structs);
+                        let val = RszValueWithInfo(struct_derefed, rsz, id);
                         val.serialize(serializer)
                     }
                     None => {
@@ -262,11 +544,16 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
             RszType::Data(val) => {
                 val.serialize(serializer)
             },
+            Unknown(r#type, bytes) => {
+                let mut state = serializer.serialize_map(Some(2))?;
+                state.serialize_entry("$unsupported", r#type)?;
+                state.serialize_entry("$data", bytes)?;
+                state.end()
+            },
             Enum(underlying, name) => {
-                let underlying = *underlying.clone();
-                match underlying {
+                match &**underlying {
                     Object(_info, ptr) => {
-                        let res = &structs.get(ptr as usize);
+                        let res = &structs.get(*ptr as usize);
                         let struct_derefed = match res {
                             Some(struct_derefed) => {
                                 struct_derefed
@@ -280,7 +567,9 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
                         /*if struct_derefed.fields.len() == 0 {
                             return serializer.serialize_str(format!("{}, {:?}", ptr, struct_derefed).as_str());
                         }*/
-                        let x = struct_derefed.fields[0].clone();
+                        let Some(x) = struct_derefed.fields.first() else {
+                            return Err(serde::ser::Error::custom(format!("Enum Object {ptr} has no value field")))
+                        };
                         //serializer.serialize_str(format!("{x:?} name goes here").as_str());
                         let v = match x {
                             RszType::UInt64(v) => Ok(v.to_string()),
@@ -292,9 +581,9 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
                             RszType::Int16(v) => Ok(v.to_string()),
                             RszType::Int8(v) => Ok(v.to_string()),
                             RszType::Object(_info, ptr) => {
-                                match &structs.get(ptr as usize) {
+                                match &structs.get(*ptr as usize) {
                                     Some(struct_derefed) => {
-                                        let val = RszValueWithInfo(struct_derefed, structs);
+                                        let val = RszValueWithInfo(struct_derefed, rsz, None);
                                         return val.serialize(serializer)
                                     }
                                     None => {
@@ -308,34 +597,19 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
                                 Err(serde::ser::Error::custom("Unknown underlying Enum type"))
                             }
                         }?;
-                        match get_enum_name(name.to_string(), v.clone()){
-                            None => serializer.serialize_str(format!("{v} // Could not find enum value in map {name}").as_str()),
-                            Some(value) => serializer.serialize_str(&value)
-                        }
+                        serialize_enum(rsz.db, name, &v, serializer)
                     },
                     Int32(v) => {
-                        match get_enum_name(name.to_string(), v.to_string()) {
-                            None => serializer.serialize_str(format!("{v} // Could not find enum value in map {name}").as_str()),
-                            Some(value) => serializer.serialize_str(&value)
-                        }
+                        serialize_enum(rsz.db, name, &v.to_string(), serializer)
                     },
                     Int64(v) => {
-                        match get_enum_name(name.to_string(), v.to_string()) {
-                            None => serializer.serialize_str(format!("{v} // Could not find enum value in map {name}").as_str()),
-                            Some(value) => serializer.serialize_str(&value)
-                        }
+                        serialize_enum(rsz.db, name, &v.to_string(), serializer)
                     },
                     UInt32(v) => {
-                        match get_enum_name(name.to_string(), v.to_string()) {
-                            None => serializer.serialize_str(format!("{v} // Could not find enum value in map {name}").as_str()),
-                            Some(value) => serializer.serialize_str(&value)
-                        }
+                        serialize_enum(rsz.db, name, &v.to_string(), serializer)
                     },
                     UInt64(v) => {
-                        match get_enum_name(name.to_string(), v.to_string()) {
-                            None => serializer.serialize_str(format!("{v} // Could not find enum value in map {name}").as_str()),
-                            Some(value) => serializer.serialize_str(&value)
-                        }
+                        serialize_enum(rsz.db, name, &v.to_string(), serializer)
                     }
                     _ => {
                         eprintln!("{rsz_type:?}");
@@ -347,41 +621,82 @@ impl<'a> Serialize for RszTypeWithInfo<'a> {
                 //let struct_derefed = &structs.get(*ptr as usize).expect("Struct not in context");
                 let mut state = serializer.serialize_seq(Some(vec_of_types.len()))?;
                 for r#type in vec_of_types {
-                    let type_with_context = RszTypeWithInfo(r#type, structs);
+                    let type_with_context = RszTypeWithInfo(r#type, rsz);
                     state.serialize_element(&type_with_context)?;
                 }
                 state.end()
                     //serializer.serialize_str("NOT IMPLEMENTED")
 
             }
-            _ => serializer.serialize_str("NOT IMPLEMENTED")
         }
     }
 }
 
+// Flag enums are dumped as the list of their flags, with the bits that have no name as a hex string
+fn serialize_enum<S: serde::Serializer>(db: &TypeDatabase, name: &str, value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    if let Some(flags) = value.parse().ok().and_then(|value| db.get_flag_names(name, value)) {
+        return flags.serialize(serializer);
+    }
+    match db.get_enum_name(name, value) {
+        None => serializer.serialize_str(format!("{value} // Could not find enum value in map {name}").as_str()),
+        Some(value) => serializer.serialize_str(&value)
+    }
+}
+
+// json has no inf/nan, those keep their bits as a hex string
+fn serialize_minifloat<S: serde::Serializer>(format: (u32, u32), bits: u32, serializer: S) -> Result<S::Ok, S::Error> {
+    let value = minifloat::decode(format, bits);
+    if value.is_finite() {
+        serializer.serialize_f32(value)
+    } else {
+        serializer.serialize_str(&format!("{bits:#x}"))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RszField {
-    align: u32,
-    array: bool,
-    name: String,
-    native: bool,          // almost always false, except for some via types
-    original_type: String, //should also be used to index other structs
-    size: u32,
-    r#type: String, //basic type of the struct
+    pub align: u32,
+    pub array: bool,
+    pub name: String,
+    pub native: bool,          // almost always false, except for some via types
+    pub original_type: String, //should also be used to index other structs
+    pub size: u32,
+    pub r#type: String, //basic type of the struct
 }
 
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct RszStruct<T> {
-    name: String,
-    crc: u32,
-    hash: u32,
-    fields: Vec<T>,
+    pub name: String,
+    pub crc: u32,
+    pub hash: u32,
+    pub fields: Vec<T>,
 }
 
-pub type RszValue = RszStruct<RszType>;
+// An instance, its name and field definitions are looked up in the type database by hash
+#[derive(Debug, Clone)]
+pub struct RszValue<'a> {
+    pub hash: u32,
+    pub crc: u32,
+    pub fields: Vec<RszType<'a>>,
+}
 
-pub struct RszValueWithInfo<'a>(&'a RszValue, &'a Vec<RszValue>);
+impl RszValue<'_> {
+    pub fn into_owned(self) -> RszValue<'static> {
+        RszValue {
+            hash: self.hash,
+            crc: self.crc,
+            fields: self.fields.into_iter().map(RszType::into_owned).collect(),
+        }
+    }
+
+    pub fn name<'a>(&self, db: &'a TypeDatabase) -> &'a str {
+        db.structs.get(&self.hash).map_or("", |info| info.name.as_str())
+    }
+}
+
+// the optional id is the instance index, written as "$id" when dumping with object refs
+pub struct RszValueWithInfo<'a>(pub &'a RszValue<'a>, pub &'a DeRsz<'a>, pub Option<u32>);
 
 impl<'a> Serialize for RszValueWithInfo<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -390,14 +705,24 @@ impl<'a> Serialize for RszValueWithInfo<'a> {
 
             let r#struct = self.0;
             let context = self.1;
-            let struct_info = RszDump::rsz_map().get(&r#struct.hash).expect("Could not find struct in dump");
-            let mut state = serializer.serialize_struct("RszValue", r#struct.fields.len())?;
-            for i in 0..struct_info.fields.len() {
-                let field_value = &r#struct.fields[i];
-                let field_info = &struct_info.fields[i];
-                let name = &field_info.name;
+            let Some(struct_info) = context.db.structs.get(&r#struct.hash) else {
+                return Err(serde::ser::Error::custom(format!("Type {:08x} not in Rsz Dump", r#struct.hash)))
+            };
+            // extern slots hold an instance without values, those are dumped as links by the field pointing at them
+            if r#struct.fields.len() != struct_info.fields.len() {
+                return Err(serde::ser::Error::custom(format!(
+                    "Instance of {} has {} values for {} fields",
+                    struct_info.name, r#struct.fields.len(), struct_info.fields.len()
+                )))
+            }
+            // field names are borrowed from the type database, so this is a map instead of a struct
+            let mut state = serializer.serialize_map(Some(r#struct.fields.len() + self.2.is_some() as usize))?;
+            if let Some(id) = self.2 {
+                state.serialize_entry("$id", &id)?;
+            }
+            for (field_value, field_info) in r#struct.fields.iter().zip(&struct_info.fields) {
                 let serialize_context = RszTypeWithInfo(field_value, context);
-                state.serialize_field(name, &serialize_context)?;
+                state.serialize_entry(&field_info.name, &serialize_context)?;
             }
             state.end()
 
@@ -406,185 +731,299 @@ impl<'a> Serialize for RszValueWithInfo<'a> {
 
 
 
+// Adds the field to the path of the diagnostic, which is made at the innermost field that failed
+fn field_error<F: Read + Seek>(data: &mut F, mut error: anyhow::Error, segment: String) -> anyhow::Error {
+    if let Some(diagnostic) = error.downcast_mut::<ParseDiagnostic>() {
+        diagnostic.field_path.insert_str(0, &segment);
+        return error;
+    }
+    let mut diagnostic = ParseDiagnostic::from_reader("RSZ data", data);
+    diagnostic.field_path = segment;
+    error.context(diagnostic)
+}
+
 pub struct RszDump;
 
 impl RszDump {
-    pub fn parse_struct<'a, F: 'a + Read + Seek>(
-        data: &mut F,
+    pub fn parse_struct<'a>(
+        data: &mut Cursor<&'a [u8]>,
         type_descriptor: TypeDescriptor,
-    ) -> anyhow::Result<RszValue> {
-        let struct_type = RszDump::rsz_map()
+        db: &TypeDatabase,
+        lenient: bool,
+    ) -> anyhow::Result<RszValue<'a>> {
+        let struct_type = db.structs
             .get(&type_descriptor.hash)
-            .with_context(|| format!("Unexpected Type: not in Rsz Dump"))?;
+            .with_context(|| "Unexpected Type: not in Rsz Dump".to_string())?;
         
         //println!("{:#?}", struct_type);
         let mut field_values = Vec::new();
         for field in &struct_type.fields {
             if field.array {
-                data.seek_align_up(4).with_context(||{
+                let count = data.seek_align_up(4).with_context(||{
                     format!("{:?}", field)
-                })?;
-                let count = data.read_u32()?;
-                let vals = (0..count).map(|_| {
-                    RszType::from_field(data, field)
-                }).collect::<anyhow::Result<Vec<RszType>>>()?;
+                }).and_then(|_| data.read_u32())
+                    .map_err(|e| field_error(data, e, format!(".{}", field.name)))?;
+                let vals = (0..count).map(|i| {
+                    RszType::from_field(data, field, db, lenient)
+                        .map_err(|e| field_error(data, e, format!(".{}[{i}]", field.name)))
+                }).collect::<anyhow::Result<Vec<_>>>()?;
                 field_values.push(RszType::Array(vals));
             } else {
-                let r#type = RszType::from_field(data, field)?;
+                let r#type = RszType::from_field(data, field, db, lenient)
+                    .map_err(|e| field_error(data, e, format!(".{}", field.name)))?;
                 field_values.push(r#type);
             }
         }
         //println!("{:#?}", field_values);
         Ok(RszValue {
-            name: struct_type.name.clone(),
-            crc: struct_type.crc,
+            crc: type_descriptor.crc,
             hash: struct_type.hash,
             fields: field_values,
         })
     }
 
-    fn rsz_map() -> &'static HashMap<u32, RszStruct<RszField>> {
-        #[derive(Debug, Clone, Deserialize)]
-        pub struct RszStructTemp<T> {
-            name: String,
-            crc: String,
-            fields: Vec<T>,
+    pub fn write_struct<F: Write + Seek>(
+        data: &mut F,
+        value: &RszValue,
+        db: &TypeDatabase,
+    ) -> anyhow::Result<()> {
+        let struct_type = db.structs
+            .get(&value.hash)
+            .with_context(|| "Unexpected Type: not in Rsz Dump".to_string())?;
+        if struct_type.fields.len() != value.fields.len() {
+            return Err(anyhow!(
+                "Field count mismatch for {}: expected {}, got {}",
+                struct_type.name, struct_type.fields.len(), value.fields.len()
+            ))
         }
-        static HASHMAP: OnceLock<HashMap<u32, RszStruct<RszField>>> = OnceLock::new();
-        HASHMAP.get_or_init(|| {
-            let mut m = HashMap::new();
-            let file = std::fs::File::open("rszmhwilds.json").unwrap();
-            let reader = BufReader::new(file);
-            let stream = serde_json::Deserializer::from_reader(reader).into_iter::<serde_json::Value>();
-
-            //let mut structs: Vec<RszStruct<RszField>> = Vec::new();
-            for value in stream {
-                match value {
-                    Ok(json_value) => {
-                        if let serde_json::Value::Object(map) = json_value {
-                            // This is where each struct is actually parsed
-                            for (_key, value) in map {
-                                //println!("{_key:?}, {value:?}");
-                                let mut rsz_struct: RszStructTemp<RszField> =
-                                                    serde_json::from_value(value).expect("Could not parse struct");
-
-                                for field in &mut rsz_struct.fields {
-                                    if field.original_type == "ace.user_data.ExcelUserData.cData[]" {
-                                        field.original_type = rsz_struct.name.clone() + ".cData[]"
-                                    }
-                                }
-                                let rsz_struct: RszStruct<RszField> = RszStruct {
-                                    name: rsz_struct.name,
-                                    crc: u32::from_str_radix(&rsz_struct.crc, 16).unwrap(),
-                                    hash: u32::from_str_radix(&_key, 16).unwrap(),
-                                    fields: rsz_struct.fields
-                                };
-                                m.insert(rsz_struct.hash, rsz_struct);
-                            }
-                        } else {
-                            println!("Expected a JSON object!");
-                        }
-                    }
-                    Err(e) => eprintln!("Error parsing JSON: {}", e),
-                }
-            }
-            m
-        })
-    }
 
-    fn name_map() -> &'static HashMap<String, u32> {
-        #[derive(Debug, Clone, Deserialize)]
-        pub struct RszStructTemp<T> {
-            name: String,
-            crc: String,
-            fields: Vec<T>,
-        }
-        static HASHMAP: OnceLock<HashMap<String, u32>> = OnceLock::new();
-        HASHMAP.get_or_init(|| {
-            let mut m = HashMap::new();
-            let file = std::fs::File::open("rszmhwilds.json").unwrap();
-            let reader = BufReader::new(file);
-            let stream = serde_json::Deserializer::from_reader(reader).into_iter::<serde_json::Value>();
-
-            //let mut structs: Vec<RszStruct<RszField>> = Vec::new();
-            for value in stream {
-                match value {
-                    Ok(json_value) => {
-                        if let serde_json::Value::Object(map) = json_value {
-                            // This is where each struct is actually parsed
-                            for (_key, value) in map {
-                                //println!("{_key:?}, {value:?}");
-                                let rsz_struct: RszStructTemp<RszField> =
-                                                serde_json::from_value(value).expect("Could not parse struct");
-                                let rsz_struct: RszStruct<RszField> = RszStruct {
-                                    name: rsz_struct.name,
-                                    crc: u32::from_str_radix(&rsz_struct.crc, 16).unwrap(),
-                                    hash: u32::from_str_radix(&_key, 16).unwrap(),
-                                    fields: rsz_struct.fields
-                                };
-                                m.insert(rsz_struct.name, rsz_struct.hash);
-                            }
-                        } else {
-                            println!("Expected a JSON object!");
-                        }
-                    }
-                    Err(e) => eprintln!("Error parsing JSON: {}", e),
+        for (field, r#type) in struct_type.fields.iter().zip(&value.fields) {
+            if field.array {
+                let RszType::Array(vals) = r#type else {
+                    return Err(anyhow!("Expected array for field {:?}", field))
+                };
+                data.write_align_up(4).with_context(||{
+                    format!("{:?}", field)
+                })?;
+                data.write_u32(vals.len().try_into()?)?;
+                for val in vals {
+                    val.write_field(data, field, db)?;
                 }
+            } else {
+                r#type.write_field(data, field, db)?;
             }
-            m
-        })
+        }
+        Ok(())
     }
 }
 
 
+// How user (and msg) files get dumped to json
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpOptions<'a> {
+    pub object_refs: bool,
+    pub lenient: bool,
+    // msg entries whose text gets dumped next to the guid fields that match them
+    pub msg_text: Option<&'a MsgText>,
+    // language ids msg files are dumped in, all of them when empty
+    pub languages: &'a [u32],
+}
+
 #[derive(Debug, Clone)]
-pub struct DeRsz {
-    pub roots: Vec<RszValue>,
-    pub structs: Vec<RszValue>,
+pub struct DeRsz<'a> {
+    // instance indices of the roots
+    pub roots: Vec<u32>,
+    pub structs: Vec<RszValue<'a>>,
+    pub externs: HashMap<u32, String>,
+    // extern slots whose user file was loaded, these get dumped in place of the link
+    pub inline_externs: HashMap<u32, Rc<DeRsz<'a>>>,
+    // dump each object once with its "$id" and later references to it as {"$ref": id}
+    pub object_refs: bool,
+    pub emitted: RefCell<HashSet<u32>>,
+    pub msg_text: Option<&'a MsgText>,
+    pub db: &'a TypeDatabase,
 }
 
-impl<'a> Serialize for DeRsz {
+impl<'a> Serialize for DeRsz<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer {
-            let mut state = serializer.serialize_struct("Rsz", self.roots.len())?;
-            let context = &self.structs;
-            for i in 0..self.roots.len() {
-            //for root in &self.roots { // do this to wrap in with context
-                let r#struct = self.roots[i].clone();
-                let x = RszDump::rsz_map().get(&r#struct.hash);
-                let name = match x {
-                    Some(v) => &v.name,
-                    None => "unknown struct?"
+            let mut state = serializer.serialize_map(Some(self.roots.len()))?;
+            let context = self;
+            self.emitted.borrow_mut().clear();
+            for &root in &self.roots {
+                let Some(r#struct) = self.structs.get(root as usize) else {
+                    continue
+                };
+                let Some(info) = self.db.structs.get(&r#struct.hash) else {
+                    return Err(serde::ser::Error::custom(format!("Root type {:08x} not in Rsz Dump", r#struct.hash)))
                 };
-                let val_with_context = RszValueWithInfo(&r#struct, &context);
-                println!("{}", r#struct.name);
-                state.serialize_field(name, &val_with_context)?;
+                // a root in an extern slot is only a link to the file it lives in
+                match self.externs.get(&root) {
+                    Some(path) => state.serialize_entry(&info.name, &HashMap::from([("$extern", path)]))?,
+                    None => state.serialize_entry(&info.name, &RszValueWithInfo(r#struct, context, None))?,
+                }
             }
             state.end()
     }
 }
 
-
-fn enum_map() -> &'static HashMap<String, HashMap<String, String>> {
-    static HASHMAP: OnceLock<HashMap<String, HashMap<String, String>>> = OnceLock::new();
-    HASHMAP.get_or_init(|| {
-        let json_data = std::fs::read_to_string("gen/enums.json").unwrap();
-        let hashmap: HashMap<String, HashMap<String, String>> = serde_json::from_str(&json_data).unwrap();
-        hashmap
-    })
+impl<'a> DeRsz<'a> {
+    // roots in extern slots live in another file, they have no values here
+    pub fn root_values(&self) -> impl Iterator<Item = &RszValue<'a>> {
+        self.roots.iter()
+            .filter(|root| !self.externs.contains_key(root))
+            .filter_map(|&root| self.structs.get(root as usize))
+    }
 }
 
-fn get_enum_name(name: String, value: String) -> Option<String> {
-    let name = name.replace("[]", "").replace("_Serializable", "_Fixed");
-    if let Some(map) = enum_map().get(&name) {
-        if let Some(value) = map.get(&value){
-            Some(value.to_string())
-        } else {
-            None
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::Msg;
+
+    const RSZ_DUMP: &str = r#"{
+        "100": { "name": "app.Text", "crc": "1", "fields": [
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Res", "type": "Resource", "original_type": "via.Prefab", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Blob", "type": "Data", "original_type": "app.Blob", "align": 1, "size": 4, "array": false, "native": false }
+        ] },
+        "200": { "name": "app.Box", "crc": "2", "fields": [
+            { "name": "_Box", "type": "OBB", "original_type": "via.OBB", "align": 16, "size": 80, "array": false, "native": false }
+        ] },
+        "300": { "name": "app.Ref", "crc": "3", "fields": [
+            { "name": "_Id", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_Other", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false }
+        ] }
+    }"#;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        let units = s.encode_utf16().collect::<Vec<_>>();
+        let mut bytes = (units.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(units.iter().flat_map(|c| c.to_le_bytes()));
+        bytes
+    }
+
+    fn borrows_from(value: &[u8], data: &[u8]) -> bool {
+        data.as_ptr_range().contains(&value.as_ptr())
+    }
+
+    #[test]
+    fn strings_and_data_borrow_from_the_file() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let mut data = utf16("ab\0");
+        data.extend([0, 0]);
+        data.extend(utf16("\0"));
+        data.extend([1, 2, 3, 4]);
+
+        let mut cursor = Cursor::new(&data[..]);
+        let value = RszDump::parse_struct(&mut cursor, TypeDescriptor { hash: 0x100, crc: 1 }, &db, false).unwrap();
+        assert_eq!(cursor.position(), data.len() as u64);
+        let [RszType::String(name), RszType::String(res), RszType::Data(blob)] = &value.fields[..] else {
+            panic!("{:?}", value.fields)
+        };
+        assert_eq!(name.to_string(), "ab\0");
+        assert_eq!(res.to_string(), "\0");
+        assert_eq!(&blob[..], [1, 2, 3, 4]);
+        assert!(matches!(&name.0, Cow::Borrowed(bytes) if borrows_from(bytes, &data)));
+        assert!(matches!(blob, Cow::Borrowed(bytes) if borrows_from(bytes, &data)));
+
+        let mut written = Cursor::new(vec![]);
+        RszDump::write_struct(&mut written, &value, &db).unwrap();
+        assert_eq!(written.into_inner(), data);
+        let owned = value.into_owned();
+        assert!(matches!(&owned.fields[0], RszType::String(Utf16Str(Cow::Owned(_)))));
+    }
+
+    fn dersz<'a>(db: &'a TypeDatabase, structs: Vec<RszValue<'a>>, externs: &[(u32, &str)]) -> DeRsz<'a> {
+        DeRsz {
+            roots: vec![1],
+            structs,
+            externs: externs.iter().map(|&(slot, path)| (slot, path.to_string())).collect(),
+            inline_externs: HashMap::new(),
+            object_refs: false,
+            emitted: RefCell::new(HashSet::new()),
+            msg_text: None,
+            db,
         }
-    } else {
-        None
     }
-}
 
+    fn empty(hash: u32) -> RszValue<'static> {
+        RszValue { hash, crc: 1, fields: vec![] }
+    }
+
+    #[test]
+    fn extern_root_is_a_link() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let rsz = dersz(&db, vec![empty(0), empty(0x100)], &[(1, "GameDesign/Text.user")]);
+        assert_eq!(rsz.root_values().count(), 0);
+        let json = serde_json::to_value(&rsz).unwrap();
+        assert_eq!(json, serde_json::json!({ "app.Text": { "$extern": "GameDesign/Text.user" } }));
+    }
+
+    #[test]
+    fn bad_instances_are_errors() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let e = serde_json::to_value(dersz(&db, vec![empty(0), empty(0x999)], &[])).unwrap_err();
+        assert!(e.to_string().contains("Root type 00000999 not in Rsz Dump"), "{e}");
+        let e = serde_json::to_value(dersz(&db, vec![empty(0), empty(0x100)], &[])).unwrap_err();
+        assert!(e.to_string().contains("Instance of app.Text has 0 values for 3 fields"), "{e}");
+    }
+
+    // the bytes after the extent are kept, and only dumped when they aren't padding
+    #[test]
+    fn obb_keeps_the_rest_of_the_field() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        for (rest, dumped_len) in [([0, 0, 0, 0], 2), ([1, 2, 3, 4], 3)] {
+            let mut data = (0..19).flat_map(|i| (i as f32).to_le_bytes()).collect::<Vec<_>>();
+            data.extend(rest);
+            let value = RszDump::parse_struct(&mut Cursor::new(&data[..]), TypeDescriptor { hash: 0x200, crc: 2 }, &db, false)
+                .unwrap();
+            let mut written = Cursor::new(vec![]);
+            RszDump::write_struct(&mut written, &value, &db).unwrap();
+            assert_eq!(written.into_inner(), data);
+
+            let rsz = dersz(&db, vec![empty(0), value], &[]);
+            let json = serde_json::to_value(&rsz).unwrap();
+            let obb = json["app.Box"]["_Box"].as_array().unwrap();
+            assert_eq!(obb.len(), dumped_len);
+            assert_eq!(obb[1], serde_json::json!([16.0, 17.0, 18.0]));
+        }
+    }
+
+    #[test]
+    fn guids_with_msg_text() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let msg = Msg::import_json(r#"{
+            "$version": 92, "$languages": [0, 1], "$attributes": [],
+            "entries": { "11111111-0000-0000-0000-000000000001": {
+                "name": "ITEM_NAME_001", "hash": 0, "unkn": 0, "attributes": [], "content": { "ja": "", "en": "Potion" }
+            } }
+        }"#).unwrap();
+        let mut msg_text = MsgText::new(&[]);
+        msg_text.add(msg);
+        let guid = |id: &str| RszType::Guid(Uuid::parse_str(id).unwrap().to_bytes_le());
+        let value = RszValue {
+            hash: 0x300,
+            crc: 3,
+            fields: vec![guid("11111111-0000-0000-0000-000000000001"), guid("22222222-0000-0000-0000-000000000002")],
+        };
+        let mut rsz = dersz(&db, vec![empty(0), value], &[]);
+        rsz.msg_text = Some(&msg_text);
+        let json = serde_json::to_value(&rsz).unwrap();
+        assert_eq!(json, serde_json::json!({ "app.Ref": {
+            "_Id": { "$guid": "11111111-0000-0000-0000-000000000001", "$msg": "ITEM_NAME_001", "$text": { "en": "Potion" } },
+            "_Other": "22222222-0000-0000-0000-000000000002",
+        } }));
+    }
+
+    #[test]
+    fn invalid_utf16_is_an_error() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend(0xd800u16.to_le_bytes());
+        let e = RszDump::parse_struct(&mut Cursor::new(&data[..]), TypeDescriptor { hash: 0x100, crc: 1 }, &db, false)
+            .unwrap_err();
+        assert!(format!("{e:?}").contains("Invalid utf16 string"), "{e:?}");
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+const WINDOW_SIZE: u64 = 64;
+
+// Where parsing a file went wrong, attached as context to the parse error.
+// RSZ instance errors fill in the instance and the field path on the way up.
+#[derive(Debug)]
+pub struct ParseDiagnostic {
+    pub format: &'static str,
+    pub offset: u64,
+    pub instance: Option<(u32, u32, String)>, // index, type hash, type name
+    pub field_path: String,
+    window_start: u64,
+    window: Vec<u8>,
+}
+
+impl ParseDiagnostic {
+    pub fn from_bytes(format: &'static str, data: &[u8], offset: u64) -> ParseDiagnostic {
+        let window_start = Self::window_start(offset).min(data.len() as u64);
+        let window_end = (window_start + WINDOW_SIZE).min(data.len() as u64);
+        ParseDiagnostic {
+            format,
+            offset,
+            instance: None,
+            field_path: String::new(),
+            window_start,
+            window: data[window_start as usize..window_end as usize].to_vec(),
+        }
+    }
+
+    // Uses the current position of the reader as the offset
+    pub fn from_reader<F: Read + Seek>(format: &'static str, file: &mut F) -> ParseDiagnostic {
+        let offset = file.stream_position().unwrap_or(0);
+        let window_start = Self::window_start(offset);
+        let mut window = vec![];
+        if file.seek(SeekFrom::Start(window_start)).is_ok() {
+            let _ = file.by_ref().take(WINDOW_SIZE).read_to_end(&mut window);
+        }
+        let _ = file.seek(SeekFrom::Start(offset));
+        ParseDiagnostic {
+            format,
+            offset,
+            instance: None,
+            field_path: String::new(),
+            window_start,
+            window,
+        }
+    }
+
+    fn window_start(offset: u64) -> u64 {
+        offset.saturating_sub(WINDOW_SIZE / 2) & !0xf
+    }
+
+    // Keeps the innermost diagnostic if the error already has one
+    pub fn attach(self, error: anyhow::Error) -> anyhow::Error {
+        if error.downcast_ref::<ParseDiagnostic>().is_some() {
+            error
+        } else {
+            error.context(self)
+        }
+    }
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} parse error at offset {:#x}", self.format, self.offset)?;
+        if let Some((index, hash, name)) = &self.instance {
+            write!(f, ", instance #{index} {name} ({hash:08x})")?;
+        }
+        if !self.field_path.is_empty() {
+            write!(f, ", field {}", self.field_path)?;
+        }
+        for (i, line) in self.window.chunks(16).enumerate() {
+            let line_start = self.window_start + i as u64 * 16;
+            write!(f, "\n    {line_start:08x}:")?;
+            for (j, byte) in line.iter().enumerate() {
+                if line_start + j as u64 == self.offset {
+                    write!(f, "[{byte:02x}]")?;
+                } else if line_start + j as u64 == self.offset + 1 {
+                    write!(f, "{byte:02x}")?;
+                } else {
+                    write!(f, " {byte:02x}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn window_around_offset() {
+        let data = bytes(0x100);
+        let diagnostic = ParseDiagnostic::from_bytes("TEST", &data, 0x55);
+        assert_eq!(diagnostic.offset, 0x55);
+        // starts half a window before, on a line boundary
+        assert_eq!(diagnostic.window_start, 0x30);
+        assert_eq!(diagnostic.window, data[0x30..0x70]);
+
+        let mut cursor = Cursor::new(&data[..]);
+        cursor.set_position(0x55);
+        let from_reader = ParseDiagnostic::from_reader("TEST", &mut cursor);
+        assert_eq!((from_reader.offset, from_reader.window_start), (0x55, 0x30));
+        assert_eq!(from_reader.window, diagnostic.window);
+        assert_eq!(cursor.position(), 0x55);
+    }
+
+    #[test]
+    fn window_at_buffer_bounds() {
+        let data = bytes(100);
+        let start = ParseDiagnostic::from_bytes("TEST", &data, 5);
+        assert_eq!((start.window_start, start.window.len()), (0, 64));
+
+        let end = ParseDiagnostic::from_bytes("TEST", &data, 98);
+        assert_eq!(end.window_start, 64);
+        assert_eq!(end.window, data[64..]);
+
+        let past_end = ParseDiagnostic::from_bytes("TEST", &data, 200);
+        assert_eq!(past_end.offset, 200);
+        assert!(past_end.window.is_empty());
+
+        let mut cursor = Cursor::new(&data[..]);
+        cursor.set_position(98);
+        let from_reader = ParseDiagnostic::from_reader("TEST", &mut cursor);
+        assert_eq!(from_reader.window, end.window);
+    }
+
+    #[test]
+    fn rendered_message() {
+        let data = bytes(0x40);
+        let mut diagnostic = ParseDiagnostic::from_bytes("RSZ data", &data, 0x12);
+        diagnostic.instance = Some((3, 0x1234, "app.Item".to_string()));
+        diagnostic.field_path = "._Values[1]._N".to_string();
+        let message = diagnostic.to_string();
+        let lines = message.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "RSZ data parse error at offset 0x12, instance #3 app.Item (00001234), field ._Values[1]._N");
+        assert_eq!(lines.len(), 1 + 4);
+        assert_eq!(lines[2], "    00000010: 10 11[12]13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+    }
+
+    #[test]
+    fn innermost_diagnostic_is_kept() {
+        let data = bytes(0x40);
+        let error = ParseDiagnostic::from_bytes("RSZ data", &data, 0x20).attach(anyhow::anyhow!("eof"));
+        let error = ParseDiagnostic::from_bytes("USR", &data, 0).attach(error);
+        assert_eq!(error.downcast_ref::<ParseDiagnostic>().unwrap().format, "RSZ data");
+    }
+
+    // a string cut off at the end of the data, the instance and the field come from the rsz parser
+    #[test]
+    fn rsz_error_has_instance_and_field() {
+        let db = crate::typedb::TypeDatabase::from_json(r#"{
+            "0": { "name": "", "crc": "0", "fields": [] },
+            "1000": { "name": "app.Root", "crc": "aa", "fields": [
+                { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+                { "name": "_Names", "type": "String", "original_type": "System.String[]", "align": 4, "size": 8, "array": true, "native": false }
+            ] }
+        }"#, "{}");
+        let json = r#"{ "app.Root": { "_N": 1, "_Names": ["a", "bc"] } }"#;
+        let mut cursor = Cursor::new(vec![]);
+        crate::import::RszImporter::import_user(json, &db).unwrap().write(&mut cursor).unwrap();
+        let mut bytes = cursor.into_inner();
+        bytes.truncate(bytes.len() - 2);
+
+        let user = crate::user::User::from_bytes(&bytes).unwrap();
+        let e = user.rsz.deserializev2(&db, false).unwrap_err();
+        let message = e.downcast_ref::<ParseDiagnostic>().unwrap().to_string();
+        assert!(message.starts_with("RSZ data parse error at offset"), "{message}");
+        assert!(message.contains(", instance #1 app.Root (00001000), field ._Names[1]"), "{message}");
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{BTreeSet, HashMap};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+// One difference between two dumps, paths look like query paths
+#[derive(Debug, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Change {
+    Added { path: String, value: Value },
+    Removed { path: String, value: Value },
+    Changed { path: String, old: Value, new: Value },
+}
+
+impl Change {
+    pub fn to_text(&self) -> String {
+        match self {
+            Change::Added { path, value } => format!("+ {path}: {value}"),
+            Change::Removed { path, value } => format!("- {path}: {value}"),
+            Change::Changed { path, old, new } => format!("~ {path}: {old} -> {new}"),
+        }
+    }
+}
+
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_value(old, new, "$", &mut changes);
+    changes
+}
+
+fn diff_value(old: &Value, new: &Value, path: &str, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                let path = if path == "$" { key.to_string() } else { format!("{path}.{key}") };
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => diff_value(old, new, &path, changes),
+                    (Some(old), None) => changes.push(Change::Removed { path, value: old.clone() }),
+                    (None, Some(new)) => changes.push(Change::Added { path, value: new.clone() }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => match id_field(old, new) {
+            Some(id) => diff_by_id(old, new, &id, path, changes),
+            None => diff_by_index(old, new, path, changes),
+        },
+        _ => {
+            if old != new {
+                changes.push(Change::Changed { path: path.to_string(), old: old.clone(), new: new.clone() });
+            }
+        }
+    }
+}
+
+fn diff_by_index(old: &[Value], new: &[Value], path: &str, changes: &mut Vec<Change>) {
+    for i in 0..old.len().max(new.len()) {
+        let path = format!("{path}[{i}]");
+        match (old.get(i), new.get(i)) {
+            (Some(old), Some(new)) => diff_value(old, new, &path, changes),
+            (Some(old), None) => changes.push(Change::Removed { path, value: old.clone() }),
+            (None, Some(new)) => changes.push(Change::Added { path, value: new.clone() }),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+// Entries in balance data are usually reordered or inserted in the middle between versions,
+// matching them by id keeps one change from turning into a change for every following entry
+fn diff_by_id(old: &[Value], new: &[Value], id: &str, path: &str, changes: &mut Vec<Change>) {
+    let key = |value: &Value| value[id].to_string();
+    let new_by_id = new.iter().map(|value| (key(value), value)).collect::<HashMap<_, _>>();
+    let old_by_id = old.iter().map(|value| (key(value), value)).collect::<HashMap<_, _>>();
+    let element_path = |value: &Value| format!("{path}[{id}={}]", id_text(&value[id]));
+
+    for old in old {
+        match new_by_id.get(&key(old)) {
+            Some(new) => diff_value(old, new, &element_path(old), changes),
+            None => changes.push(Change::Removed { path: element_path(old), value: old.clone() }),
+        }
+    }
+    for new in new {
+        if !old_by_id.contains_key(&key(new)) {
+            changes.push(Change::Added { path: element_path(new), value: new.clone() });
+        }
+    }
+}
+
+fn id_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+// A field that looks like an id (_Id, _SkillId, _Guid...), is a scalar in every element
+// of both arrays and is unique within each of them
+fn id_field(old: &[Value], new: &[Value]) -> Option<String> {
+    let first = old.first().or(new.first())?.as_object()?;
+    // in the original case, so _Valid or _Grid don't count
+    let is_id_name = |name: &str| ["Id", "ID", "Guid"].iter().any(|suffix| name.ends_with(suffix));
+    first.keys()
+        .filter(|name| is_id_name(name))
+        .find(|name| [old, new].iter().all(|values| unique_scalar(values, name)))
+        .cloned()
+}
+
+fn unique_scalar(values: &[Value], name: &str) -> bool {
+    let mut seen = BTreeSet::new();
+    values.iter().all(|value| match value.get(name) {
+        Some(id @ (Value::String(_) | Value::Number(_))) => seen.insert(id.to_string()),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn diff_text(old: Value, new: Value) -> Vec<String> {
+        diff(&old, &new).iter().map(Change::to_text).collect()
+    }
+
+    #[test]
+    fn matches_by_id() {
+        let old = json!({ "r": { "_Values": [{ "_SkillId": 1, "_Lv": 1 }, { "_SkillId": 2, "_Lv": 1 }] } });
+        let new = json!({ "r": { "_Values": [{ "_SkillId": 3, "_Lv": 1 }, { "_SkillId": 1, "_Lv": 1 }, { "_SkillId": 2, "_Lv": 5 }] } });
+        assert_eq!(diff_text(old, new), [
+            "~ r._Values[_SkillId=2]._Lv: 1 -> 5",
+            "+ r._Values[_SkillId=3]: {\"_Lv\":1,\"_SkillId\":3}",
+        ]);
+    }
+
+    #[test]
+    fn names_ending_in_id_are_not_ids() {
+        let old = json!({ "r": { "_Values": [{ "_Valid": "A", "_Lv": 1 }, { "_Valid": "B", "_Lv": 2 }] } });
+        let new = json!({ "r": { "_Values": [{ "_Valid": "B", "_Lv": 1 }, { "_Valid": "A", "_Lv": 2 }] } });
+        assert_eq!(diff_text(old, new), [
+            "~ r._Values[0]._Valid: \"A\" -> \"B\"",
+            "~ r._Values[1]._Valid: \"B\" -> \"A\"",
+        ]);
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::typedb::FLAGS_KEY;
+
+// Reads the enums out of the Enums_Internal.hpp header made by the game dumper, into the
+// enum database (gen/enums.json) that maps "namespace.Enum" -> value -> name.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i128),
+    Punct(&'static str),
+    // anything else, only matters inside enums where it is an error
+    Other(char),
+}
+
+// longest first so "::" isn't read as two ":"
+const PUNCTS: [&str; 17] = ["::", "<<", ">>", "{", "}", "(", ")", "=", ",", ";", ":", "-", "+", "|", "&", "~", "*"];
+
+fn parse_number(text: &str) -> Option<i128> {
+    let text = text.trim_end_matches(['u', 'U', 'l', 'L']);
+    let text = text.replace('\'', "");
+    if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+        i128::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// Tokens with the line they are on, comments and preprocessor lines are skipped
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = vec![];
+    let mut in_block_comment = false;
+    for (line_number, line) in source.lines().enumerate() {
+        let line_number = line_number + 1;
+        let mut rest = line;
+        if !in_block_comment && rest.trim_start().starts_with('#') {
+            continue;
+        }
+        loop {
+            if in_block_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        in_block_comment = false;
+                    }
+                    None => break,
+                }
+            }
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with("//") {
+                break;
+            }
+            if rest.starts_with("/*") {
+                in_block_comment = true;
+                rest = &rest[2..];
+                continue;
+            }
+
+            let c = rest.chars().next().unwrap();
+            if c.is_ascii_alphabetic() || c == '_' {
+                let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..end].to_string()), line_number));
+                rest = &rest[end..];
+            } else if c.is_ascii_digit() {
+                let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '\'')).unwrap_or(rest.len());
+                let number = parse_number(&rest[..end])
+                    .ok_or(anyhow!("Invalid number {:?} on line {line_number}", &rest[..end]))?;
+                tokens.push((Token::Number(number), line_number));
+                rest = &rest[end..];
+            } else if let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+                tokens.push((Token::Punct(punct), line_number));
+                rest = &rest[punct.len()..];
+            } else {
+                tokens.push((Token::Other(c), line_number));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+pub struct EnumDef {
+    pub name: String,
+    // in header order, aliases included
+    pub values: Vec<(String, i128)>,
+}
+
+enum Scope {
+    // how many names the namespace added, "namespace a::b" adds two
+    Names(usize),
+    Other,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(0, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned().ok_or(anyhow!("Unexpected end of header"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.peek() == Some(&Token::Punct(Self::intern(punct))) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn intern(punct: &str) -> &'static str {
+        PUNCTS.iter().find(|p| **p == punct).expect("not a punctuation token")
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if !self.eat(punct) {
+            bail!("Expected {punct:?} on line {}, got {:?}", self.line(), self.peek());
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(name) => Ok(name),
+            token => bail!("Expected a name on line {}, got {token:?}", self.line()),
+        }
+    }
+
+    // a::b::c
+    fn path(&mut self) -> Result<Vec<String>> {
+        let mut path = vec![self.ident()?];
+        while self.eat("::") {
+            path.push(self.ident()?);
+        }
+        Ok(path)
+    }
+
+    fn parse(&mut self) -> Result<Vec<EnumDef>> {
+        let mut enums = vec![];
+        let mut names: Vec<String> = vec![];
+        let mut scopes = vec![];
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Ident(keyword) if keyword == "namespace" => {
+                    self.pos += 1;
+                    let path = self.path()?;
+                    self.expect("{")?;
+                    scopes.push(Scope::Names(path.len()));
+                    names.extend(path);
+                }
+                Token::Ident(keyword) if keyword == "enum" => {
+                    self.pos += 1;
+                    if let Some(enum_def) = self.enum_def(&names)? {
+                        enums.push(enum_def);
+                    }
+                }
+                Token::Ident(keyword) if keyword == "struct" || keyword == "class" => {
+                    self.pos += 1;
+                    // enums nested in a type get its name like a namespace, declarations are skipped
+                    let name = self.ident()?;
+                    while !matches!(self.peek(), Some(Token::Punct("{" | ";")) | None) {
+                        self.pos += 1;
+                    }
+                    if self.eat("{") {
+                        scopes.push(Scope::Names(1));
+                        names.push(name);
+                    }
+                }
+                Token::Punct("{") => {
+                    self.pos += 1;
+                    scopes.push(Scope::Other);
+                }
+                Token::Punct("}") => {
+                    let line = self.line();
+                    self.pos += 1;
+                    match scopes.pop() {
+                        Some(Scope::Names(n)) => names.truncate(names.len() - n),
+                        Some(Scope::Other) => (),
+                        None => bail!("Unmatched '}}' on line {line}"),
+                    }
+                }
+                _ => self.pos += 1,
+            }
+        }
+        if !scopes.is_empty() {
+            bail!("Header ends inside {} unclosed block(s)", scopes.len());
+        }
+        Ok(enums)
+    }
+
+    // After "enum", None for forward declarations
+    fn enum_def(&mut self, names: &[String]) -> Result<Option<EnumDef>> {
+        if matches!(self.peek(), Some(Token::Ident(k)) if k == "class" || k == "struct") {
+            self.pos += 1;
+        }
+        let name = self.ident()?;
+        let mut underlying = vec![];
+        if self.eat(":") {
+            while !matches!(self.peek(), Some(Token::Punct("{" | ";")) | None) {
+                // std::int32_t is int32_t
+                if let Token::Ident(part) = self.next()? {
+                    if !self.eat("::") {
+                        underlying.push(part);
+                    }
+                }
+            }
+        }
+        if self.eat(";") {
+            return Ok(None);
+        }
+        self.expect("{")?;
+
+        let mut values: Vec<(String, i128)> = vec![];
+        let mut next = Some(0);
+        while !self.eat("}") {
+            let line = self.line();
+            let value_name = self.ident()?;
+            let value = match self.eat("=") {
+                true => self.expr(&values)?,
+                false => next.ok_or(anyhow!("Enum value {value_name} out of range on line {line}"))?,
+            };
+            let value = wrap(value, &underlying.join(" "));
+            values.push((value_name, value));
+            next = value.checked_add(1);
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        self.eat(";");
+
+        let full_name = names.iter().chain([&name]).cloned().collect::<Vec<_>>().join(".");
+        Ok(Some(EnumDef { name: full_name, values }))
+    }
+
+    // Constant expressions: literals, earlier values and | & << >> + - ~ with c precedence
+    fn expr(&mut self, values: &[(String, i128)]) -> Result<i128> {
+        self.binary(values, 0)
+    }
+
+    fn binary(&mut self, values: &[(String, i128)], level: usize) -> Result<i128> {
+        const LEVELS: [&[&str]; 4] = [&["|"], &["&"], &["<<", ">>"], &["+", "-"]];
+        if level == LEVELS.len() {
+            return self.unary(values);
+        }
+        let mut lhs = self.binary(values, level + 1)?;
+        while let Some(op) = LEVELS[level].iter().find(|op| self.peek() == Some(&Token::Punct(Self::intern(op)))) {
+            let line = self.line();
+            self.pos += 1;
+            let rhs = self.binary(values, level + 1)?;
+            let shift = u32::try_from(rhs).ok();
+            lhs = match *op {
+                "|" => Some(lhs | rhs),
+                "&" => Some(lhs & rhs),
+                "<<" => shift.and_then(|rhs| lhs.checked_shl(rhs)),
+                ">>" => shift.and_then(|rhs| lhs.checked_shr(rhs)),
+                "+" => lhs.checked_add(rhs),
+                _ => lhs.checked_sub(rhs),
+            }.ok_or(anyhow!("Enum value {lhs} {op} {rhs} out of range on line {line}"))?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, values: &[(String, i128)]) -> Result<i128> {
+        let line = self.line();
+        match self.next()? {
+            Token::Number(n) => Ok(n),
+            Token::Punct("-") => {
+                let value = self.unary(values)?;
+                value.checked_neg().ok_or(anyhow!("Enum value -({value}) out of range on line {line}"))
+            }
+            Token::Punct("~") => Ok(!self.unary(values)?),
+            Token::Punct("(") => {
+                let value = self.expr(values)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Ident(name) => {
+                // an alias, possibly qualified with the enum name
+                let mut name = name;
+                while self.eat("::") {
+                    name = self.ident()?;
+                }
+                values.iter()
+                    .find(|(value_name, _)| *value_name == name)
+                    .map(|(_, value)| *value)
+                    .ok_or(anyhow!("Unknown enum value {name:?} on line {line}"))
+            }
+            token => bail!("Unexpected {token:?} in enum value on line {line}"),
+        }
+    }
+}
+
+// Values are stored the way the field reads them, so 0xFFFFFFFF in an int32_t enum is -1
+fn wrap(value: i128, underlying: &str) -> i128 {
+    match underlying {
+        "int8_t" | "signed char" | "char" => value as i8 as i128,
+        "uint8_t" | "unsigned char" => value as u8 as i128,
+        "int16_t" | "short" => value as i16 as i128,
+        "uint16_t" | "unsigned short" => value as u16 as i128,
+        "int32_t" | "int" => value as i32 as i128,
+        "uint32_t" | "unsigned int" | "unsigned" => value as u32 as i128,
+        "int64_t" | "long long" => value as i64 as i128,
+        "uint64_t" | "unsigned long long" => value as u64 as i128,
+        _ => value,
+    }
+}
+
+pub fn parse_header(source: &str) -> Result<Vec<EnumDef>> {
+    let tokens = tokenize(source)?;
+    Parser { tokens, pos: 0 }.parse()
+}
+
+// value -> name like gen/enums.json, values with aliases get the last name like gen/enumtors.py did,
+// in the order the values first show up. The "$flags" override of the enum goes first when there is one
+struct EnumValues<'a>(&'a [(String, i128)], Option<&'a String>);
+
+impl Serialize for EnumValues<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut index = HashMap::<i128, usize>::new();
+        let mut names: Vec<(i128, &String)> = vec![];
+        for (name, value) in self.0 {
+            match index.get(value) {
+                Some(&i) => names[i].1 = name,
+                None => {
+                    index.insert(*value, names.len());
+                    names.push((*value, name));
+                }
+            }
+        }
+        let mut map = serializer.serialize_map(Some(names.len() + self.1.is_some() as usize))?;
+        if let Some(marker) = self.1 {
+            map.serialize_entry(FLAGS_KEY, marker)?;
+        }
+        for (value, name) in names {
+            map.serialize_entry(&value.to_string(), name)?;
+        }
+        map.end()
+    }
+}
+
+// enum name -> "$flags" marker
+struct EnumDatabase<'a>(&'a [EnumDef], &'a HashMap<String, String>);
+
+impl Serialize for EnumDatabase<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|e| (&e.name, EnumValues(&e.values, self.1.get(&e.name)))))
+    }
+}
+
+// The "$flags" overrides added by hand to the enum database being replaced
+fn flag_overrides(path: &Path) -> HashMap<String, String> {
+    let Ok(json_data) = std::fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    let Ok(enums) = serde_json::from_str::<HashMap<String, HashMap<String, String>>>(&json_data) else {
+        eprintln!("[WARN] Could not parse {path:?}, its $flags overrides are not kept");
+        return HashMap::new();
+    };
+    enums.into_iter()
+        .filter_map(|(name, mut values)| Some((name, values.remove(FLAGS_KEY)?)))
+        .collect()
+}
+
+// Rebuilding keeps the "$flags" overrides of the file it replaces
+pub fn save_enum_database(enums: &[EnumDef], path: &Path) -> Result<()> {
+    let overrides = flag_overrides(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let f = File::create(path).with_context(|| format!("Could not create {path:?}"))?;
+    let mut writer = BufWriter::new(f);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut writer, formatter);
+    EnumDatabase(enums, &overrides).serialize(&mut serializer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuild_keeps_flag_overrides() {
+        let enums = parse_header("namespace app { enum class Mode : int32_t { A, B, C = A | B }; enum Other { X }; }").unwrap();
+        let path = std::env::temp_dir().join("enum_header_rebuild_keeps_flag_overrides.json");
+        std::fs::write(&path, r#"{ "app.Mode": { "$flags": "false", "0": "A" }, "app.Gone": { "$flags": "true" } }"#).unwrap();
+        save_enum_database(&enums, &path).unwrap();
+        let saved: HashMap<String, HashMap<String, String>> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved["app.Mode"][FLAGS_KEY], "false");
+        // C is an alias of B
+        assert_eq!(saved["app.Mode"]["1"], "C");
+        assert!(!saved["app.Other"].contains_key(FLAGS_KEY));
+    }
+
+    fn values<'a>(enums: &'a [EnumDef], name: &str) -> Vec<(&'a str, i128)> {
+        let enum_def = enums.iter().find(|e| e.name == name).unwrap_or_else(|| panic!("no enum {name}"));
+        enum_def.values.iter().map(|(name, value)| (name.as_str(), *value)).collect()
+    }
+
+    #[test]
+    fn namespaces_and_nested_types() {
+        let enums = parse_header("
+            #pragma once
+            namespace app {
+                namespace a::b { enum E { X }; }
+                struct S : public Base { enum class Inner { Y }; };
+                class Fwd;
+                enum class Declared : int32_t;
+                enum Top { Z };
+            }
+            enum Global { W };
+        ").unwrap();
+        let names = enums.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["app.a.b.E", "app.S.Inner", "app.Top", "Global"]);
+        assert!(parse_header("namespace app { enum E { X };").is_err());
+        assert!(parse_header("enum E { X }; }").is_err());
+    }
+
+    #[test]
+    fn implicit_and_literal_values() {
+        let enums = parse_header("
+            enum E {
+                A, B, // comments are skipped
+                C = 10, D,
+                E = -3, F,
+                G = 0x1f, H = 0b101, I = 1'000u,
+                /* shifts and masks */ J = 1 << 4, K = ~0, L = (J | G) & ~1, M = J >> 2,
+            };
+        ").unwrap();
+        assert_eq!(values(&enums, "E"), [
+            ("A", 0), ("B", 1), ("C", 10), ("D", 11), ("E", -3), ("F", -2),
+            ("G", 31), ("H", 5), ("I", 1000), ("J", 16), ("K", -1), ("L", 30), ("M", 4),
+        ]);
+    }
+
+    #[test]
+    fn qualified_aliases_keep_last_name() {
+        let enums = parse_header("enum class E : uint8_t { A = 1, B = 2, AB = E::A | E::B, Alias = B, Other = app::E::A + 2 };").unwrap();
+        assert_eq!(values(&enums, "E"), [("A", 1), ("B", 2), ("AB", 3), ("Alias", 2), ("Other", 3)]);
+        // like gen/enumtors.py the last name of a value wins, in the order the values first show up
+        let json = serde_json::to_string(&EnumValues(&enums[0].values, None)).unwrap();
+        assert_eq!(json, r#"{"1":"A","2":"Alias","3":"Other"}"#);
+        assert!(parse_header("enum E { A = Missing };").unwrap_err().to_string().contains("Unknown enum value \"Missing\""));
+    }
+
+    #[test]
+    fn values_wrap_to_underlying_type() {
+        let enums = parse_header("
+            enum class S : int32_t { Max = 0xFFFFFFFF, After };
+            enum class U : unsigned int { Neg = -1 };
+            enum class B : uint8_t { Big = 0x1ff };
+            enum class L : unsigned long long { All = ~0 };
+            enum struct C : std::int16_t { Min = 0x8000 };
+            enum Plain { Big = 0xFFFFFFFF };
+        ").unwrap();
+        assert_eq!(values(&enums, "S"), [("Max", -1), ("After", 0)]);
+        assert_eq!(values(&enums, "U"), [("Neg", u32::MAX as i128)]);
+        assert_eq!(values(&enums, "B"), [("Big", 0xff)]);
+        assert_eq!(values(&enums, "L"), [("All", u64::MAX as i128)]);
+        assert_eq!(values(&enums, "C"), [("Min", i16::MIN as i128)]);
+        assert_eq!(values(&enums, "Plain"), [("Big", 0xFFFFFFFF)]);
+    }
+
+    #[test]
+    fn overflow_is_an_error_with_line() {
+        let error = |header: &str| parse_header(header).unwrap_err().to_string();
+        assert!(error("enum E {\n A = 1 << 200\n};").contains("line 2"));
+        assert!(error("enum E {\n A = 1 << -1\n};").contains("line 2"));
+        let max = "0x7fffffffffffffffffffffffffffffff";
+        assert!(error(&format!("enum E {{\n A = {max},\n B = A + 1\n}};")).contains("out of range on line 3"));
+        assert!(error(&format!("enum E {{\n A = -{max} - 2\n}};")).contains("line 2"));
+        assert!(error(&format!("enum E {{\n A = {max},\n B\n}};")).contains("Enum value B out of range on line 3"));
+        // only the values that are actually used
+        assert!(parse_header(&format!("enum E {{ A = {max} }};")).is_ok());
+    }
+}
@@ -2,7 +2,7 @@ use crate::align::*;
 use anyhow::{bail, Result};
 use nalgebra_glm::*;
 use std::convert::TryInto;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
 
 #[allow(dead_code)]
 pub trait ReadExt {
@@ -35,6 +35,35 @@ pub trait SeekExt {
     fn tell(&mut self) -> Result<u64>;
 }
 
+#[allow(dead_code)]
+pub trait WriteExt {
+    fn write_bool(&mut self, v: bool) -> Result<()>;
+    fn write_u8(&mut self, v: u8) -> Result<()>;
+    fn write_u16(&mut self, v: u16) -> Result<()>;
+    fn write_u32(&mut self, v: u32) -> Result<()>;
+    fn write_u64(&mut self, v: u64) -> Result<()>;
+    fn write_i8(&mut self, v: i8) -> Result<()>;
+    fn write_i16(&mut self, v: i16) -> Result<()>;
+    fn write_i32(&mut self, v: i32) -> Result<()>;
+    fn write_i64(&mut self, v: i64) -> Result<()>;
+    fn write_magic(&mut self, magic: &[u8; 4]) -> Result<()>;
+    fn write_u16str(&mut self, s: &str) -> Result<()>;
+    fn write_utf16str(&mut self, s: &str) -> Result<()>;
+    fn write_u8str(&mut self, s: &str) -> Result<()>;
+    fn write_f32(&mut self, v: f32) -> Result<()>;
+    fn write_f64(&mut self, v: f64) -> Result<()>;
+    fn write_f32vec2(&mut self, v: &Vec2) -> Result<()>;
+    fn write_f32vec3(&mut self, v: &Vec3) -> Result<()>;
+    fn write_f32vec4(&mut self, v: &Vec4) -> Result<()>;
+    fn write_f32m4x4(&mut self, v: &Mat4x4) -> Result<()>;
+}
+
+#[allow(dead_code)]
+pub trait SeekWriteExt {
+    fn write_align_up(&mut self, align: u64) -> Result<u64>;
+    fn write_pad_to(&mut self, from_start: u64) -> Result<u64>;
+}
+
 impl<T: Read + ?Sized> ReadExt for T {
     fn read_bool(&mut self) -> Result<bool> {
         let v = self.read_u8()?;
@@ -209,3 +238,131 @@ impl<T: Seek + Read + ?Sized> SeekExt for T {
         Ok(self.stream_position()?)
     }
 }
+
+impl<T: Write + ?Sized> WriteExt for T {
+    fn write_bool(&mut self, v: bool) -> Result<()> {
+        self.write_u8(v as u8)
+    }
+    fn write_u8(&mut self, v: u8) -> Result<()> {
+        self.write_all(&[v])?;
+        Ok(())
+    }
+    fn write_u16(&mut self, v: u16) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_u32(&mut self, v: u32) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_u64(&mut self, v: u64) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_i8(&mut self, v: i8) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_i16(&mut self, v: i16) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_i32(&mut self, v: i32) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_i64(&mut self, v: i64) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_magic(&mut self, magic: &[u8; 4]) -> Result<()> {
+        self.write_all(magic)?;
+        Ok(())
+    }
+
+    // counterpart of read_utf16str, the length is in u16 units
+    fn write_utf16str(&mut self, s: &str) -> Result<()> {
+        let u16str = s.encode_utf16().collect::<Vec<_>>();
+        self.write_u32(u16str.len().try_into()?)?;
+        for c in u16str {
+            self.write_u16(c)?;
+        }
+        Ok(())
+    }
+
+    fn write_u16str(&mut self, s: &str) -> Result<()> {
+        for c in s.encode_utf16() {
+            self.write_u16(c)?;
+        }
+        self.write_u16(0)
+    }
+
+    fn write_u8str(&mut self, s: &str) -> Result<()> {
+        if s.contains('\0') {
+            bail!("Null byte in string {:?}", s);
+        }
+        self.write_all(s.as_bytes())?;
+        self.write_u8(0)
+    }
+
+    fn write_f32(&mut self, v: f32) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+    fn write_f64(&mut self, v: f64) -> Result<()> {
+        self.write_all(&v.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write_f32vec2(&mut self, v: &Vec2) -> Result<()> {
+        for x in v.iter() {
+            self.write_f32(*x)?;
+        }
+        Ok(())
+    }
+
+    fn write_f32vec3(&mut self, v: &Vec3) -> Result<()> {
+        for x in v.iter() {
+            self.write_f32(*x)?;
+        }
+        Ok(())
+    }
+
+    fn write_f32vec4(&mut self, v: &Vec4) -> Result<()> {
+        for x in v.iter() {
+            self.write_f32(*x)?;
+        }
+        Ok(())
+    }
+
+    fn write_f32m4x4(&mut self, v: &Mat4x4) -> Result<()> {
+        // column major, same order make_mat4x4 consumes it in
+        for x in v.as_slice() {
+            self.write_f32(*x)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Seek + Write + ?Sized> SeekWriteExt for T {
+    fn write_align_up(&mut self, align: u64) -> Result<u64> {
+        let pos = self.stream_position()?;
+        self.write_pad_to(align_up(pos, align))
+    }
+
+    fn write_pad_to(&mut self, from_start: u64) -> Result<u64> {
+        let pos = self.stream_position()?;
+        if pos > from_start {
+            bail!(
+                "Cannot pad backwards. At 0x{:08X}, padding to 0x{:08X}",
+                pos,
+                from_start
+            );
+        }
+        if pos != from_start {
+            let buf = vec![0; (from_start - pos).try_into()?];
+            self.write_all(&buf)?;
+        }
+        Ok(from_start)
+    }
+}
@@ -0,0 +1,389 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::byte_reader::map_file;
+use crate::dersz::{DeRsz, RszType, RszValue};
+use crate::rsz::int_from_rsz;
+use crate::typedb::TypeDatabase;
+use crate::user::{user_file_path, User};
+
+pub use rsz_derive::FromRsz;
+
+// Typed access to deserialized data, implemented with #[derive(FromRsz)]
+//
+// #[derive(FromRsz)]
+// #[rsz("app.user_data.SkillCommonData.cData")]
+// struct SkillCommonData {
+//     #[rsz(name = "_SkillId")]
+//     skill_id: SkillId,
+//     skill_name: Uuid, // matches _SkillName
+// }
+pub trait FromRsz: Sized {
+    const SYMBOL: &'static str;
+    fn from_rsz(value: &RszValue, rsz: &DeRsz) -> Result<Self>;
+
+    fn from_root(rsz: &DeRsz) -> Result<Self> {
+        let root = rsz.root_values().next().context("No root in rsz")?;
+        Self::from_rsz(root, rsz)
+    }
+}
+
+pub trait FieldFromRsz: Sized {
+    fn field_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<Self>;
+}
+
+pub fn check_type<T: FromRsz>(value: &RszValue, crc: Option<u32>, rsz: &DeRsz) -> Result<()> {
+    let hash = *rsz.db.names
+        .get(T::SYMBOL)
+        .with_context(|| format!("Type {} not in Rsz Dump", T::SYMBOL))?;
+    if value.hash != hash {
+        bail!("Expected type {} ({hash:08x}), got {} ({:08x})", T::SYMBOL, value.name(rsz.db), value.hash)
+    }
+    let crc = match crc {
+        Some(crc) => crc,
+        None => rsz.db.structs.get(&hash).context("Hash not in Rsz Dump")?.crc,
+    };
+    if value.crc != crc {
+        bail!("CRC mismatch for {}: expected {crc:08x}, got {:08x}", T::SYMBOL, value.crc)
+    }
+    Ok(())
+}
+
+// field names are compared without underscores and case, so skill_id finds _SkillId
+pub fn find_field<'a, 'd>(value: &'a RszValue<'d>, name: &str, rsz: &DeRsz) -> Result<&'a RszType<'d>> {
+    let normalize = |name: &str| name.replace('_', "").to_lowercase();
+    let struct_info = rsz.db.structs
+        .get(&value.hash)
+        .with_context(|| format!("Type {:08x} not in Rsz Dump", value.hash))?;
+    let index = struct_info.fields.iter()
+        .position(|field| field.name == name)
+        .or_else(|| struct_info.fields.iter().position(|field| normalize(&field.name) == normalize(name)))
+        .with_context(|| format!("No field {name} in {}", struct_info.name))?;
+    value.fields.get(index).with_context(|| format!("Missing value for field {name} in {}", struct_info.name))
+}
+
+pub fn instance_from_rsz<T: FromRsz>(field: &RszType, rsz: &DeRsz) -> Result<T> {
+    match field {
+        RszType::Object(_, ptr) => {
+            let value = rsz.structs.get(*ptr as usize)
+                .with_context(|| format!("Object index {ptr} out of bounds"))?;
+            T::from_rsz(value, rsz)
+        }
+        RszType::Struct(value) => T::from_rsz(value, rsz),
+        _ => bail!("Expected object of type {}, got {field:?}", T::SYMBOL),
+    }
+}
+
+macro_rules! impl_field_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl FieldFromRsz for $t {
+                fn field_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<Self> {
+                    let v = int_from_rsz(field, rsz)?;
+                    <$t>::try_from(v).with_context(|| format!("{v} does not fit in {}", stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+impl_field_from_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl FieldFromRsz for f32 {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::F32(v) => Ok(*v),
+            _ => bail!("Expected F32, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for f64 {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::F32(v) => Ok(*v as f64),
+            RszType::F64(v) => Ok(*v),
+            _ => bail!("Expected F64, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for bool {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Bool(v) => Ok(*v),
+            _ => bail!("Expected Bool, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for String {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::String(v) => Ok(v.to_string().trim_end_matches('\0').to_string()),
+            RszType::RuntimeType(v) => Ok(v.clone()),
+            _ => bail!("Expected String, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for Uuid {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Guid(id) => Ok(Uuid::from_bytes_le(*id)),
+            _ => bail!("Expected Guid, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for Vec2 {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Vec2(v) | RszType::Float2(v) => Ok(*v),
+            _ => bail!("Expected Vec2, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for Vec3 {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Vec3(v) | RszType::Float3(v) => Ok(*v),
+            _ => bail!("Expected Vec3, got {field:?}"),
+        }
+    }
+}
+
+impl FieldFromRsz for Vec4 {
+    fn field_from_rsz(field: &RszType, _rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Vec4(v) | RszType::Float4(v) => Ok(*v),
+            _ => bail!("Expected Vec4, got {field:?}"),
+        }
+    }
+}
+
+impl<T: FieldFromRsz> FieldFromRsz for Vec<T> {
+    fn field_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Array(values) => values.iter()
+                .enumerate()
+                .map(|(i, v)| T::field_from_rsz(v, rsz).with_context(|| format!("[{i}]")))
+                .collect(),
+            _ => bail!("Expected Array, got {field:?}"),
+        }
+    }
+}
+
+// null object pointers become None
+impl<T: FieldFromRsz> FieldFromRsz for Option<T> {
+    fn field_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Object(_, 0) => Ok(None),
+            _ => Ok(Some(T::field_from_rsz(field, rsz)?)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub enum ExternUser<T> {
+    Path(String),
+    Loaded(T),
+}
+
+impl<T: FromRsz> ExternUser<T> {
+    pub fn load<'a>(&'a mut self, root_dir: &Path, db: &TypeDatabase) -> Result<&'a mut T> {
+        match self {
+            ExternUser::Path(path) => {
+                let file = map_file(user_file_path(root_dir, path))
+                    .with_context(|| format!("Could not open extern user {path}"))?;
+                let user = User::from_bytes(&file)?;
+                let rsz = user.rsz.deserializev2(db, false)?;
+                *self = ExternUser::Loaded(T::from_root(&rsz)?);
+                if let ExternUser::Loaded(t) = self {
+                    Ok(t)
+                } else {
+                    unreachable!()
+                }
+            }
+            ExternUser::Loaded(t) => Ok(t),
+        }
+    }
+
+    pub fn unwrap(&self) -> &T {
+        match self {
+            ExternUser::Path(_) => {
+                panic!("ExternUser not loaded")
+            }
+            ExternUser::Loaded(t) => t,
+        }
+    }
+}
+
+impl<T: FromRsz> FieldFromRsz for ExternUser<T> {
+    fn field_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<Self> {
+        match field {
+            RszType::Object(_, ptr) => match rsz.externs.get(ptr) {
+                Some(path) => Ok(ExternUser::Path(path.clone())),
+                None => Ok(ExternUser::Loaded(instance_from_rsz(field, rsz)?)),
+            },
+            _ => bail!("Expected extern user data {}, got {field:?}", T::SYMBOL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::RszImporter;
+    use std::io::Cursor;
+
+    // the types the structs below are read from
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Values", "type": "Object", "original_type": "app.Root.cData[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Id", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_Flags", "type": "U32", "original_type": "app.Flags_Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Other", "type": "UserData", "original_type": "app.Other", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Root.cData", "crc": "bb", "fields": [
+            { "name": "_Rare", "type": "S32", "original_type": "app.RareTypes.Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Flag", "type": "Bool", "original_type": "System.Boolean", "align": 1, "size": 1, "array": false, "native": false },
+            { "name": "_Pos", "type": "Vec3", "original_type": "via.vec3", "align": 16, "size": 16, "array": false, "native": false },
+            { "name": "_Item", "type": "Object", "original_type": "app.ItemID_Serializable", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Res", "type": "Resource", "original_type": "via.Prefab", "align": 4, "size": 8, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.ItemID_Serializable", "crc": "cc", "fields": [
+            { "name": "_Value", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "4000": { "name": "app.Root.cData[]", "crc": "dd", "fields": [] },
+        "5000": { "name": "app.Other", "crc": "ee", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    const ENUMS: &str = r#"{
+        "app.RareTypes.Fixed": { "0": "RARE_0", "5": "RARE_5", "8": "RARE_8" },
+        "app.ItemID_Fixed": { "1": "ITEM_A", "2": "ITEM_B" },
+        "app.Flags_Fixed": { "1": "FLAG_A", "2": "FLAG_B", "4": "FLAG_C" }
+    }"#;
+
+    const USER_JSON: &str = r#"{
+        "app.Root": {
+            "_Values": [
+                { "_Rare": "RARE_8", "_Flag": true, "_Pos": [1.0, 2.0, 3.0], "_Item": "ITEM_B", "_Res": "Art/Model/a.pfb" },
+                { "_Rare": "RARE_5", "_Flag": false, "_Pos": [-0.5, 0.0, 8.25], "_Item": "ITEM_A", "_Res": "" }
+            ],
+            "_Name": "root",
+            "_Id": "01234567-89ab-cdef-0123-456789abcdef",
+            "_Flags": ["FLAG_A", "FLAG_C"],
+            "_Other": { "$extern": "GameDesign/Other.user", "$type": "app.Other" }
+        }
+    }"#;
+
+    const OTHER_JSON: &str = r#"{ "app.Other": { "_N": 7 } }"#;
+
+    fn test_db() -> TypeDatabase {
+        TypeDatabase::from_json(RSZ_DUMP, ENUMS)
+    }
+
+    #[derive(FromRsz, Debug, PartialEq)]
+    enum Rare {
+        Rare0 = 0,
+        Rare5 = 5,
+        Rare8 = 8,
+    }
+
+    // enum wrapped in an object (_Serializable)
+    #[derive(FromRsz, Debug, PartialEq)]
+    enum Item {
+        A = 1,
+        B = 2,
+    }
+
+    #[derive(FromRsz, Debug)]
+    #[rsz("app.Other")]
+    struct Other {
+        n: i32,
+    }
+
+    #[derive(FromRsz, Debug)]
+    #[rsz("app.Root.cData")]
+    struct Data {
+        rare: Rare,
+        flag: bool,
+        pos: Vec3,
+        item: Item,
+        #[rsz(name = "_Res")]
+        resource: String,
+    }
+
+    #[derive(FromRsz, Debug)]
+    #[rsz("app.Root", crc = 0xaa)]
+    struct Root {
+        values: Vec<Data>,
+        name: String,
+        id: Uuid,
+        #[rsz(name = "_Flags")]
+        flag_bits: u32,
+        other: ExternUser<Other>,
+    }
+
+    #[derive(FromRsz, Debug)]
+    #[rsz("app.Root", crc = 0x1234)]
+    struct OldRoot {}
+
+    fn import(json: &str, db: &TypeDatabase) -> Vec<u8> {
+        let mut cursor = Cursor::new(vec![]);
+        RszImporter::import_user(json, db).unwrap().write(&mut cursor).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn from_rsz() {
+        let db = test_db();
+        let bytes = import(USER_JSON, &db);
+        let user = User::from_bytes(&bytes).unwrap();
+        let rsz = user.rsz.deserializev2(&db, false).unwrap();
+
+        let mut root = Root::from_root(&rsz).unwrap();
+        assert_eq!(root.name, "root");
+        assert_eq!(root.id, Uuid::parse_str("01234567-89ab-cdef-0123-456789abcdef").unwrap());
+        assert_eq!(root.flag_bits, 5);
+        assert_eq!(root.values.len(), 2);
+        assert_eq!((&root.values[0].rare, &root.values[0].item), (&Rare::Rare8, &Item::B));
+        assert_eq!((&root.values[1].rare, &root.values[1].item), (&Rare::Rare5, &Item::A));
+        assert!(root.values[0].flag && !root.values[1].flag);
+        assert_eq!(root.values[1].pos, Vec3::new(-0.5, 0.0, 8.25));
+        assert_eq!(root.values[0].resource, "Art/Model/a.pfb");
+        assert!(matches!(&root.other, ExternUser::Path(path) if path == "GameDesign/Other.user"));
+
+        let root_dir = std::env::temp_dir().join(format!("mhwsgen_from_rsz_{}", std::process::id()));
+        let other_path = crate::user::user_file_path(&root_dir, "GameDesign/Other.user");
+        std::fs::create_dir_all(other_path.parent().unwrap()).unwrap();
+        std::fs::write(&other_path, import(OTHER_JSON, &db)).unwrap();
+        let other = root.other.load(&root_dir, &db).map(|other| other.n);
+        std::fs::remove_dir_all(&root_dir).unwrap();
+        assert_eq!(other.unwrap(), 7);
+        assert_eq!(root.other.unwrap().n, 7);
+    }
+
+    #[test]
+    fn from_rsz_checks_type() {
+        let db = test_db();
+        let bytes = import(USER_JSON, &db);
+        let user = User::from_bytes(&bytes).unwrap();
+        let rsz = user.rsz.deserializev2(&db, false).unwrap();
+
+        let e = OldRoot::from_root(&rsz).unwrap_err().to_string();
+        assert!(e.contains("CRC mismatch for app.Root"), "{e}");
+        let e = Other::from_root(&rsz).unwrap_err().to_string();
+        assert!(e.contains("Expected type app.Other"), "{e}");
+    }
+}
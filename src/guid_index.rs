@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::dersz::{DeRsz, RszType, RszValue};
+use crate::msg::Msg;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    // path of the field in the user file dump, like a query path
+    Field(String),
+    // name of the msg entry with that guid
+    Msg(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GuidRef {
+    pub file: String,
+    #[serde(flatten)]
+    pub location: Location,
+}
+
+// Where each guid shows up, saved as json so it can be added to and looked up later.
+// Indexing a file again replaces what was indexed from it before.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GuidIndex {
+    guids: BTreeMap<String, Vec<GuidRef>>,
+    #[serde(skip)]
+    indexed: HashSet<String>,
+}
+
+impl GuidIndex {
+    pub fn load(path: &Path) -> Result<GuidIndex> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Error parsing guid index {path:?}")),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(GuidIndex::default()),
+            Err(e) => Err(e).with_context(|| format!("Could not open guid index {path:?}")),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let f = File::create(path).with_context(|| format!("Could not create guid index {path:?}"))?;
+        serde_json::to_writer_pretty(BufWriter::new(f), self)?;
+        Ok(())
+    }
+
+    pub fn lookup(&self, guid: &str) -> &[GuidRef] {
+        self.guids.get(&guid.to_lowercase()).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn guid_count(&self) -> usize {
+        self.guids.len()
+    }
+
+    // drops what an earlier run indexed from the file, the first time the file is seen in this run
+    fn start_file(&mut self, file: &str) {
+        if self.indexed.insert(file.to_string()) {
+            self.guids.retain(|_, refs| {
+                refs.retain(|r| r.file != file);
+                !refs.is_empty()
+            });
+        }
+    }
+
+    fn add(&mut self, guid: Uuid, file: &str, location: Location) {
+        // default values are everywhere and don't point at anything
+        if guid.is_nil() {
+            return;
+        }
+        let refs = self.guids.entry(guid.to_string()).or_default();
+        let guid_ref = GuidRef { file: file.to_string(), location };
+        if !refs.contains(&guid_ref) {
+            refs.push(guid_ref);
+        }
+    }
+
+    pub fn add_msg(&mut self, file: &str, msg: &Msg) -> usize {
+        self.start_file(file);
+        let mut count = 0;
+        for (guid, name) in msg.entry_names() {
+            self.add(guid, file, Location::Msg(name.to_string()));
+            count += 1;
+        }
+        count
+    }
+
+    pub fn add_user(&mut self, file: &str, rsz: &DeRsz) -> usize {
+        self.start_file(file);
+        let mut walker = GuidWalker { rsz, visited: HashSet::new(), found: vec![] };
+        for root in rsz.root_values() {
+            walker.walk_struct(root, root.name(rsz.db).to_string());
+        }
+        let count = walker.found.len();
+        for (guid, path) in walker.found {
+            self.add(guid, file, Location::Field(path));
+        }
+        count
+    }
+}
+
+struct GuidWalker<'a, 'b> {
+    rsz: &'b DeRsz<'a>,
+    visited: HashSet<u32>,
+    found: Vec<(Uuid, String)>,
+}
+
+impl<'a, 'b> GuidWalker<'a, 'b> {
+    fn walk_struct(&mut self, value: &RszValue, path: String) {
+        let Some(info) = self.rsz.db.structs.get(&value.hash) else {
+            return;
+        };
+        for (field, field_value) in info.fields.iter().zip(&value.fields) {
+            self.walk(field_value, format!("{path}.{}", field.name));
+        }
+    }
+
+    fn walk(&mut self, value: &RszType, path: String) {
+        match value {
+            RszType::Guid(bytes) => self.found.push((Uuid::from_bytes_le(*bytes), path)),
+            RszType::Array(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    self.walk(value, format!("{path}[{i}]"));
+                }
+            }
+            RszType::Struct(value) => self.walk_struct(value, path),
+            // objects shared between fields are only walked the first time
+            RszType::Object(_, ptr) if !self.rsz.externs.contains_key(ptr) && self.visited.insert(*ptr) => {
+                if let Some(value) = self.rsz.structs.get(*ptr as usize) {
+                    self.walk_struct(value, path);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::import::RszImporter;
+    use crate::typedb::TypeDatabase;
+    use crate::user::User;
+
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Id", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_Items", "type": "Object", "original_type": "app.Item[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Pos", "type": "Struct", "original_type": "app.Pos", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_First", "type": "Object", "original_type": "app.Item", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Again", "type": "Object", "original_type": "app.Item", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Item", "crc": "bb", "fields": [
+            { "name": "_Guid", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.Item[]", "crc": "cc", "fields": [] },
+        "4000": { "name": "app.Pos", "crc": "dd", "fields": [
+            { "name": "_Guid", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false }
+        ] }
+    }"#;
+
+    const USER_JSON: &str = r#"{
+        "app.Root": {
+            "_Id": "11111111-0000-0000-0000-000000000001",
+            "_Items": [
+                { "_Guid": "22222222-0000-0000-0000-000000000002" },
+                { "_Guid": "00000000-0000-0000-0000-000000000000" },
+                { "_Guid": "11111111-0000-0000-0000-000000000001" }
+            ],
+            "_Pos": { "_Guid": "33333333-0000-0000-0000-00000000000a" },
+            "_First": { "$id": 1, "_Guid": "44444444-0000-0000-0000-000000000004" },
+            "_Again": { "$ref": 1 }
+        }
+    }"#;
+
+    fn msg(entries: &[(&str, &str)]) -> Msg {
+        let mut json = serde_json::json!({ "$version": 0x5c, "$languages": [1], "$attributes": [], "entries": {} });
+        for (guid, name) in entries {
+            json["entries"][guid] = serde_json::json!({ "name": name, "hash": 0, "unkn": 0, "attributes": [], "content": { "en": "" } });
+        }
+        Msg::import_json(&json.to_string()).unwrap()
+    }
+
+    fn index_user(index: &mut GuidIndex, file: &str) {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let mut cursor = Cursor::new(vec![]);
+        RszImporter::import_user(USER_JSON, &db).unwrap().write(&mut cursor).unwrap();
+        let bytes = cursor.into_inner();
+        let user = User::from_bytes(&bytes).unwrap();
+        let rsz = user.rsz.deserializev2(&db, false).unwrap();
+        index.add_user(file, &rsz);
+    }
+
+    fn field(file: &str, path: &str) -> GuidRef {
+        GuidRef { file: file.to_string(), location: Location::Field(path.to_string()) }
+    }
+
+    #[test]
+    fn user_field_paths() {
+        let mut index = GuidIndex::default();
+        index_user(&mut index, "a.user.3");
+        assert_eq!(index.lookup("11111111-0000-0000-0000-000000000001"), [
+            field("a.user.3", "app.Root._Id"),
+            field("a.user.3", "app.Root._Items[2]._Guid"),
+        ]);
+        assert_eq!(index.lookup("22222222-0000-0000-0000-000000000002"), [field("a.user.3", "app.Root._Items[0]._Guid")]);
+        // lookups ignore case
+        assert_eq!(index.lookup("33333333-0000-0000-0000-00000000000A"), [field("a.user.3", "app.Root._Pos._Guid")]);
+        // the shared object is found under the first field pointing at it
+        assert_eq!(index.lookup("44444444-0000-0000-0000-000000000004"), [field("a.user.3", "app.Root._First._Guid")]);
+        assert!(index.lookup(&Uuid::nil().to_string()).is_empty());
+        assert_eq!(index.guid_count(), 4);
+
+        // the same file again in the same run doesn't add the refs twice
+        index_user(&mut index, "a.user.3");
+        assert_eq!(index.lookup("22222222-0000-0000-0000-000000000002").len(), 1);
+    }
+
+    #[test]
+    fn reindexing_replaces_old_refs() {
+        let path = std::env::temp_dir().join(format!("mhwsgen_guid_index_{}.json", std::process::id()));
+        let mut index = GuidIndex::default();
+        index_user(&mut index, "a.user.3");
+        let first = msg(&[("22222222-0000-0000-0000-000000000002", "ITEM_A"), ("55555555-0000-0000-0000-000000000005", "ITEM_B")]);
+        assert_eq!(index.add_msg("a.msg.23", &first), 2);
+        index.save(&path).unwrap();
+
+        let mut loaded = GuidIndex::load(&path).unwrap();
+        let second = msg(&[("66666666-0000-0000-0000-000000000006", "ITEM_B")]);
+        loaded.add_msg("a.msg.23", &second);
+        let missing = GuidIndex::load(&path.with_extension("missing")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let msg_ref = |name: &str| GuidRef { file: "a.msg.23".to_string(), location: Location::Msg(name.to_string()) };
+        assert_eq!(index.lookup("22222222-0000-0000-0000-000000000002"), [
+            field("a.user.3", "app.Root._Items[0]._Guid"),
+            msg_ref("ITEM_A"),
+        ]);
+        // the refs of the other file survive, the ones of the msg are the new ones
+        assert_eq!(loaded.lookup("22222222-0000-0000-0000-000000000002"), [field("a.user.3", "app.Root._Items[0]._Guid")]);
+        assert!(loaded.lookup("55555555-0000-0000-0000-000000000005").is_empty());
+        assert_eq!(loaded.lookup("66666666-0000-0000-0000-000000000006"), [msg_ref("ITEM_B")]);
+        assert_eq!(loaded.guid_count(), 5);
+        assert_eq!(missing.guid_count(), 0);
+    }
+}
@@ -0,0 +1,565 @@
+use std::{borrow::Cow, cell::RefCell, collections::{HashMap, HashSet}, fmt};
+
+use crate::dersz::*;
+use crate::minifloat::{self, F16_FORMAT, F8_FORMAT};
+use crate::rsz::{Extern, Rsz, TypeDescriptor};
+use crate::typedb::TypeDatabase;
+use crate::user::{User, UserChild};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nalgebra_glm::{Mat4x4, Vec2, Vec3, Vec4};
+use serde::de::{DeserializeOwned, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use uuid::Uuid;
+
+// serde_json::Map sorts its keys, the roots (and msg entries) need to stay in file order
+pub(crate) struct OrderedMap(pub Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for OrderedMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor;
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = OrderedMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of names to values")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry::<String, Value>()? {
+                    entries.push(entry);
+                }
+                Ok(OrderedMap(entries))
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor)
+    }
+}
+
+fn from_json<T: DeserializeOwned>(value: &Value, path: &str) -> Result<T> {
+    serde_json::from_value(value.clone())
+        .with_context(|| format!("{path}: expected {}, got {value}", std::any::type_name::<T>()))
+}
+
+fn is_enum_type(original_type: &str) -> bool {
+    original_type.ends_with("Serializable") || original_type.ends_with("Fixed")
+        || original_type.ends_with("Serializable[]") || original_type.ends_with("Fixed[]")
+}
+
+// Rebuilds the instance list of an rsz block from the json made by the DeRsz serializer.
+// Objects get laid out depth first with children before their parents, like the game does.
+pub struct RszImporter<'a> {
+    db: &'a TypeDatabase,
+    structs: Vec<RszValue<'static>>,
+    resource_names: Vec<String>,
+    extern_slots: HashMap<u32, Extern>,
+    children: Vec<UserChild>,
+    // "$id" of objects dumped with object refs to their new index
+    ids: HashMap<u32, u32>,
+}
+
+impl<'a> RszImporter<'a> {
+    pub fn import_user(json: &str, db: &'a TypeDatabase) -> Result<User<'static>> {
+        let OrderedMap(json_roots) = serde_json::from_str(json)?;
+
+        let mut importer = RszImporter {
+            db,
+            structs: vec![],
+            resource_names: vec![],
+            extern_slots: HashMap::new(),
+            children: vec![],
+            ids: HashMap::new(),
+        };
+        let null_info = importer.struct_info(0, "$")?;
+        importer.structs.push(RszValue {
+            crc: null_info.crc,
+            hash: 0,
+            fields: vec![],
+        });
+
+        let mut roots = Vec::new();
+        for (name, value) in &json_roots {
+            let path = format!("$.{name}");
+            let root = match value {
+                Value::Object(map) if map.contains_key("$extern") => importer.import_extern(map, name, &path)?,
+                _ => {
+                    let hash = importer.struct_hash(name, &path)?;
+                    importer.import_instance(hash, value, &path)?
+                }
+            };
+            roots.push(root);
+        }
+
+        let type_descriptors = importer.structs.iter()
+            .map(|s| TypeDescriptor { hash: s.hash, crc: s.crc })
+            .collect();
+        let externs = importer.extern_slots.iter()
+            .map(|(&slot, Extern { path, .. })| (slot, path.clone()))
+            .collect();
+        let mut rsz = Rsz {
+            roots: roots.clone(),
+            extern_slots: importer.extern_slots,
+            type_descriptors,
+            data: Cow::default(),
+        };
+        let dersz = DeRsz {
+            roots,
+            structs: importer.structs,
+            externs,
+            inline_externs: HashMap::new(),
+            object_refs: false,
+            emitted: RefCell::new(HashSet::new()),
+            msg_text: None,
+            db,
+        };
+        rsz.data = Cow::Owned(rsz.serializev2(&dersz)?);
+
+        Ok(User {
+            resource_names: importer.resource_names,
+            children: importer.children,
+            rsz,
+        })
+    }
+
+    fn struct_hash(&self, name: &str, path: &str) -> Result<u32> {
+        self.db.names
+            .get(name.trim_end_matches("[]"))
+            .copied()
+            .with_context(|| format!("{path}: type {name} not in Rsz Dump"))
+    }
+
+    fn struct_info(&self, hash: u32, path: &str) -> Result<&'a RszStruct<RszField>> {
+        self.db.structs
+            .get(&hash)
+            .with_context(|| format!("{path}: type hash {hash:08x} not in Rsz Dump"))
+    }
+
+    fn import_instance(&mut self, hash: u32, value: &Value, path: &str) -> Result<u32> {
+        let instance = self.import_struct(hash, value, path)?;
+        self.structs.push(instance);
+        let index = u32::try_from(self.structs.len() - 1)?;
+        if let Some(id) = value.get("$id") {
+            let id = from_json(id, &format!("{path}.$id"))?;
+            if self.ids.insert(id, index).is_some() {
+                bail!("{path}.$id: {id} is used more than once")
+            }
+        }
+        Ok(index)
+    }
+
+    // {"$extern": "path/to/file.user", "$type": "app.user_data.Foo"}, with the root of that file in "$value"
+    // when it was inlined. The value belongs to the other file (so do the $ids in it), only the link is imported.
+    // Without "$type" the link is of the field (or root) type
+    fn import_extern(&mut self, map: &serde_json::Map<String, Value>, default_type: &str, path: &str) -> Result<u32> {
+        if let Some(key) = map.keys().find(|key| !["$extern", "$type", "$value"].contains(&key.as_str())) {
+            bail!("{path}.{key}: unknown key in extern link")
+        }
+        let extern_path: String = from_json(&map["$extern"], &format!("{path}.$extern"))?;
+        let type_name = match map.get("$type") {
+            Some(type_name) => from_json(type_name, &format!("{path}.$type"))?,
+            None => default_type.to_string(),
+        };
+        let hash = self.struct_hash(&type_name, path)?;
+        let info = self.struct_info(hash, path)?;
+        self.structs.push(RszValue {
+            crc: info.crc,
+            hash,
+            fields: vec![],
+        });
+        let slot = u32::try_from(self.structs.len() - 1)?;
+        if !self.children.iter().any(|child| child.name == extern_path) {
+            self.children.push(UserChild { hash, name: extern_path.clone() });
+        }
+        self.extern_slots.insert(slot, Extern { hash, path: extern_path });
+        Ok(slot)
+    }
+
+    fn import_struct(&mut self, hash: u32, value: &Value, path: &str) -> Result<RszValue<'static>> {
+        let info = self.struct_info(hash, path)?;
+        let Value::Object(map) = value else {
+            bail!("{path}: expected object for {}, got {value}", info.name)
+        };
+        let is_field = |key: &str| key == "$id" || info.fields.iter().any(|field| field.name == key);
+        if let Some(key) = map.keys().find(|key| !is_field(key)) {
+            bail!("{path}.{key}: unknown field for {}", info.name)
+        }
+
+        let mut fields = Vec::new();
+        for field in &info.fields {
+            let path = format!("{path}.{}", field.name);
+            let value = map.get(&field.name)
+                .with_context(|| format!("{path}: missing field of {}", info.name))?;
+            if field.array {
+                let Value::Array(values) = value else {
+                    bail!("{path}: expected array, got {value}")
+                };
+                let mut vals = Vec::new();
+                for (i, value) in values.iter().enumerate() {
+                    vals.push(self.import_field(value, field, &format!("{path}[{i}]"))?);
+                }
+                fields.push(RszType::Array(vals));
+            } else {
+                fields.push(self.import_field(value, field, &path)?);
+            }
+        }
+
+        Ok(RszValue {
+            crc: info.crc,
+            hash,
+            fields,
+        })
+    }
+
+    fn import_field(&mut self, value: &Value, field: &RszField, path: &str) -> Result<RszType<'static>> {
+        if is_enum_type(&field.original_type) {
+            let underlying = self.import_enum(value, field, path)?;
+            Ok(RszType::Enum(Box::new(underlying), field.original_type.clone()))
+        } else {
+            self.import_value(value, field, path)
+        }
+    }
+
+    fn import_enum(&mut self, value: &Value, field: &RszField, path: &str) -> Result<RszType<'static>> {
+        let number = match value {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => match s.split_once(" // ") {
+                // value that was not in the enum map when dumping
+                Some((n, _)) => n.to_string(),
+                None => self.db.get_enum_value(&field.original_type, s)
+                    .with_context(|| format!("{path}: unknown enum name {s:?} for {}", field.original_type))?,
+            },
+            Value::Array(flags) if self.db.is_flag_enum(&field.original_type) => {
+                let flags = flags.iter()
+                    .map(|flag| flag.as_str().with_context(|| format!("{path}: expected flag name, got {flag}")))
+                    .collect::<Result<Vec<_>>>()?;
+                // enum objects hold the value in their only field
+                let size = match field.r#type.as_str() {
+                    "Object" | "UserData" => {
+                        let hash = self.struct_hash(&field.original_type, path)?;
+                        self.struct_info(hash, path)?.fields.first().map_or(8, |value_field| value_field.size)
+                    }
+                    _ => field.size,
+                };
+                self.db.get_flag_value(&field.original_type, &flags, size).with_context(|| path.to_string())?.to_string()
+            },
+            // enum objects that wrap another object get dumped as that object,
+            // so it goes in the value field of a new wrapper instance
+            Value::Object(_) if matches!(field.r#type.as_str(), "Object" | "UserData") => {
+                let hash = self.struct_hash(&field.original_type, path)?;
+                let info = self.struct_info(hash, path)?;
+                let [value_field] = info.fields.as_slice() else {
+                    bail!("{path}: enum type {} does not have a single value field", info.name)
+                };
+                let inner = self.import_value(value, value_field, path)?;
+                return self.push_enum_object(info, hash, inner);
+            }
+            Value::Object(_) => return self.import_value(value, field, path),
+            _ => bail!("{path}: expected enum name or number for {}, got {value}", field.original_type),
+        };
+
+        match field.r#type.as_str() {
+            "Object" | "UserData" => {
+                let hash = self.struct_hash(&field.original_type, path)?;
+                let info = self.struct_info(hash, path)?;
+                let [value_field] = info.fields.as_slice() else {
+                    bail!("{path}: enum type {} does not have a single value field", info.name)
+                };
+                let value = parse_int(&number, &value_field.r#type, path)?;
+                self.push_enum_object(info, hash, value)
+            }
+            r#type => parse_int(&number, r#type, path),
+        }
+    }
+
+    fn push_enum_object(&mut self, info: &RszStruct<RszField>, hash: u32, value: RszType<'static>) -> Result<RszType<'static>> {
+        self.structs.push(RszValue { crc: info.crc, hash, fields: vec![value] });
+        Ok(RszType::Object(hash, u32::try_from(self.structs.len() - 1)?))
+    }
+
+    fn import_value(&mut self, value: &Value, field: &RszField, path: &str) -> Result<RszType<'static>> {
+        let r#type = match field.r#type.as_str() {
+            "S8" => RszType::Int8(from_json(value, path)?),
+            "S16" => RszType::Int16(from_json(value, path)?),
+            "S32" => RszType::Int32(from_json(value, path)?),
+            "S64" => RszType::Int64(from_json(value, path)?),
+            "U8" => RszType::UInt8(from_json(value, path)?),
+            "U16" => RszType::UInt16(from_json(value, path)?),
+            "U32" => RszType::UInt32(from_json(value, path)?),
+            "U64" => RszType::UInt64(from_json(value, path)?),
+            "F8" => RszType::F8(u8::try_from(import_minifloat(F8_FORMAT, value, path)?)?),
+            "F16" => RszType::F16(u16::try_from(import_minifloat(F16_FORMAT, value, path)?)?),
+            "F32" => RszType::F32(from_json(value, path)?),
+            "F64" => RszType::F64(from_json(value, path)?),
+
+            "Uint2" => RszType::UInt2(from_json(value, path)?),
+            "Uint3" => RszType::UInt3(from_json(value, path)?),
+            "Uint4" => RszType::UInt4(from_json(value, path)?),
+            "Color" => RszType::Color(from_json(value, path)?),
+            "Int2" => RszType::Int2(from_json(value, path)?),
+            "Int3" => RszType::Int3(from_json(value, path)?),
+            "Int4" => RszType::Int4(from_json(value, path)?),
+            "Vec2" => RszType::Vec2(from_json::<Vec2>(value, path)?),
+            "Vec3" => RszType::Vec3(from_json::<Vec3>(value, path)?),
+            "Vec4" => RszType::Vec4(from_json::<Vec4>(value, path)?),
+            "Quaternion" => RszType::Quaternion(from_json(value, path)?),
+            "Float2" => RszType::Float2(from_json::<Vec2>(value, path)?),
+            "Float3" => RszType::Float3(from_json::<Vec3>(value, path)?),
+            "Float4" => RszType::Float4(from_json::<Vec4>(value, path)?),
+            "Mat4" => RszType::Mat4x4(from_json::<Mat4x4>(value, path)?),
+
+            "Range" => RszType::Range(from_json(value, path)?),
+            "RangeI" => RszType::RangeI(from_json(value, path)?),
+
+            "Data" => RszType::Data(Cow::Owned(from_json(value, path)?)),
+            "AABB" => RszType::AABB(from_json(value, path)?),
+            "OBB" => match value {
+                Value::Array(values) if values.len() == 3 => {
+                    let (coord, extent, rest) = from_json::<(Mat4x4, Vec3, Vec<u8>)>(value, path)?;
+                    RszType::OBB((coord, extent), Cow::Owned(rest))
+                },
+                // without the rest of the field it was all zeros
+                _ => {
+                    let rest = vec![0; field.size.saturating_sub(76) as usize];
+                    RszType::OBB(from_json::<(Mat4x4, Vec3)>(value, path)?, Cow::Owned(rest))
+                },
+            },
+            "Capsule" => RszType::Capsule(from_json(value, path)?),
+            "Rect" => RszType::Rect(from_json(value, path)?),
+            "Guid" => {
+                // {"$guid": id, "$msg": name, "$text": text} when dumped with msg text
+                let id: String = match value {
+                    Value::Object(map) if map.contains_key("$guid") => from_json(&map["$guid"], &format!("{path}.$guid"))?,
+                    _ => from_json(value, path)?,
+                };
+                let id = Uuid::parse_str(&id).with_context(|| format!("{path}: invalid guid {id:?}"))?;
+                RszType::Guid(id.to_bytes_le())
+            },
+            "Bool" => RszType::Bool(from_json(value, path)?),
+            "String" => RszType::String(Utf16Str::from(from_json::<String>(value, path)?.as_str())),
+            "Resource" => {
+                let resource: String = from_json(value, path)?;
+                let name = resource.trim_end_matches('\0');
+                if !name.is_empty() && !self.resource_names.iter().any(|n| n == name) {
+                    self.resource_names.push(name.to_string());
+                }
+                RszType::String(Utf16Str::from(resource.as_str()))
+            },
+            "Struct" => {
+                let hash = self.struct_hash(&field.original_type, path)?;
+                RszType::Struct(self.import_struct(hash, value, path)?)
+            },
+            "RuntimeType" => RszType::RuntimeType(from_json(value, path)?),
+            "Object" | "UserData" => {
+                let hash = self.struct_hash(&field.original_type, path)?;
+                let info = self.struct_info(hash, path)?;
+                let index = match value {
+                    // null pointers get dumped as the empty instance 0
+                    Value::Object(map) if map.is_empty() && !info.fields.is_empty() => 0,
+                    Value::Object(map) if map.contains_key("$extern") => self.import_extern(map, &field.original_type, path)?,
+                    Value::Object(map) if map.contains_key("$ref") => {
+                        let id: u32 = from_json(&map["$ref"], &format!("{path}.$ref"))?;
+                        *self.ids.get(&id)
+                            .with_context(|| format!("{path}.$ref: {id} does not match an earlier $id"))?
+                    },
+                    _ => self.import_instance(hash, value, path)?,
+                };
+                RszType::Object(hash, index)
+            },
+            _ => match value {
+                // written by lenient dumping
+                Value::Object(map) if map.contains_key("$unsupported") => {
+                    let bytes: Vec<u8> = from_json(&map["$data"], &format!("{path}.$data"))?;
+                    if bytes.len() != field.size as usize {
+                        bail!("{path}.$data: expected {} bytes, got {}", field.size, bytes.len())
+                    }
+                    RszType::Unknown(field.r#type.clone(), Cow::Owned(bytes))
+                },
+                _ => return Err(anyhow!("{path}: type {:?} cannot be imported", field.r#type)),
+            }
+        };
+        Ok(r#type)
+    }
+}
+
+// floats, or the raw bits as a hex string for inf/nan
+fn import_minifloat(format: (u32, u32), value: &Value, path: &str) -> Result<u32> {
+    match value {
+        Value::String(s) => s.strip_prefix("0x")
+            .and_then(|bits| u32::from_str_radix(bits, 16).ok())
+            .with_context(|| format!("{path}: expected float or hex bits, got {value}")),
+        _ => Ok(minifloat::encode(format, from_json(value, path)?)),
+    }
+}
+
+fn parse_int(number: &str, r#type: &str, path: &str) -> Result<RszType<'static>> {
+    let context = || format!("{path}: {number:?} is not a valid {type}");
+    let r#type = match r#type {
+        // flags of signed enums come back as unsigned bits
+        "S8" => RszType::Int8(number.parse().or_else(|_| number.parse::<u8>().map(|v| v as i8)).with_context(context)?),
+        "S16" => RszType::Int16(number.parse().or_else(|_| number.parse::<u16>().map(|v| v as i16)).with_context(context)?),
+        "S32" => RszType::Int32(number.parse().or_else(|_| number.parse::<u32>().map(|v| v as i32)).with_context(context)?),
+        "S64" => RszType::Int64(number.parse().or_else(|_| number.parse::<u64>().map(|v| v as i64)).with_context(context)?),
+        "U8" => RszType::UInt8(number.parse().with_context(context)?),
+        "U16" => RszType::UInt16(number.parse().with_context(context)?),
+        "U32" => RszType::UInt32(number.parse().with_context(context)?),
+        "U64" => RszType::UInt64(number.parse().with_context(context)?),
+        _ => bail!("{path}: unexpected enum underlying type {type}"),
+    };
+    Ok(r#type)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use std::rc::Rc;
+
+    // A few made up types covering the field kinds the importer handles
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Values", "type": "Object", "original_type": "app.Root.cData[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Id", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_Scale", "type": "F16", "original_type": "System.Half", "align": 2, "size": 2, "array": false, "native": false },
+            { "name": "_Pos", "type": "Vec3", "original_type": "via.vec3", "align": 16, "size": 16, "array": false, "native": false },
+            { "name": "_Flags", "type": "U32", "original_type": "app.Flags_Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Other", "type": "UserData", "original_type": "app.Other", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Root.cData", "crc": "bb", "fields": [
+            { "name": "_Rare", "type": "S32", "original_type": "app.RareTypes.Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Flag", "type": "Bool", "original_type": "System.Boolean", "align": 1, "size": 1, "array": false, "native": false },
+            { "name": "_Pos", "type": "Vec3", "original_type": "via.vec3", "align": 16, "size": 16, "array": false, "native": false },
+            { "name": "_Item", "type": "Object", "original_type": "app.ItemID_Serializable", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Res", "type": "Resource", "original_type": "via.Prefab", "align": 4, "size": 8, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.ItemID_Serializable", "crc": "cc", "fields": [
+            { "name": "_Value", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "4000": { "name": "app.Root.cData[]", "crc": "dd", "fields": [] },
+        "5000": { "name": "app.Other", "crc": "ee", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Data", "type": "Object", "original_type": "app.Root.cData", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "6000": { "name": "app.Slot", "crc": "ff", "fields": [
+            { "name": "_Kind", "type": "Object", "original_type": "app.KindID_Serializable", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "7000": { "name": "app.KindID_Serializable", "crc": "11", "fields": [
+            { "name": "_Value", "type": "Object", "original_type": "app.KindData", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "8000": { "name": "app.KindData", "crc": "22", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    const ENUMS: &str = r#"{
+        "app.RareTypes.Fixed": { "0": "RARE_0", "5": "RARE_5", "8": "RARE_8" },
+        "app.ItemID_Fixed": { "1": "ITEM_A", "2": "ITEM_B" },
+        "app.Flags_Fixed": { "1": "FLAG_A", "2": "FLAG_B", "4": "FLAG_C", "-1": "ALL" }
+    }"#;
+
+    // dump of a user file of those types
+    const USER_JSON: &str = r#"{
+        "app.Root": {
+            "_Values": [
+                { "_Rare": "RARE_8", "_Flag": true, "_Pos": [1.0, 2.0, 3.0], "_Item": "ITEM_B", "_Res": "Art/Model/a.pfb" },
+                { "_Rare": "RARE_5", "_Flag": false, "_Pos": [-0.5, 0.0, 8.25], "_Item": "ITEM_A", "_Res": "" }
+            ],
+            "_Name": "root",
+            "_Id": "01234567-89ab-cdef-0123-456789abcdef",
+            "_Scale": 1.5,
+            "_Pos": [4.0, 5.0, 6.0],
+            "_Flags": ["FLAG_A", "FLAG_C"],
+            "_Other": { "$extern": "GameDesign/Other.user", "$type": "app.Other" }
+        }
+    }"#;
+
+    // the user file the root links to
+    const OTHER_JSON: &str = r#"{
+        "app.Other": {
+            "_N": 7,
+            "_Data": { "_Rare": "RARE_0", "_Flag": true, "_Pos": [0.0, 0.0, 0.0], "_Item": "ITEM_A", "_Res": "" }
+        }
+    }"#;
+
+    fn test_db() -> TypeDatabase {
+        TypeDatabase::from_json(RSZ_DUMP, ENUMS)
+    }
+
+    fn read_user<'a>(bytes: &[u8], db: &'a TypeDatabase) -> DeRsz<'a> {
+        User::from_bytes(bytes).unwrap().rsz.deserialize_owned(db, DumpOptions::default()).unwrap()
+    }
+
+    fn write_user(user: &User) -> Vec<u8> {
+        let mut cursor = Cursor::new(vec![]);
+        user.write(&mut cursor).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn import_round_trip() {
+        let db = test_db();
+        let bytes = write_user(&RszImporter::import_user(USER_JSON, &db).unwrap());
+
+        let user = User::from_bytes(&bytes).unwrap();
+        assert_eq!(user.resource_names, ["Art/Model/a.pfb"]);
+        assert_eq!(user.children.len(), 1);
+        assert_eq!(user.children[0].name, "GameDesign/Other.user");
+        let dumped = serde_json::to_string(&user.rsz.deserializev2(&db, false).unwrap()).unwrap();
+        let expected: Value = serde_json::from_str(USER_JSON).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&dumped).unwrap(), expected);
+
+        let rewritten = write_user(&RszImporter::import_user(&dumped, &db).unwrap());
+        assert_eq!(rewritten, bytes);
+    }
+
+    // the inlined file is only a link again once imported, its $ids don't mix with the ones of the root
+    #[test]
+    fn import_inlined_extern() {
+        let db = test_db();
+        let bytes = write_user(&RszImporter::import_user(USER_JSON, &db).unwrap());
+        let other_bytes = write_user(&RszImporter::import_user(OTHER_JSON, &db).unwrap());
+
+        let mut rsz = read_user(&bytes, &db);
+        let mut other = read_user(&other_bytes, &db);
+        rsz.object_refs = true;
+        other.object_refs = true;
+        let (&slot, _) = rsz.externs.iter().next().unwrap();
+        rsz.inline_externs.insert(slot, Rc::new(other));
+        let dumped = serde_json::to_value(&rsz).unwrap();
+
+        let link = &dumped["app.Root"]["_Other"];
+        assert_eq!(link["$extern"], "GameDesign/Other.user");
+        assert_eq!(link["$value"]["_N"], 7);
+        let inner_id = &link["$value"]["_Data"]["$id"];
+        assert!(dumped["app.Root"]["_Values"].as_array().unwrap().iter().any(|value| &value["$id"] == inner_id));
+
+        let rewritten = write_user(&RszImporter::import_user(&dumped.to_string(), &db).unwrap());
+        assert_eq!(rewritten, bytes);
+    }
+
+    // the enum object is dumped as the object in its value field, importing puts the wrapper back
+    #[test]
+    fn import_enum_wrapped_object() {
+        let db = test_db();
+        let json = r#"{ "app.Slot": { "_Kind": { "_N": 3 } } }"#;
+        let bytes = write_user(&RszImporter::import_user(json, &db).unwrap());
+
+        let rsz = read_user(&bytes, &db);
+        let hashes: Vec<u32> = rsz.structs.iter().map(|instance| instance.hash).collect();
+        assert_eq!(hashes, [0, 0x8000, 0x7000, 0x6000]);
+        let dumped = serde_json::to_value(&rsz).unwrap();
+        assert_eq!(dumped, serde_json::from_str::<Value>(json).unwrap());
+
+        let rewritten = write_user(&RszImporter::import_user(&dumped.to_string(), &db).unwrap());
+        assert_eq!(rewritten, bytes);
+    }
+}
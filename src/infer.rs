@@ -0,0 +1,554 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::align::align_up;
+use crate::dersz::{RszDump, RszField, RszType, RszValue};
+use crate::rsz::{Rsz, TypeDescriptor};
+use crate::typedb::TypeDatabase;
+
+// A guessed layout for an instance whose type is not in the rsz dump
+#[derive(Debug)]
+pub struct InferredStruct {
+    pub hash: u32,
+    pub crc: u32,
+    pub instance: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub fields: Vec<RszField>,
+    // where known instances point at this one, the field type is usually the name or a base class of it
+    pub referenced_as: Vec<String>,
+    // the search ran out of budget, so the instance bounds are the best split found but not every split was tried
+    pub search_exhausted: bool,
+}
+
+// Same layout as an entry of rszmhwilds.json, keyed by the hash in hex
+#[derive(Serialize)]
+pub struct DumpEntry {
+    crc: String,
+    name: String,
+    fields: Vec<RszField>,
+}
+
+pub fn placeholder_name(hash: u32) -> String {
+    format!("unknown_{hash:08x}")
+}
+
+impl InferredStruct {
+    pub fn to_dump_entry(&self) -> (String, DumpEntry) {
+        (format!("{:x}", self.hash), DumpEntry {
+            crc: format!("{:x}", self.crc),
+            name: placeholder_name(self.hash),
+            fields: self.fields.clone(),
+        })
+    }
+}
+
+#[derive(Clone)]
+enum Span<'a> {
+    Known(RszValue<'a>),
+    Unknown { start: u64, end: u64 },
+    Extern,
+}
+
+// instance index and the type hash of the field pointing at it
+fn object_refs(value: &RszType, refs: &mut Vec<(u32, u32)>) {
+    match value {
+        RszType::Object(hash, ptr) => refs.push((*ptr, *hash)),
+        RszType::Array(values) => values.iter().for_each(|value| object_refs(value, refs)),
+        RszType::Struct(value) => value.fields.iter().for_each(|value| object_refs(value, refs)),
+        RszType::Enum(value, _) => object_refs(value, refs),
+        _ => (),
+    }
+}
+
+fn instance_refs(value: &RszValue) -> Vec<(u32, u32)> {
+    let mut refs = vec![];
+    value.fields.iter().for_each(|value| object_refs(value, &mut refs));
+    refs
+}
+
+fn is_known(rsz: &Rsz, db: &TypeDatabase, i: usize) -> bool {
+    rsz.extern_slots.contains_key(&(i as u32)) || db.structs.contains_key(&rsz.type_descriptors[i].hash)
+}
+
+// Parses the known instances first..last from the cursor, instances only point at earlier ones
+fn parse_known<'a>(rsz: &Rsz, db: &TypeDatabase, cursor: &mut Cursor<&'a [u8]>, first: usize, last: usize) -> Option<Vec<Span<'a>>> {
+    (first..last).map(|i| {
+        if rsz.extern_slots.contains_key(&(i as u32)) {
+            return Some(Span::Extern);
+        }
+        let td = &rsz.type_descriptors[i];
+        let value = RszDump::parse_struct(cursor, TypeDescriptor { hash: td.hash, crc: td.crc }, db, true).ok()?;
+        if instance_refs(&value).iter().any(|(ptr, _)| *ptr as usize >= i) {
+            return None;
+        }
+        Some(Span::Known(value))
+    }).collect()
+}
+
+// Where the first field of an instance starts relative to where the previous one ended
+fn first_field_align(db: &TypeDatabase, hash: u32) -> u64 {
+    match db.structs.get(&hash).and_then(|s| s.fields.first()) {
+        Some(field) if field.array => field.align.max(4) as u64,
+        Some(field) => field.align.max(1) as u64,
+        None => 1,
+    }
+}
+
+// Unknown instances only leave their start, the end is searched for: an end is possible when the known
+// instances after it parse up to the next unknown one, or up to the end of the data (only padding left).
+// An unknown instance can swallow the ones around it and still leave something that parses, so every
+// split is tried (within a budget) and the best one kept: the one with the most strings, objects, guids and
+// vectors recognized in it, then one where a single layout fits all the instances of a type.
+struct Search<'a> {
+    rsz: &'a Rsz<'a>,
+    db: &'a TypeDatabase,
+    // instance parses left before settling for the best split found so far
+    budget: usize,
+    // splits were left untried when the budget ran out
+    exhausted: bool,
+    best: Option<((usize, bool), Vec<Span<'a>>)>,
+}
+
+const SEARCH_BUDGET: usize = 200_000;
+
+impl<'a> Search<'a> {
+    fn finish(&mut self, spans: &[Span<'a>]) {
+        let inferred = infer_fields(self.rsz, self.db, spans);
+        let score = (evidence(&inferred), shared_layouts(self.rsz, &inferred).is_some());
+        if self.best.as_ref().is_none_or(|(best, _)| score > *best) {
+            self.best = Some((score, spans.to_vec()));
+        }
+    }
+
+    fn run(&mut self, spans: &mut Vec<Span<'a>>, pos: u64) {
+        let count = self.rsz.type_descriptors.len();
+        let i = spans.len();
+        if i == count {
+            return self.finish(spans);
+        }
+
+        let next_unknown = (i + 1..count).find(|&j| !is_known(self.rsz, self.db, j)).unwrap_or(count);
+        if is_known(self.rsz, self.db, i) {
+            let mut cursor = Cursor::new(&self.rsz.data[..]);
+            cursor.set_position(pos);
+            if let Some(known) = parse_known(self.rsz, self.db, &mut cursor, i, next_unknown) {
+                let len = spans.len();
+                spans.extend(known);
+                self.run(spans, cursor.position());
+                spans.truncate(len);
+            }
+            return;
+        }
+
+        if i + 1 == count {
+            spans.push(Span::Unknown { start: pos, end: self.rsz.data.len() as u64 });
+            self.run(spans, self.rsz.data.len() as u64);
+            spans.pop();
+            return;
+        }
+        // two unknown instances in a row can be split anywhere, the guesser reads the next one from a u32
+        if next_unknown == i + 1 {
+            for end in pos..=self.rsz.data.len() as u64 {
+                if end != align_up(end, 4) {
+                    continue;
+                }
+                if self.budget == 0 {
+                    self.exhausted = true;
+                    return;
+                }
+                self.budget -= 1;
+                spans.push(Span::Unknown { start: pos, end });
+                self.run(spans, end);
+                spans.pop();
+            }
+            return;
+        }
+
+        let align = first_field_align(self.db, self.rsz.type_descriptors[i + 1].hash);
+        let mut cursor = Cursor::new(&self.rsz.data[..]);
+        for end in pos..=self.rsz.data.len() as u64 {
+            // the next instance starts aligned, other ends give the same parse
+            if end != align_up(end, align) {
+                continue;
+            }
+            if self.budget == 0 {
+                self.exhausted = true;
+                return;
+            }
+            self.budget -= 1;
+            cursor.set_position(end);
+            let Some(known) = parse_known(self.rsz, self.db, &mut cursor, i + 1, next_unknown) else {
+                continue;
+            };
+            let rest = &self.rsz.data[cursor.position() as usize..];
+            if next_unknown == count && (rest.len() >= 16 || rest.iter().any(|&b| b != 0)) {
+                continue;
+            }
+            let len = spans.len();
+            spans.push(Span::Unknown { start: pos, end });
+            spans.extend(known);
+            self.run(spans, cursor.position());
+            spans.truncate(len);
+        }
+    }
+}
+
+fn evidence(inferred: &[InferredStruct]) -> usize {
+    inferred.iter()
+        .flat_map(|instance| &instance.fields)
+        .filter(|field| matches!(field.r#type.as_str(), "String" | "Object" | "Guid" | "Vec3"))
+        .count()
+}
+
+// Whether the fields read exactly the instance data, leaving at most some padding
+fn fits(fields: &[RszField], data: &[u8], start: u64) -> bool {
+    let read_u32 = |pos: u64| {
+        let pos = pos as usize;
+        data.get(pos..pos + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as u64)
+    };
+    let mut pos = 0;
+    for field in fields {
+        let align = if field.array { field.align.max(4) } else { field.align.max(1) } as u64;
+        pos = align_up(start + pos, align) - start;
+        let size = match (field.array, field.r#type.as_str()) {
+            (true, _) => read_u32(pos).map(|n| 4 + n * field.size as u64),
+            (false, "String") => read_u32(pos).map(|n| 4 + n * 2),
+            (false, _) => Some(field.size as u64),
+        };
+        match size {
+            Some(size) if pos + size <= data.len() as u64 => pos += size,
+            _ => return false,
+        }
+    }
+    let rest = &data[pos as usize..];
+    rest.len() < 16 && rest.iter().all(|&b| b == 0)
+}
+
+// For each type, the layout guessed from one of its instances that fits all of them
+fn shared_layouts(rsz: &Rsz, inferred: &[InferredStruct]) -> Option<HashMap<u32, Vec<RszField>>> {
+    let mut layouts = HashMap::new();
+    for instance in inferred {
+        if layouts.contains_key(&instance.hash) {
+            continue;
+        }
+        let same_type = inferred.iter().filter(|other| other.hash == instance.hash).collect::<Vec<_>>();
+        let layout = same_type.iter().find(|candidate| same_type.iter().all(|other| {
+            let data = &rsz.data[other.offset as usize..(other.offset + other.size) as usize];
+            fits(&candidate.fields, data, other.offset)
+        }))?;
+        layouts.insert(instance.hash, layout.fields.clone());
+    }
+    Some(layouts)
+}
+
+pub fn infer_layouts(rsz: &Rsz, db: &TypeDatabase) -> Result<Vec<InferredStruct>> {
+    infer_layouts_with_budget(rsz, db, SEARCH_BUDGET)
+}
+
+fn infer_layouts_with_budget(rsz: &Rsz, db: &TypeDatabase, budget: usize) -> Result<Vec<InferredStruct>> {
+    let count = rsz.type_descriptors.len();
+    let first_unknown = (0..count).find(|&i| !is_known(rsz, db, i)).unwrap_or(count);
+    if first_unknown == count {
+        return Ok(vec![]);
+    }
+    let mut cursor = Cursor::new(&rsz.data[..]);
+    let mut spans = vec![];
+    for i in 0..first_unknown {
+        let mut known = parse_known(rsz, db, &mut cursor, i, i + 1)
+            .ok_or(anyhow!("Could not parse known instance #{i} ({:08x})", rsz.type_descriptors[i].hash))?;
+        spans.append(&mut known);
+    }
+
+    let mut search = Search { rsz, db, budget, exhausted: false, best: None };
+    let start = cursor.position();
+    search.run(&mut spans, start);
+    let Some((_, spans)) = search.best else {
+        let td = &rsz.type_descriptors[first_unknown];
+        return Err(anyhow!("Could not find where the unknown instances end, starting at #{first_unknown} ({:08x})", td.hash));
+    };
+
+    let mut inferred = infer_fields(rsz, db, &spans);
+    if search.exhausted {
+        inferred.iter_mut().for_each(|instance| instance.search_exhausted = true);
+    }
+    match shared_layouts(rsz, &inferred) {
+        Some(layouts) => inferred.iter_mut().for_each(|instance| instance.fields = layouts[&instance.hash].clone()),
+        None => eprintln!("[WARN] No layout fits every instance of a type, the guess is likely wrong"),
+    }
+    Ok(inferred)
+}
+
+fn infer_fields(rsz: &Rsz, db: &TypeDatabase, spans: &[Span]) -> Vec<InferredStruct> {
+    // every instance is pointed at once, by its parent, or is a root
+    let mut referenced = rsz.roots.iter().copied().collect::<HashSet<_>>();
+    let mut referenced_as = BTreeMap::<u32, Vec<String>>::new();
+    for span in spans {
+        if let Span::Known(value) = span {
+            for (ptr, hash) in instance_refs(value) {
+                referenced.insert(ptr);
+                let name = db.structs.get(&hash).map(|s| s.name.clone()).unwrap_or_default();
+                referenced_as.entry(ptr).or_default().push(name);
+            }
+        }
+    }
+
+    let mut inferred = vec![];
+    for (i, span) in spans.iter().enumerate() {
+        let Span::Unknown { start, end } = *span else {
+            continue;
+        };
+        let td = &rsz.type_descriptors[i];
+        let data = &rsz.data[start as usize..end as usize];
+        let fields = FieldGuesser { rsz, db, instance: i as u32, data, start, referenced: &mut referenced }.guess();
+        inferred.push(InferredStruct {
+            hash: td.hash,
+            crc: td.crc,
+            instance: i as u32,
+            offset: start,
+            size: end - start,
+            fields,
+            referenced_as: referenced_as.remove(&(i as u32)).unwrap_or_default(),
+            search_exhausted: false,
+        });
+    }
+    inferred
+}
+
+struct FieldGuesser<'a> {
+    rsz: &'a Rsz<'a>,
+    db: &'a TypeDatabase,
+    instance: u32,
+    data: &'a [u8],
+    // offset of data in the rsz data, alignment is relative to that
+    start: u64,
+    referenced: &'a mut HashSet<u32>,
+}
+
+fn field(name: String, r#type: &str, original_type: &str, align: u32, size: u32, array: bool) -> RszField {
+    RszField {
+        align,
+        array,
+        name,
+        native: false,
+        original_type: original_type.to_string(),
+        size,
+        r#type: r#type.to_string(),
+    }
+}
+
+// Floats in game data are mostly in a small range, their bits are large as integers
+fn is_float(value: u32) -> bool {
+    let float = f32::from_bits(value);
+    (value as i32).unsigned_abs() >= 0x10000 && float.is_normal() && (1e-5..=1e6).contains(&float.abs())
+}
+
+impl<'a> FieldGuesser<'a> {
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn type_name(&self, ptr: u32) -> String {
+        let hash = self.rsz.type_descriptors[ptr as usize].hash;
+        match self.db.structs.get(&hash) {
+            Some(s) => s.name.clone(),
+            None => placeholder_name(hash),
+        }
+    }
+
+    // An earlier instance that nothing else points at
+    fn is_free_instance(&self, ptr: u32) -> bool {
+        ptr != 0 && ptr < self.instance && !self.referenced.contains(&ptr)
+    }
+
+    // u32 length in utf16 units with the null terminator counted, followed by printable text
+    fn string_len(&self, pos: usize) -> Option<usize> {
+        let n = self.u32_at(pos)? as usize;
+        if n < 2 {
+            return None;
+        }
+        let bytes = self.data.get(pos + 4..pos + 4 + n.checked_mul(2)?)?;
+        let units = bytes.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>();
+        let (last, text) = units.split_last()?;
+        let text = String::from_utf16(text).ok()?;
+        (*last == 0 && !text.chars().any(char::is_control)).then_some(4 + n * 2)
+    }
+
+    fn object_array_len(&self, pos: usize) -> Option<usize> {
+        let n = self.u32_at(pos)?;
+        if n == 0 || n > 1024 {
+            return None;
+        }
+        let ptrs = (0..n as usize).map(|k| self.u32_at(pos + 4 + k * 4)).collect::<Option<Vec<_>>>()?;
+        let ascending = ptrs.windows(2).all(|w| w[0] < w[1]);
+        (ascending && ptrs.iter().all(|&ptr| self.is_free_instance(ptr))).then_some(4 + n as usize * 4)
+    }
+
+    fn is_guid(&self, pos: usize) -> bool {
+        let Some(bytes) = self.data.get(pos..pos + 16) else {
+            return false;
+        };
+        (self.start + pos as u64).is_multiple_of(8) && (1..=5).contains(&(bytes[7] >> 4)) && bytes[8] & 0xc0 == 0x80
+    }
+
+    // Three floats and the padding after them, starting 16 aligned
+    fn is_vec3(&self, pos: usize) -> bool {
+        let values = (0..4).map(|k| self.u32_at(pos + k * 4)).collect::<Option<Vec<_>>>();
+        let Some(values) = values.filter(|_| (self.start + pos as u64).is_multiple_of(16)) else {
+            return false;
+        };
+        values[3] == 0
+            && values[..3].iter().all(|&v| v == 0 || is_float(v))
+            && values[..3].iter().any(|&v| is_float(v))
+    }
+
+    fn guess(self) -> Vec<RszField> {
+        let mut fields = vec![];
+        let mut pos = 0;
+        while pos < self.data.len() {
+            let name = format!("_Unknown{pos:02X}");
+            let aligned = (self.start + pos as u64).is_multiple_of(4);
+            let Some(value) = self.u32_at(pos).filter(|_| aligned) else {
+                // leftover bytes that don't make up a u32, zeros at the end are padding
+                if self.data[pos..].iter().all(|&b| b == 0) && !fields.is_empty() {
+                    break;
+                }
+                match self.data[pos] {
+                    0 | 1 => fields.push(field(name, "Bool", "System.Boolean", 1, 1, false)),
+                    _ => fields.push(field(name, "U8", "System.Byte", 1, 1, false)),
+                }
+                pos += 1;
+                continue;
+            };
+
+            // fields aligned to 16 leave zeros before them, which move around between instances
+            let next_aligned = (align_up(self.start + pos as u64, 16) - self.start) as usize;
+            if next_aligned > pos && value == 0 && self.data[pos..next_aligned.min(self.data.len())].iter().all(|&b| b == 0) && self.is_vec3(next_aligned) {
+                pos = next_aligned;
+                continue;
+            }
+
+            if self.is_vec3(pos) {
+                fields.push(field(name, "Vec3", "via.vec3", 16, 16, false));
+                pos += 16;
+            } else if let Some(len) = self.string_len(pos) {
+                fields.push(field(name, "String", "System.String", 4, 8, false));
+                pos += len;
+            } else if let Some(len) = self.object_array_len(pos) {
+                let first = self.u32_at(pos + 4).unwrap();
+                for k in 0..(len - 4) / 4 {
+                    self.referenced.insert(self.u32_at(pos + 4 + k * 4).unwrap());
+                }
+                fields.push(field(name, "Object", &self.type_name(first), 4, 4, true));
+                pos += len;
+            } else if self.is_guid(pos) {
+                fields.push(field(name, "Guid", "System.Guid", 8, 16, false));
+                pos += 16;
+            } else if self.is_free_instance(value) {
+                self.referenced.insert(value);
+                fields.push(field(name, "Object", &self.type_name(value), 4, 4, false));
+                pos += 4;
+            } else {
+                let small_int = (value as i32).unsigned_abs() < 0x10000;
+                if is_float(value) {
+                    fields.push(field(name, "F32", "System.Single", 4, 4, false));
+                } else if small_int || value <= i32::MAX as u32 {
+                    fields.push(field(name, "S32", "System.Int32", 4, 4, false));
+                } else {
+                    fields.push(field(name, "U32", "System.UInt32", 4, 4, false));
+                }
+                pos += 4;
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // only the null type and one known type, the tests use hashes outside of it for the unknown instances
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Known", "crc": "aa", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    fn test_db() -> TypeDatabase {
+        TypeDatabase::from_json(RSZ_DUMP, "{}")
+    }
+
+    // the null instance followed by instances of the given types, the last one is the root
+    fn rsz(hashes: &[u32], data: Vec<u8>) -> Rsz<'static> {
+        Rsz {
+            roots: vec![hashes.len() as u32],
+            extern_slots: HashMap::new(),
+            type_descriptors: [0].iter().chain(hashes).map(|&hash| TypeDescriptor { hash, crc: 0 }).collect(),
+            data: Cow::Owned(data),
+        }
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn types(inferred: &InferredStruct) -> Vec<&str> {
+        inferred.fields.iter().map(|field| field.r#type.as_str()).collect()
+    }
+
+    #[test]
+    fn guess_vec3() {
+        let db = test_db();
+        for data in [floats(&[1.0, 1.5, 2.5, 0.0]), floats(&[0.0, 1.5, 2.5, 0.0])] {
+            let inferred = infer_layouts(&rsz(&[0xdead], data), &db).unwrap();
+            assert_eq!(types(&inferred[0]), ["Vec3"]);
+        }
+    }
+
+    #[test]
+    fn split_adjacent_unknowns() {
+        let db = test_db();
+        let mut data = 7u32.to_le_bytes().to_vec();
+        data.extend(3u32.to_le_bytes());
+        data.extend("ab\0".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        let inferred = infer_layouts(&rsz(&[0xdead, 0xbeef], data.clone()), &db).unwrap();
+        assert_eq!(inferred.len(), 2);
+        assert_eq!(inferred[0].offset + inferred[0].size, inferred[1].offset);
+        assert_eq!(inferred[1].offset + inferred[1].size, data.len() as u64);
+        assert!(inferred.iter().any(|instance| types(instance).contains(&"String")));
+    }
+
+    #[test]
+    fn exhausted_search_is_flagged() {
+        let db = test_db();
+        let data = floats(&[1.0, 2.0, 3.0, 4.0]);
+        let hashes = [0xdead, 0xbeef, 0xf00d];
+        let inferred = infer_layouts(&rsz(&hashes, data.clone()), &db).unwrap();
+        assert!(inferred.iter().all(|instance| !instance.search_exhausted));
+
+        // enough for one split of the three instances and not the others
+        let inferred = infer_layouts_with_budget(&rsz(&hashes, data), &db, 2).unwrap();
+        assert_eq!(inferred.len(), 3);
+        assert!(inferred.iter().all(|instance| instance.search_exhausted));
+    }
+
+    #[test]
+    fn known_instances_bound_unknown_ones() {
+        let db = test_db();
+        let mut data = floats(&[1.0, 1.5, 2.5]);
+        data.extend(7i32.to_le_bytes());
+        let inferred = infer_layouts(&rsz(&[0xdead, 0x1000], data), &db).unwrap();
+        assert_eq!(inferred.len(), 1);
+        assert_eq!((inferred[0].offset, inferred[0].size), (0, 12));
+        assert!(!inferred[0].search_exhausted);
+    }
+}
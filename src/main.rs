@@ -1,238 +1,748 @@
-mod align;
-mod bitfield;
-mod byte_reader;
-mod compression;
-mod file_ext;
-mod msg;
-mod rsz;
-mod tex;
-mod user;
-mod dersz;
-
-extern crate image;
-
-use std::io::*;
-use anyhow::anyhow;
-use clap::Parser;
-use msg::Msg;
-use std::fs::{self, read_to_string,File};
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::time::SystemTime;
-use tex::Tex;
-use user::User;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short('f'), long)]
-    file_name: Option<String>,
-    
-    #[arg(short('r'), long)]
-    root_dir: Option<String>,
-
-    #[arg(short('l'), long)]
-    list: Option<String>, 
-
-    #[arg(short('o'), long, default_value_t = String::from("outputs"))]
-    out_dir: String,
-}
-
-fn construct_paths(file: String, prefix: Option<String>, out_dir_base: String, preserve_structure: bool) -> Result<(PathBuf, PathBuf)> {
-    let full_file_path = match prefix {
-        Some(ref prefix) => Path::new(&prefix).join(&file),
-        None => PathBuf::from(&file),
-    };
-    let output_path = PathBuf::from(out_dir_base).join(
-        if preserve_structure {
-            match prefix {
-                Some(ref prefix) => {
-                    let file = Path::new(&full_file_path);
-                    file.strip_prefix(prefix).unwrap().to_str().unwrap()
-                }
-                None => &file
-            }
-        } else {
-            let file = Path::new(&file);
-            let path = file.file_name().unwrap().to_str().unwrap();
-            path
-        }
-    );
-
-    Ok((full_file_path, output_path))
-}
-
-enum FileType {
-    Msg(u32),
-    User(u32),
-    Tex(u32),
-    Unknown
-}
-
-fn get_file_ext(file_name: String) -> Result<FileType> {
-    let split = file_name.split('.').collect::<Vec<_>>();
-
-    let version = match u32::from_str_radix(split[split.len() - 1], 10) {
-        Ok(val) => val,
-        Err(e) => return Err(Error::new(ErrorKind::Other, format!("{e}"))),
-    };
-
-    let file_type = match split.get(split.len() - 2) {
-        Some(ext) => {
-            match *ext {
-                "user" => FileType::User(version),
-                "msg" => FileType::Msg(version),
-                "tex" => FileType::Tex(version),
-                _ => FileType::Unknown
-            }
-        },
-        None => {
-            FileType::Unknown
-        }
-    };
-
-    Ok(file_type)
-}
-
-fn dump_file(file_path: PathBuf, output_path: PathBuf) -> anyhow::Result<()> {
-    //output_path.set_file_name(file_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
-    let file_name = match file_path.file_name() {
-        Some(file_name) => file_name,
-        None => {
-            return Err(anyhow!("Path does not contain file"));
-        }
-    };
-    let file_type = get_file_ext(file_name.to_string_lossy().to_string())?;
-    let res = match file_type {
-        FileType::Msg(_v) => {
-            let mut output_path = output_path.clone();
-            output_path.set_file_name(output_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
-            let msg = Msg::new(file_path.to_string_lossy().to_string())?;
-
-            println!("Trying to save to {:?}", &output_path);
-            let _ = fs::create_dir_all(output_path.parent().unwrap())?;
-            let mut f = std::fs::File::create(&output_path).expect("Error Creating File");
-            msg.save(&mut f);
-            println!("Saved file");
-            Ok(())
-        },
-        FileType::User(_v) => {
-            let rsz = User::new(File::open(file_path.clone())?)?.rsz;
-            let res = rsz.deserializev2();
-            let res = match res {
-                Ok(nodes) => {
-                    let mut output_path = output_path.clone();
-                    output_path.set_file_name(output_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
-                    //output_path.push(file_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
-
-                    let json_res = serde_json::to_string_pretty(&nodes); 
-                    match json_res {
-                        Ok(json) => {
-                            let _ = fs::create_dir_all(output_path.parent().unwrap())?;
-                            let mut f = std::fs::File::create(&output_path).expect("Error Creating File");
-                            f.write_all(json.as_bytes())?;
-                            println!("[INFO] Saved File {:?}", &output_path);
-                            Ok(())
-                        },
-                        Err(e) => {
-                            Err(anyhow!("File: {file_path:?} Reason: {e:?}"))
-                        }
-                    }
-                },
-                Err(e) => {
-                    Err(anyhow!("File: {file_path:?} Reason: {e:?}"))
-                }
-            };
-            res
-            //Ok(())
-        },
-        FileType::Tex(_v) => {
-            let file_name = file_name.to_string_lossy().to_string();
-            let tex = Tex::new(file_name.clone())?;
-            let rgba = tex.to_rgba(0)?;
-            println!("{}", rgba.data.len());
-            let name = format!("{}_{}.png", file_name, 0);
-            println!("saving to {name}");
-            let _ = image::save_buffer(
-                &Path::new(&name),
-                &rgba.data,
-                rgba.width,
-                rgba.height,
-                image::ExtendedColorType::Rgba8,
-            );
-            Ok(())
-        },
-        FileType::Unknown => return Err(anyhow!("Unknown File Type")),
-    };
-    res
-}
-
-#[allow(dead_code)]
-fn find_files_with_extension(base_dir: PathBuf, extension: &str) -> Vec<PathBuf> {
-    let mut results = Vec::new();
-    let mut paths: Vec<PathBuf> = Vec::new();
-    paths.push(base_dir);
-    while let Some(dir) = paths.pop() {
-        if let Result::Ok(entries) = std::fs::read_dir(dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    paths.push(path);
-                } else {
-                    if let Some(x) = path.file_name().unwrap().to_str() {
-                        if x.ends_with(extension) {
-                            results.push(path);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    results
-}
-
-fn dump_all(root_dir: Option<String>, out_dir: String, list_file: String) -> anyhow::Result<()> {
-    let list = read_to_string(&list_file).expect(format!("Could not open file {list_file}").as_str());
-    let list: Vec<&str> = list.lines().collect();
-    for file in list {
-        let paths = construct_paths(file.to_string(), root_dir.clone(), out_dir.clone(), true);
-        let (file_path, output_path) = match paths {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("[ERROR] Could not create file path {file} and output path {e}");
-                continue
-            }
-        };
-        match dump_file(file_path, output_path) {
-            Ok(()) => (),
-            Err(e) => {
-                eprintln!("[ERROR] Error dumping file {e}");
-                continue
-            }
-        };
-    }
-    Ok(())
-}
-
-
-
-fn main() -> anyhow::Result<()> {
-    let now = SystemTime::now();
-    let args = Args::parse();
-    println!("{:#?}", args);
-    
-    match args.list {
-        Some(list) => {
-            dump_all(args.root_dir, args.out_dir, list)?;
-        }, 
-        None => match args.file_name {
-            Some(file_name) => {
-                let (file_path, output_path) = construct_paths(file_name.clone(), args.root_dir.clone(), args.out_dir.clone(), false)?;
-                dump_file(file_path, output_path)?;
-            },
-            None => println!("Must provide file name"),
-        }
-    }
-    println!("Time taken: {} ms", now.elapsed().unwrap().as_millis());
-    Ok(())
-}
+mod align;
+mod batch;
+mod bitfield;
+mod byte_reader;
+mod compression;
+mod diagnostic;
+mod diff;
+mod enum_header;
+mod file_ext;
+// the tool only works on the json so far, typed access is only built for the tests
+#[cfg(test)]
+mod from_rsz;
+mod guid_index;
+mod msg;
+mod query;
+mod rsz;
+mod schema;
+mod tex;
+mod user;
+mod dersz;
+mod import;
+mod infer;
+mod minifloat;
+mod resolve;
+mod typedb;
+mod update;
+mod verify;
+
+extern crate image;
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::*;
+use anyhow::anyhow;
+use batch::BatchSummary;
+use byte_reader::map_file;
+use clap::{Parser, Subcommand};
+use dersz::{DeRsz, DumpOptions};
+use diff::DiffFormat;
+use guid_index::GuidIndex;
+use import::RszImporter;
+use msg::{Msg, MsgText};
+use query::Query;
+use std::fs::{self, read_to_string};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use resolve::{ExternMode, UserLoader};
+use tex::Tex;
+use typedb::LazyTypeDatabase;
+use update::UpdateReport;
+use user::User;
+use verify::VerifyReport;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short('f'), long, global = true)]
+    file_name: Option<String>,
+    
+    #[arg(short('r'), long, global = true)]
+    root_dir: Option<String>,
+
+    #[arg(short('l'), long, global = true)]
+    list: Option<String>, 
+
+    #[arg(short('o'), long, global = true, default_value_t = String::from("outputs"))]
+    out_dir: String,
+
+    /// Rsz type dump used for user files
+    #[arg(long, global = true, default_value_t = String::from("rszmhwilds.json"))]
+    rsz_dump: String,
+
+    /// Enum names used for user files
+    #[arg(long, global = true, default_value_t = String::from("gen/enums.json"))]
+    enums: String,
+
+    /// Don't read or write the binary cache of the rsz dump (<rsz_dump>.cache)
+    #[arg(long, global = true)]
+    no_type_cache: bool,
+
+    /// Also load the .user files referenced by user files, needs --root-dir
+    #[arg(long, global = true, value_enum)]
+    externs: Option<ExternMode>,
+
+    /// Dump objects once with their instance index as "$id" and later references to them as {"$ref": id}
+    #[arg(long, global = true)]
+    object_refs: bool,
+
+    /// Keep fields of unsupported types as raw bytes instead of skipping the file
+    #[arg(long, global = true)]
+    lenient: bool,
+
+    /// Msg files (or directories of them) whose text gets dumped next to the guid fields of user files that match an entry
+    #[arg(long, global = true)]
+    msg: Vec<String>,
+
+    /// Languages msg files are dumped in (like en,ja), all of them by default. Text dumped with --msg is english by default
+    #[arg(long, global = true, value_delimiter = ',')]
+    lang: Vec<String>,
+
+    /// Number of files of --list processed at the same time, only when dumping or importing
+    #[arg(short('j'), long, global = true, default_value_t = 1)]
+    jobs: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rebuild .user.3 or .msg.23 files from their json dumps
+    Import,
+    /// Report structs whose crc in .user.3 files differs from the rsz dump, or that are missing from it
+    Verify,
+    /// Print the values selected by a query from user files, e.g. "app.Root._Values[?_Rare == RARE_8]._Item"
+    Query {
+        query: String,
+    },
+    /// Compare two versions of a user or msg file
+    Diff {
+        old: String,
+        new: String,
+        /// json is saved to <out_dir>/<new file>.diff.json
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+    },
+    /// List the files that were added, removed or changed between --root-dir and an older root dir
+    Update {
+        /// Root dir of the older version
+        old_root: String,
+    },
+    /// Guess the layout of structs missing from the rsz dump, saved as <file>.inferred.json in the rsz dump format
+    Infer,
+    /// Add the guids in user and msg files to the guid index, files indexed before are replaced
+    Index {
+        #[arg(long, default_value_t = String::from("guid_index.json"))]
+        index: String,
+    },
+    /// Print where a guid shows up, from the guid index
+    Lookup {
+        guid: String,
+        #[arg(long, default_value_t = String::from("guid_index.json"))]
+        index: String,
+    },
+    /// Build the enum names (--enums) from the Enums_Internal.hpp header of the game dumper
+    Enums {
+        header: String,
+    },
+    /// Write json schemas (<out_dir>/schema) and typescript definitions (<out_dir>/rsz.d.ts) for the dumped user files
+    Schema,
+}
+
+fn construct_paths(file: String, prefix: Option<String>, out_dir_base: String, preserve_structure: bool) -> Result<(PathBuf, PathBuf)> {
+    let full_file_path = match prefix {
+        Some(ref prefix) => Path::new(&prefix).join(&file),
+        None => PathBuf::from(&file),
+    };
+    let output_path = PathBuf::from(out_dir_base).join(
+        if preserve_structure {
+            match prefix {
+                Some(ref prefix) => {
+                    let file = Path::new(&full_file_path);
+                    file.strip_prefix(prefix).unwrap().to_str().unwrap()
+                }
+                None => &file
+            }
+        } else {
+            let file = Path::new(&file);
+            let path = file.file_name().unwrap().to_str().unwrap();
+            path
+        }
+    );
+
+    Ok((full_file_path, output_path))
+}
+
+enum FileType {
+    Msg(u32),
+    User(u32),
+    Tex(u32),
+    Unknown
+}
+
+impl FileType {
+    fn name(&self) -> &'static str {
+        match self {
+            FileType::Msg(_) => "msg",
+            FileType::User(_) => "user",
+            FileType::Tex(_) => "tex",
+            FileType::Unknown => "unknown",
+        }
+    }
+}
+
+fn get_file_ext(file_name: String) -> Result<FileType> {
+    let split = file_name.split('.').collect::<Vec<_>>();
+
+    let version = match u32::from_str_radix(split[split.len() - 1], 10) {
+        Ok(val) => val,
+        Err(e) => return Err(Error::new(ErrorKind::Other, format!("{e}"))),
+    };
+
+    let file_type = match split.get(split.len() - 2) {
+        Some(ext) => {
+            match *ext {
+                "user" => FileType::User(version),
+                "msg" => FileType::Msg(version),
+                "tex" => FileType::Tex(version),
+                _ => FileType::Unknown
+            }
+        },
+        None => {
+            FileType::Unknown
+        }
+    };
+
+    Ok(file_type)
+}
+
+fn save_user_json(nodes: &DeRsz, output_path: PathBuf) -> anyhow::Result<()> {
+    let mut output_path = output_path.clone();
+    output_path.set_file_name(output_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
+    let json = serde_json::to_string_pretty(nodes)?;
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    let mut f = std::fs::File::create(&output_path).expect("Error Creating File");
+    f.write_all(json.as_bytes())?;
+    println!("[INFO] Saved File {:?}", &output_path);
+    Ok(())
+}
+
+// In link mode the files pulled in by references get dumped with the same layout as the root dir
+fn save_referenced(loader: Option<&UserLoader>, out_dir: &str) -> anyhow::Result<()> {
+    let Some(loader) = loader else {
+        return Ok(());
+    };
+    for (file_path, nodes) in loader.take_pending() {
+        let relative = file_path.strip_prefix(loader.root_dir()).unwrap_or(&file_path);
+        save_user_json(&nodes, Path::new(out_dir).join(relative))?;
+    }
+    Ok(())
+}
+
+// Msg entries of the --msg files, directories are searched for msg files
+fn load_msg_text(paths: &[String], languages: &[u32]) -> anyhow::Result<MsgText> {
+    fn add_path(msg_text: &mut MsgText, path: &Path) -> anyhow::Result<()> {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.path());
+            for entry in entries {
+                let path = entry.path();
+                let is_msg = path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .filter(|name| name.contains(".msg."))
+                    .and_then(|name| get_file_ext(name).ok())
+                    .is_some_and(|file_type| matches!(file_type, FileType::Msg(_)));
+                if is_msg || path.is_dir() {
+                    add_path(msg_text, &path)?;
+                }
+            }
+            return Ok(());
+        }
+        let msg = Msg::new(path.to_string_lossy().to_string()).map_err(|e| anyhow!("File: {path:?} Reason: {e:?}"))?;
+        msg_text.add(msg);
+        Ok(())
+    }
+
+    let mut msg_text = MsgText::new(languages);
+    for path in paths {
+        add_path(&mut msg_text, Path::new(path))?;
+    }
+    Ok(msg_text)
+}
+
+// The strings of the dump borrow from the mapped file, which stays open while f runs
+fn with_user<'a, T>(
+    file_path: &Path,
+    db: &'a LazyTypeDatabase,
+    loader: Option<&UserLoader<'a>>,
+    options: DumpOptions<'a>,
+    f: impl FnOnce(&DeRsz) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let reason = |e: anyhow::Error| anyhow!("File: {file_path:?} Reason: {e:?}");
+    match loader {
+        Some(loader) => {
+            let result = f(&*loader.load(file_path).map_err(reason)?);
+            loader.check_references().map_err(reason)?;
+            result
+        }
+        None => {
+            let map = map_file(file_path).map_err(|e| reason(e.into()))?;
+            let user = User::from_bytes(&map).map_err(reason)?;
+            let nodes = db.get().and_then(|db| user.rsz.deserialize_with(db, options)).map_err(reason)?;
+            f(&nodes)
+        }
+    }
+}
+
+fn dump_file<'a>(file_path: PathBuf, output_path: PathBuf, db: &'a LazyTypeDatabase, loader: Option<&UserLoader<'a>>, options: DumpOptions<'a>) -> anyhow::Result<()> {
+    //output_path.set_file_name(file_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
+    let file_name = match file_path.file_name() {
+        Some(file_name) => file_name,
+        None => {
+            return Err(anyhow!("Path does not contain file"));
+        }
+    };
+    let file_type = get_file_ext(file_name.to_string_lossy().to_string())?;
+    let res = match file_type {
+        FileType::Msg(_v) => {
+            let mut output_path = output_path.clone();
+            output_path.set_file_name(output_path.file_name().unwrap().to_str().unwrap().to_string() + ".json");
+            let mut msg = Msg::new(file_path.to_string_lossy().to_string())?;
+            msg.retain_languages(options.languages);
+
+            println!("Trying to save to {:?}", &output_path);
+            fs::create_dir_all(output_path.parent().unwrap())?;
+            let mut f = std::fs::File::create(&output_path).expect("Error Creating File");
+            msg.save(&mut f);
+            println!("Saved file");
+            Ok(())
+        },
+        FileType::User(_v) => {
+            if loader.is_some_and(|loader| !loader.start_dump(&file_path)) {
+                println!("[INFO] {file_path:?} was already dumped as a reference");
+                return Ok(());
+            }
+            with_user(&file_path, db, loader, options, |nodes| save_user_json(nodes, output_path))
+        },
+        FileType::Tex(_v) => {
+            let file_name = file_name.to_string_lossy().to_string();
+            let tex = Tex::new(file_name.clone())?;
+            let rgba = tex.to_rgba(0)?;
+            println!("{}", rgba.data.len());
+            let name = format!("{}_{}.png", file_name, 0);
+            println!("saving to {name}");
+            let _ = image::save_buffer(
+                &Path::new(&name),
+                &rgba.data,
+                rgba.width,
+                rgba.height,
+                image::ExtendedColorType::Rgba8,
+            );
+            Ok(())
+        },
+        FileType::Unknown => return Err(anyhow!("Unknown File Type")),
+    };
+    res
+}
+
+fn import_file(file_path: PathBuf, output_path: PathBuf, db: &LazyTypeDatabase) -> anyhow::Result<()> {
+    let json = read_to_string(&file_path)?;
+
+    let mut output_path = output_path.clone();
+    let file_name = output_path.file_name().unwrap().to_string_lossy().to_string();
+    match file_name.strip_suffix(".json") {
+        Some(file_name) => output_path.set_file_name(file_name),
+        None => return Err(anyhow!("Expected a .json file, got {file_path:?}")),
+    };
+    let file_type = get_file_ext(output_path.to_string_lossy().to_string())?;
+
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    match file_type {
+        FileType::Msg(_v) => {
+            let msg = Msg::import_json(&json).map_err(|e| anyhow!("File: {file_path:?} Reason: {e:?}"))?;
+            let f = std::fs::File::create(&output_path).expect("Error Creating File");
+            msg.write(BufWriter::new(f))?;
+        }
+        _ => {
+            let user = RszImporter::import_user(&json, db.get()?).map_err(|e| anyhow!("File: {file_path:?} Reason: {e:?}"))?;
+            let f = std::fs::File::create(&output_path).expect("Error Creating File");
+            user.write(BufWriter::new(f))?;
+        }
+    }
+    println!("[INFO] Saved File {:?}", &output_path);
+    Ok(())
+}
+
+fn verify_file(file_path: PathBuf, report: &RefCell<VerifyReport>, db: &LazyTypeDatabase) -> anyhow::Result<()> {
+    let file_name = file_path.file_name().ok_or(anyhow!("Path does not contain file"))?;
+    match get_file_ext(file_name.to_string_lossy().to_string())? {
+        FileType::User(_v) => report.borrow_mut().verify_user(&file_path, db.get()?)
+            .map_err(|e| anyhow!("File: {file_path:?} Reason: {e:?}")),
+        _ => Err(anyhow!("Only user files can be verified, got {file_path:?}")),
+    }
+}
+
+fn query_file<'a>(file_path: PathBuf, query: &Query, db: &'a LazyTypeDatabase, loader: Option<&UserLoader<'a>>, options: DumpOptions<'a>) -> anyhow::Result<usize> {
+    let file_name = file_path.file_name().ok_or(anyhow!("Path does not contain file"))?;
+    let FileType::User(_v) = get_file_ext(file_name.to_string_lossy().to_string())? else {
+        return Err(anyhow!("Only user files can be queried, got {file_path:?}"));
+    };
+    with_user(&file_path, db, loader, options, |nodes| {
+        let matches = query.run(nodes).map_err(|e| anyhow!("File: {file_path:?} Reason: {e:?}"))?;
+        for (path, value) in &matches {
+            println!("{}: {path} = {value}", file_path.display());
+        }
+        Ok(matches.len())
+    })
+}
+
+// The dump of a user or msg file, like the one saved by dump_file
+fn load_json<'a>(file_path: &Path, db: &'a LazyTypeDatabase, loader: Option<&UserLoader<'a>>, options: DumpOptions<'a>) -> anyhow::Result<serde_json::Value> {
+    let file_name = file_path.file_name().ok_or(anyhow!("Path does not contain file"))?;
+    match get_file_ext(file_name.to_string_lossy().to_string())? {
+        FileType::User(_v) => with_user(file_path, db, loader, options, |nodes| Ok(serde_json::to_value(nodes)?)),
+        FileType::Msg(_v) => Ok(Msg::new(file_path.to_string_lossy().to_string())?.to_json()),
+        _ => Err(anyhow!("Only user and msg files can be compared, got {file_path:?}")),
+    }
+}
+
+fn diff_files<'a>(
+    old: &Path,
+    new: &Path,
+    format: DiffFormat,
+    out_dir: &str,
+    db: &'a LazyTypeDatabase,
+    loader: Option<&UserLoader<'a>>,
+    options: DumpOptions<'a>,
+) -> anyhow::Result<()> {
+    let changes = diff::diff(&load_json(old, db, loader, options)?, &load_json(new, db, loader, options)?);
+    match format {
+        DiffFormat::Text => {
+            for change in &changes {
+                println!("{}", change.to_text());
+            }
+        }
+        DiffFormat::Json => {
+            let file_name = new.file_name().unwrap().to_string_lossy().to_string();
+            let output_path = Path::new(out_dir).join(file_name + ".diff.json");
+            fs::create_dir_all(output_path.parent().unwrap())?;
+            let f = std::fs::File::create(&output_path).expect("Error Creating File");
+            serde_json::to_writer_pretty(BufWriter::new(f), &changes)?;
+            println!("[INFO] Saved File {:?}", &output_path);
+        }
+    }
+    println!("[INFO] {} change(s) between {old:?} and {new:?}", changes.len());
+    Ok(())
+}
+
+// The new file comes from --root-dir, the old one from the same relative path under old_root
+fn update_file(file_path: PathBuf, root_dir: &str, old_root: &str, report: &RefCell<UpdateReport>, db: &LazyTypeDatabase, options: DumpOptions<'_>) -> anyhow::Result<()> {
+    let relative = file_path.strip_prefix(root_dir).unwrap_or(&file_path);
+    let old_path = Path::new(old_root).join(relative);
+    let file_name = file_path.file_name().ok_or(anyhow!("Path does not contain file"))?;
+    let count_changes = || match get_file_ext(file_name.to_string_lossy().to_string()) {
+        Ok(FileType::User(_) | FileType::Msg(_)) => Some(load_json(&old_path, db, None, options)
+            .and_then(|old| Ok(diff::diff(&old, &load_json(&file_path, db, None, options)?).len()))),
+        _ => None,
+    };
+    report.borrow_mut().compare(relative, &old_path, &file_path, count_changes)
+}
+
+fn index_file<'a>(
+    file_path: PathBuf,
+    root_dir: Option<&str>,
+    index: &RefCell<GuidIndex>,
+    db: &'a LazyTypeDatabase,
+    loader: Option<&UserLoader<'a>>,
+    options: DumpOptions<'a>,
+) -> anyhow::Result<()> {
+    let file_name = file_path.file_name().ok_or(anyhow!("Path does not contain file"))?;
+    let file = root_dir.and_then(|root_dir| file_path.strip_prefix(root_dir).ok()).unwrap_or(&file_path);
+    let file = file.to_string_lossy().to_string();
+    let count = match get_file_ext(file_name.to_string_lossy().to_string())? {
+        FileType::User(_v) => with_user(&file_path, db, loader, options, |nodes| Ok(index.borrow_mut().add_user(&file, nodes)))?,
+        FileType::Msg(_v) => index.borrow_mut().add_msg(&file, &Msg::new(file_path.to_string_lossy().to_string())?),
+        _ => return Err(anyhow!("Only user and msg files can be indexed, got {file_path:?}")),
+    };
+    println!("[INFO] Indexed {count} guid(s) from {file:?}");
+    Ok(())
+}
+
+fn lookup_guid(guid: &str, index_path: &str) -> anyhow::Result<()> {
+    let index = GuidIndex::load(Path::new(index_path))?;
+    let refs = index.lookup(guid);
+    for guid_ref in refs {
+        match &guid_ref.location {
+            guid_index::Location::Field(path) => println!("{}: {path}", guid_ref.file),
+            guid_index::Location::Msg(name) => println!("{}: msg entry {name}", guid_ref.file),
+        }
+    }
+    println!("[INFO] {} reference(s) to {guid}", refs.len());
+    Ok(())
+}
+
+fn infer_file(file_path: PathBuf, output_path: PathBuf, db: &LazyTypeDatabase) -> anyhow::Result<()> {
+    let map = map_file(&file_path)?;
+    let user = User::from_bytes(&map)?;
+    let inferred = infer::infer_layouts(&user.rsz, db.get()?).map_err(|e| anyhow!("File: {file_path:?} Reason: {e:?}"))?;
+    if inferred.is_empty() {
+        println!("[INFO] No unknown structs in {file_path:?}");
+        return Ok(());
+    }
+
+    let mut entries = BTreeMap::new();
+    for instance in &inferred {
+        println!(
+            "[INFO] Instance #{} ({:08x}) at {:#x}, {} byte(s), {} field(s)",
+            instance.instance, instance.hash, instance.offset, instance.size, instance.fields.len()
+        );
+        for name in &instance.referenced_as {
+            println!("    referenced as {name}");
+        }
+        let (key, entry) = instance.to_dump_entry();
+        if entries.contains_key(&key) {
+            println!("    skipped, already inferred from an earlier instance of the same type");
+            continue;
+        }
+        entries.insert(key, entry);
+    }
+
+    // kept apart from the files that can be merged into the dump as they are
+    let extension = match inferred.iter().any(|instance| instance.search_exhausted) {
+        true => {
+            eprintln!("[WARN] Gave up searching for where the unknown instances of {file_path:?} end, the layouts are the best split found");
+            ".inferred.unreliable.json"
+        }
+        false => ".inferred.json",
+    };
+    let mut output_path = output_path.clone();
+    output_path.set_file_name(output_path.file_name().unwrap().to_str().unwrap().to_string() + extension);
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    let f = std::fs::File::create(&output_path).expect("Error Creating File");
+    serde_json::to_writer_pretty(BufWriter::new(f), &entries)?;
+    println!("[INFO] Saved File {:?}", &output_path);
+    Ok(())
+}
+
+fn build_enums(header: &str, enums_path: &str) -> anyhow::Result<()> {
+    let source = read_to_string(header).map_err(|e| anyhow!("Could not open header {header:?}: {e}"))?;
+    let enums = enum_header::parse_header(&source).map_err(|e| anyhow!("File: {header:?} Reason: {e:?}"))?;
+    enum_header::save_enum_database(&enums, Path::new(enums_path))?;
+    let value_count = enums.iter().map(|e| e.values.len()).sum::<usize>();
+    println!("[INFO] Saved {} enum(s) with {value_count} value(s) to {enums_path:?}", enums.len());
+    Ok(())
+}
+
+fn export_schema(out_dir: &str, db: &LazyTypeDatabase) -> anyhow::Result<()> {
+    let db = db.get()?;
+    let schema_dir = Path::new(out_dir).join("schema");
+    let count = schema::save_json_schemas(db, &schema_dir)?;
+    println!("[INFO] Saved {count} struct schema(s) to {schema_dir:?}");
+
+    let output_path = Path::new(out_dir).join("rsz.d.ts");
+    fs::write(&output_path, schema::typescript(db))?;
+    println!("[INFO] Saved File {:?}", &output_path);
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn find_files_with_extension(base_dir: PathBuf, extension: &str) -> Vec<PathBuf> {
+    let mut results = Vec::new();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    paths.push(base_dir);
+    while let Some(dir) = paths.pop() {
+        if let Result::Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    paths.push(path);
+                } else {
+                    if let Some(x) = path.file_name().unwrap().to_str() {
+                        if x.ends_with(extension) {
+                            results.push(path);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    results
+}
+
+enum Processor<'p> {
+    Sequential(&'p dyn Fn(PathBuf, PathBuf) -> anyhow::Result<()>),
+    // number of jobs, files are handed out to threads but reported in list order
+    Parallel(usize, &'p (dyn Fn(PathBuf, PathBuf) -> anyhow::Result<()> + Sync)),
+}
+
+fn process_all(
+    root_dir: Option<String>,
+    out_dir: String,
+    list_file: String,
+    process: Processor,
+) -> anyhow::Result<()> {
+    let list = read_to_string(&list_file).expect(format!("Could not open file {list_file}").as_str());
+    let list: Vec<&str> = list.lines().collect();
+    let run = |file: &&str, process: &dyn Fn(PathBuf, PathBuf) -> anyhow::Result<()>| {
+        let start = Instant::now();
+        let result = match construct_paths(file.to_string(), root_dir.clone(), out_dir.clone(), true) {
+            Ok((file_path, output_path)) => process(file_path, output_path),
+            Err(e) => Err(anyhow!("Could not create file path {file} and output path {e}")),
+        };
+        (start.elapsed(), result)
+    };
+
+    let mut summary = BatchSummary::default();
+    let mut record = |file: &str, (elapsed, result)| {
+        let file_type = Path::new(file).file_name()
+            .and_then(|name| get_file_ext(name.to_string_lossy().to_string()).ok())
+            .map_or("unknown", |file_type| file_type.name());
+        summary.record(file, file_type, elapsed, result);
+    };
+    match process {
+        Processor::Sequential(process) => {
+            for file in &list {
+                record(file, run(file, process));
+            }
+        },
+        Processor::Parallel(jobs, process) => {
+            let mut files = list.iter();
+            batch::run_in_order(&list, jobs, |file| run(file, process), |outcome| record(files.next().unwrap(), outcome));
+        },
+    }
+    summary.print();
+    Ok(())
+}
+
+
+
+fn main() -> anyhow::Result<()> {
+    let now = SystemTime::now();
+    let args = Args::parse();
+    println!("{:#?}", args);
+
+    let languages = args.lang.iter().map(|code| msg::language_id(code)).collect::<anyhow::Result<Vec<_>>>()?;
+    let msg_text = match args.msg.is_empty() {
+        true => None,
+        false => {
+            let msg_text = load_msg_text(&args.msg, &languages)?;
+            println!("[INFO] Loaded {} msg entries", msg_text.len());
+            Some(msg_text)
+        }
+    };
+    let db = LazyTypeDatabase::new(&args.rsz_dump, &args.enums, !args.no_type_cache);
+    let options = DumpOptions {
+        object_refs: args.object_refs,
+        lenient: args.lenient,
+        msg_text: msg_text.as_ref(),
+        languages: &languages,
+    };
+    let loader = match (args.externs, &args.root_dir) {
+        (Some(mode), Some(root_dir)) => Some(UserLoader::new(root_dir, mode, options, &db)),
+        (Some(_), None) => return Err(anyhow!("--externs needs --root-dir to find the referenced files")),
+        (None, _) => None,
+    };
+    let report = RefCell::new(VerifyReport::default());
+    let query = match &args.command {
+        Some(Command::Query { query }) => Some(Query::parse(query)?),
+        _ => None,
+    };
+    let match_count = Cell::new(0);
+    let update_report = RefCell::new(UpdateReport::default());
+    let guid_index = match &args.command {
+        Some(Command::Index { index }) => RefCell::new(GuidIndex::load(Path::new(index))?),
+        _ => RefCell::new(GuidIndex::default()),
+    };
+    if let (Some(Command::Update { .. }), None) = (&args.command, &args.root_dir) {
+        return Err(anyhow!("update needs --root-dir for the new version"));
+    }
+    if args.jobs == 0 {
+        return Err(anyhow!("--jobs needs to be at least 1"));
+    }
+    if args.jobs > 1 && !matches!(args.command, None | Some(Command::Import)) {
+        return Err(anyhow!("--jobs only works when dumping or importing"));
+    }
+    // the loader shares the files it loaded between the files of the list
+    if args.jobs > 1 && loader.is_some() {
+        return Err(anyhow!("--jobs can't be used with --externs"));
+    }
+    let process_parallel = |file_path, output_path| match args.command {
+        None => dump_file(file_path, output_path, &db, None, options),
+        Some(Command::Import) => import_file(file_path, output_path, &db),
+        _ => unreachable!(),
+    };
+    let process = |file_path, output_path| match args.command {
+        None => dump_file(file_path, output_path, &db, loader.as_ref(), options)
+            .and_then(|()| save_referenced(loader.as_ref(), &args.out_dir)),
+        Some(Command::Import) => import_file(file_path, output_path, &db),
+        Some(Command::Verify) => verify_file(file_path, &report, &db),
+        Some(Command::Infer) => infer_file(file_path, output_path, &db),
+        Some(Command::Index { .. }) => index_file(file_path, args.root_dir.as_deref(), &guid_index, &db, loader.as_ref(), options),
+        Some(Command::Diff { .. } | Command::Schema | Command::Enums { .. } | Command::Lookup { .. }) => unreachable!(),
+        Some(Command::Update { ref old_root }) => {
+            let root_dir = args.root_dir.as_deref().unwrap();
+            update_file(file_path, root_dir, old_root, &update_report, &db, options)
+        },
+        Some(Command::Query { .. }) => {
+            let query = query.as_ref().unwrap();
+            let count = query_file(file_path, query, &db, loader.as_ref(), options)?;
+            match_count.set(match_count.get() + count);
+            Ok(())
+        },
+    };
+    
+    if let Some(Command::Diff { old, new, format }) = &args.command {
+        diff_files(Path::new(old), Path::new(new), *format, &args.out_dir, &db, loader.as_ref(), options)?;
+        println!("Time taken: {} ms", now.elapsed().unwrap().as_millis());
+        return Ok(());
+    }
+    if let Some(Command::Lookup { guid, index }) = &args.command {
+        return lookup_guid(guid, index);
+    }
+    if let Some(Command::Enums { header }) = &args.command {
+        build_enums(header, &args.enums)?;
+        println!("Time taken: {} ms", now.elapsed().unwrap().as_millis());
+        return Ok(());
+    }
+    if let Some(Command::Schema) = &args.command {
+        export_schema(&args.out_dir, &db)?;
+        println!("Time taken: {} ms", now.elapsed().unwrap().as_millis());
+        return Ok(());
+    }
+
+    match args.list {
+        Some(list) => {
+            let processor = match args.jobs {
+                1 => Processor::Sequential(&process),
+                jobs => Processor::Parallel(jobs, &process_parallel),
+            };
+            process_all(args.root_dir.clone(), args.out_dir.clone(), list, processor)?;
+        }, 
+        None => match args.file_name {
+            Some(file_name) => {
+                let (file_path, output_path) = construct_paths(file_name.clone(), args.root_dir.clone(), args.out_dir.clone(), false)?;
+                process(file_path, output_path)?;
+            },
+            None => println!("Must provide file name"),
+        }
+    }
+    match args.command {
+        Some(Command::Verify) => report.borrow().print(),
+        Some(Command::Query { .. }) => println!("[INFO] {} match(es)", match_count.get()),
+        Some(Command::Index { ref index }) => {
+            let guid_index = guid_index.borrow();
+            guid_index.save(Path::new(index))?;
+            println!("[INFO] Saved {} guid(s) to {index:?}", guid_index.guid_count());
+        },
+        Some(Command::Update { .. }) => {
+            let update_report = update_report.borrow();
+            update_report.print();
+            update_report.save_changed_list(&args.out_dir)?;
+        },
+        _ => (),
+    }
+    println!("Time taken: {} ms", now.elapsed().unwrap().as_millis());
+    Ok(())
+}
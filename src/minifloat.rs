@@ -0,0 +1,127 @@
+// Small float formats used by F8 (1 sign, 4 exponent, 3 mantissa bits) and F16 (ieee half) rsz fields.
+// Both are ieee style, with subnormals and an all ones exponent for inf/nan.
+
+pub const F8_FORMAT: (u32, u32) = (4, 3);
+pub const F16_FORMAT: (u32, u32) = (5, 10);
+
+pub fn decode((exp_bits, man_bits): (u32, u32), bits: u32) -> f32 {
+    let sign = if bits >> (exp_bits + man_bits) & 1 == 1 { -1.0 } else { 1.0 };
+    let max_exp = (1 << exp_bits) - 1;
+    let bias = (1 << (exp_bits - 1)) - 1;
+    let exp = (bits >> man_bits) & max_exp;
+    let man = (bits & ((1 << man_bits) - 1)) as f64;
+    let value = if exp == max_exp {
+        if man == 0.0 { f64::INFINITY } else { f64::NAN }
+    } else if exp == 0 {
+        man * 2f64.powi(1 - bias - man_bits as i32)
+    } else {
+        (1.0 + man / 2f64.powi(man_bits as i32)) * 2f64.powi(exp as i32 - bias)
+    };
+    (sign * value) as f32
+}
+
+// Rounds to the nearest representable value, ties to even
+pub fn encode((exp_bits, man_bits): (u32, u32), value: f32) -> u32 {
+    let sign = if value.is_sign_negative() { 1 << (exp_bits + man_bits) } else { 0 };
+    let max_exp = (1 << exp_bits) - 1;
+    if value.is_nan() {
+        return sign | (max_exp << man_bits) | (1 << (man_bits - 1));
+    }
+    if value.is_infinite() {
+        return sign | (max_exp << man_bits);
+    }
+    let bias = (1 << (exp_bits - 1)) - 1;
+    let min_exp = 1 - bias;
+    let one = 1u32 << man_bits;
+    let a = value.abs() as f64;
+
+    if a < 2f64.powi(min_exp) {
+        // subnormal, rounding up to `one` gives the smallest normal which has the same bits
+        let man = (a * 2f64.powi(man_bits as i32 - min_exp)).round_ties_even() as u32;
+        return sign | man;
+    }
+
+    let mut exp = a.log2().floor() as i32;
+    while 2f64.powi(exp) > a {
+        exp -= 1;
+    }
+    while 2f64.powi(exp + 1) <= a {
+        exp += 1;
+    }
+    let mut man = (a / 2f64.powi(exp) * one as f64).round_ties_even() as u32;
+    if man == one << 1 {
+        man = one;
+        exp += 1;
+    }
+    let biased = exp + bias;
+    if biased >= max_exp as i32 {
+        return sign | (max_exp << man_bits);
+    }
+    sign | ((biased as u32) << man_bits) | (man - one)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_exact(format: (u32, u32), cases: &[(u32, f32)]) {
+        for &(bits, value) in cases {
+            assert_eq!(decode(format, bits).to_bits(), value.to_bits(), "decode {bits:#x}");
+            assert_eq!(encode(format, value), bits, "encode {value}");
+        }
+    }
+
+    #[test]
+    fn f8() {
+        assert_exact(F8_FORMAT, &[
+            (0x00, 0.0),
+            (0x80, -0.0),
+            // subnormals
+            (0x01, 2f32.powi(-9)),
+            (0x07, 7.0 * 2f32.powi(-9)),
+            (0x08, 2f32.powi(-6)),
+            (0x38, 1.0),
+            (0xc0, -2.0),
+            (0x77, 240.0),
+            (0x78, f32::INFINITY),
+            (0xf8, f32::NEG_INFINITY),
+        ]);
+        assert!(decode(F8_FORMAT, 0x79).is_nan());
+        assert_eq!(encode(F8_FORMAT, f32::NAN), 0x7c);
+
+        // ties go to the even mantissa
+        assert_eq!(encode(F8_FORMAT, 1.0625), 0x38);
+        assert_eq!(encode(F8_FORMAT, 1.1875), 0x3a);
+        assert_eq!(encode(F8_FORMAT, 2f32.powi(-10)), 0x00);
+        assert_eq!(encode(F8_FORMAT, 3.0 * 2f32.powi(-10)), 0x02);
+        // the largest subnormal rounds up into the normals, the largest finite one into infinity
+        assert_eq!(encode(F8_FORMAT, 7.5 * 2f32.powi(-9)), 0x08);
+        assert_eq!(encode(F8_FORMAT, 248.0), 0x78);
+        assert_eq!(encode(F8_FORMAT, 1000.0), 0x78);
+    }
+
+    #[test]
+    fn f16() {
+        assert_exact(F16_FORMAT, &[
+            (0x0000, 0.0),
+            (0x8000, -0.0),
+            (0x0001, 2f32.powi(-24)),
+            (0x03ff, 1023.0 * 2f32.powi(-24)),
+            (0x0400, 2f32.powi(-14)),
+            (0x3c00, 1.0),
+            (0x3e00, 1.5),
+            (0xc000, -2.0),
+            (0x7bff, 65504.0),
+            (0x7c00, f32::INFINITY),
+            (0xfc00, f32::NEG_INFINITY),
+        ]);
+        assert!(decode(F16_FORMAT, 0x7c01).is_nan());
+        assert_eq!(encode(F16_FORMAT, f32::NAN), 0x7e00);
+
+        assert_eq!(encode(F16_FORMAT, 1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(encode(F16_FORMAT, 1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        assert_eq!(encode(F16_FORMAT, 2f32.powi(-25)), 0x0000);
+        assert_eq!(encode(F16_FORMAT, 3.0 * 2f32.powi(-25)), 0x0002);
+        assert_eq!(encode(F16_FORMAT, 65520.0), 0x7c00);
+    }
+}
@@ -1,60 +1,142 @@
 use core::str;
-use std::{collections::HashMap, io::{Error, ErrorKind, Result, Write}};
+use std::{borrow::Cow, collections::HashMap, io::{Error, ErrorKind, Result, Seek, SeekFrom, Write}};
 
-use anyhow::anyhow;
-use serde::Serialize;
-use serde_json::json;
+use anyhow::{anyhow, bail, Context};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 use uuid::Uuid;
 
-use crate::byte_reader::BytesFile;
+use crate::align::align_up;
+use crate::byte_reader::{map_file, BytesFile};
+use crate::diagnostic::ParseDiagnostic;
+use crate::file_ext::*;
+use crate::import::OrderedMap;
 
 const KEY: [u8; 16] = [207, 206, 251, 248, 236, 10, 51, 102, 147, 169, 29, 147, 80, 57, 95, 9];
 
+// codes of the language ids in via.Language, ids past the end are dumped as the number
+const LANGUAGES: [&str; 33] = [
+    "ja", "en", "fr", "it", "de", "es", "ru", "pl", "nl", "pt", "pt-BR", "ko", "zh-TW", "zh-CN", "fi", "sv",
+    "da", "no", "cs", "hu", "sk", "ar", "tr", "bg", "el", "ro", "th", "uk", "vi", "id", "fiction", "hi", "es-419",
+];
+const ENGLISH: u32 = 1;
+
+pub fn language_code(id: u32) -> Cow<'static, str> {
+    match LANGUAGES.get(id as usize) {
+        Some(code) => Cow::Borrowed(code),
+        None => Cow::Owned(id.to_string()),
+    }
+}
+
+pub fn language_id(code: &str) -> anyhow::Result<u32> {
+    match LANGUAGES.iter().position(|c| c.eq_ignore_ascii_case(code)) {
+        Some(id) => Ok(id as u32),
+        None => code.parse().map_err(|_| anyhow!("Unknown language {code}, expected one of {}", LANGUAGES.join(", "))),
+    }
+}
+
+// Text keyed by language code, the way msg content is written to json
+pub struct ByLanguage<'a>(Vec<(u32, &'a str)>);
+
+impl Serialize for ByLanguage<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(id, text)| (language_code(*id), text)))
+    }
+}
+
+// attribute types from the header, each entry has one 8 byte value per attribute
+const ATTRIBUTE_INT: i32 = 0;
+const ATTRIBUTE_FLOAT: i32 = 1;
+const ATTRIBUTE_STRING: i32 = 2;
+// also an offset to a string, seems to be the same as a string
+const ATTRIBUTE_STRING_ALT: i32 = -1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    name: String,
+    r#type: i32,
+}
+
 #[derive(Debug)]
-#[allow(unused)]
 struct Entry {
-    unkn: u32,
     guid: [u8; 16],
-    hash: u32,
     name: String,
-    attributes: u64,
+    hash: u32,
+    unkn: u32,
+    attributes: Vec<AttributeValue>,
+    // one per language of the file
     content: Vec<String>,
 }
 
+// An entry as it is in the json, the guid is its key
+#[derive(Serialize, Deserialize)]
+struct EntryJson<C> {
+    name: String,
+    hash: u32,
+    unkn: u32,
+    attributes: Vec<AttributeValue>,
+    content: C,
+}
+
 #[derive(Debug, Default)]
 pub struct Msg {
+    version: u32,
+    languages: Vec<u32>,
+    attributes: Vec<Attribute>,
     entries: Vec<Entry>,
 }
 
-#[derive(Default)]
-#[allow(unused)]
-pub struct MsgHeader {
-    version: u32,
-    magic: [u8; 4],
-    header_offset: u64,
-    entry_count: u32,
-    type_count: u32,
-    lang_count: u32,
-    data1_offset: u64,
-    p_offset: u64,
-    lang_offset: u64,
-    type_offset: u64,
-    type_name_offset: u64
+// strings are stored as offsets into the decrypted data
+fn read_string(data: &mut BytesFile<'_>, offset: u64, data_offset: u64) -> Result<String> {
+    let from = offset.checked_sub(data_offset).ok_or_else(|| {
+        Error::new(ErrorKind::InvalidData, format!("String offset {offset:#x} is before the data at {data_offset:#x}"))
+    })?;
+    data.read_utf16(from as usize)
+}
+
+// a bad entry or string doesn't stop the rest of the file from being read, `at` is where its offset is
+fn warn(file: &BytesFile<'_>, at: usize, what: &str, e: impl std::fmt::Display) {
+    eprintln!("[WARN] {what}: {e}\n{}", ParseDiagnostic::from_bytes("GMSG", &file.data, at as u64));
+}
+
+// every byte is xored with the key and the encrypted byte before it
+fn encrypt(data: &mut [u8]) {
+    let mut prev = 0;
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= prev ^ KEY[i & 0xf];
+        prev = *b;
+    }
 }
 
 impl Msg {
-    pub fn new(file_name: String) -> Result<Msg> {
-        let mut file = BytesFile::new(file_name)?;
-        let _version = file.read::<u32>()?;
+    pub fn new(file_name: String) -> anyhow::Result<Msg> {
+        Self::from_bytes(&map_file(file_name)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Msg> {
+        let mut file = BytesFile::new(data);
+        Self::read(&mut file)
+            .map_err(|e| ParseDiagnostic::from_bytes("GMSG", &file.data, file.index as u64).attach(e))
+    }
+
+    fn read(file: &mut BytesFile<'_>) -> anyhow::Result<Msg> {
+        let version = file.read::<u32>()?;
         let magic = file.readn::<u8, 4>()?;
-        let magic = str::from_utf8(&magic).unwrap();
-        if magic != "GMSG" {
-            return Err(Error::new(ErrorKind::Other, format!("Invalid Magic {magic}, {_version}")))
+        if &magic != b"GMSG" {
+            bail!("Wrong magic for GMSG file {:?}, version {version}", String::from_utf8_lossy(&magic));
         }
 
         let _header_offset = file.read::<u64>()?;
         let entry_count = file.read::<u32>()?;
-        let _type_count = file.read::<u32>()?;
+        let type_count = file.read::<u32>()?;
         let lang_count = file.read::<u32>()?;
         file.read::<u32>()?; // null
         let data_offset = file.read::<u64>()?;
@@ -80,24 +162,30 @@ impl Msg {
             num2 = num;
         }
 
-        let mut data = BytesFile {
-            data,
-            index: 0,
-        };
+        let mut data = BytesFile::new(data);
         
         // PUT A CHECK HERE FOR IF ITS A VALID FILE OR NOT
 
         file.index = lang_offset as usize;
-        let _languages = (0..lang_count).map(|_| file.read::<u32>()).collect::<Result<Vec<_>>>()?;
+        let languages = (0..lang_count).map(|_| file.read::<u32>()).collect::<Result<Vec<_>>>()?;
 
         file.index = p_offset as usize;
         file.read::<u64>()?; // idk what this does
 
-        file.index += type_offset as usize;
-        //let attribute_types = (0..type_count).map(|_| file.read::<u32>().unwrap_or(0) as i32).collect::<Vec<i32>>();
-        file.index += type_name_offset as usize;
-        //let type_names = (0..type_count).map(|_| file.read::<u32>().unwrap_or(0) as i32).collect::<Vec<i32>>();
-
+        file.index = type_offset as usize;
+        let attribute_types = (0..type_count).map(|_| file.read::<u32>().map(|t| t as i32)).collect::<Result<Vec<_>>>()?;
+        file.index = type_name_offset as usize;
+        let name_offsets = (0..type_count).map(|_| file.read::<u64>()).collect::<Result<Vec<_>>>()?;
+        let attributes = attribute_types.into_iter().zip(name_offsets).enumerate().map(|(i, (r#type, name))| {
+            let name = read_string(&mut data, name, data_offset).unwrap_or_else(|e| {
+                warn(file, type_name_offset as usize + i * 8, &format!("Could not read the name of attribute {i}"), e);
+                String::new()
+            });
+            if !matches!(r#type, ATTRIBUTE_INT | ATTRIBUTE_FLOAT | ATTRIBUTE_STRING | ATTRIBUTE_STRING_ALT) {
+                eprintln!("[WARN] Unknown type {type} of attribute {name}, its values are kept as numbers");
+            }
+            Attribute { name, r#type }
+        }).collect::<Vec<_>>();
 
         let mut entries: Vec<Entry> = Vec::new();
         for i in 0..entry_count as usize {
@@ -105,46 +193,453 @@ impl Msg {
             let entry_offset = file.read::<u64>()?;
             file.index = entry_offset as usize;
 
-            let guid_vec = file.read_bytes_to_vec(16)?;
-            let mut guid: [u8; 16] = [0; 16];
-            for i in 0..16 {
-                guid[i] = guid_vec[i];
-            }
-
-            let unkn = file.read::<u32>()?;
-            let hash = file.read::<u32>()?;
-            let name = file.read::<u64>()?;
-            let attributes = file.read::<u64>()?;
-            let content = (0..lang_count).map(|_| {
-                let offset = file.read::<u64>().unwrap_or(0) as usize;
-                data.read_utf16(offset - data_offset as usize).unwrap_or("".to_string())
-            }).collect::<Vec<_>>();
-            let name = data.read_utf16(name as usize - data_offset as usize)?;
+            // entries whose values can't be read are left out, strings that can't be read are left empty
+            let values = (|| -> Result<_> {
+                let guid = file.readn::<u8, 16>()?;
+                let unkn = file.read::<u32>()?;
+                let hash = file.read::<u32>()?;
+                let name = file.read::<u64>()?;
+                let attribute_offset = file.read::<u64>()?;
+                let content = (0..lang_count).map(|_| file.read::<u64>()).collect::<Result<Vec<_>>>()?;
+                file.index = attribute_offset as usize;
+                let attribute_values = (0..type_count).map(|_| file.read::<u64>()).collect::<Result<Vec<_>>>()?;
+                Ok((guid, unkn, hash, name, attribute_offset, content, attribute_values))
+            })();
+            let (guid, unkn, hash, name, attribute_offset, content, attribute_values) = match values {
+                Ok(values) => values,
+                Err(e) => {
+                    warn(file, file.index, &format!("Skipped entry {i} at {entry_offset:#x}"), e);
+                    continue;
+                }
+            };
+            let mut string = |offset: u64, at: u64, what: &dyn Fn() -> String| {
+                read_string(&mut data, offset, data_offset).unwrap_or_else(|e| {
+                    warn(file, at as usize, &what(), e);
+                    String::new()
+                })
+            };
+            let name = string(name, entry_offset + 24, &|| format!("Could not read the name of entry {i}"));
+            let content = content.into_iter().zip(&languages).enumerate().map(|(j, (offset, id))| {
+                string(offset, entry_offset + 40 + j as u64 * 8, &|| format!("Could not read the {} text of {name}", language_code(*id)))
+            }).collect();
+            let attributes = attribute_values.into_iter().zip(&attributes).enumerate().map(|(j, (value, attribute))| {
+                match attribute.r#type {
+                    ATTRIBUTE_FLOAT => AttributeValue::Float(f64::from_bits(value)),
+                    ATTRIBUTE_STRING | ATTRIBUTE_STRING_ALT => AttributeValue::String(string(
+                        value,
+                        attribute_offset + j as u64 * 8,
+                        &|| format!("Could not read attribute {} of {name}", attribute.name),
+                    )),
+                    _ => AttributeValue::Int(value as i64),
+                }
+            }).collect();
             entries.push(Entry { name, guid, unkn, hash, attributes, content });
 
         }
         Ok(Msg {
+            version,
+            languages,
+            attributes,
             entries
         })
     }
 
+    // Rebuilds a msg from its json dump, the header values come from the "$" keys
+    pub fn import_json(json: &str) -> anyhow::Result<Msg> {
+        let OrderedMap(values) = serde_json::from_str(json)?;
+        let mut version = None;
+        let mut languages = None;
+        let mut attributes = vec![];
+        let mut entries = vec![];
+        for (key, value) in values {
+            match key.as_str() {
+                "$version" => version = Some(serde_json::from_value(value).context("Invalid $version")?),
+                "$languages" => languages = Some(serde_json::from_value::<Vec<u32>>(value).context("Invalid $languages")?),
+                "$attributes" => attributes = serde_json::from_value::<Vec<Attribute>>(value).context("Invalid $attributes")?,
+                "entries" => {
+                    let OrderedMap(values) = serde_json::from_value(value).context("Invalid entries")?;
+                    for (key, value) in values {
+                        let guid = Uuid::parse_str(&key).with_context(|| format!("Invalid entry guid {key}"))?;
+                        let entry = serde_json::from_value::<EntryJson<HashMap<String, String>>>(value)
+                            .with_context(|| format!("Invalid entry {key}"))?;
+                        entries.push((guid, entry));
+                    }
+                }
+                _ => bail!("Unknown key {key} in the msg dump, the entries go under \"entries\""),
+            }
+        }
+        let version = version.context("Missing $version, the json is not a msg dump")?;
+        let languages = languages.context("Missing $languages, the json is not a msg dump")?;
+
+        let mut entries = entries.into_iter().map(|(guid, mut entry)| {
+            let content = languages.iter().map(|id| {
+                let code = language_code(*id);
+                entry.content.remove(code.as_ref()).with_context(|| format!("Entry {} has no text in {code}", entry.name))
+            }).collect::<anyhow::Result<Vec<_>>>()?;
+            if let Some(code) = entry.content.keys().next() {
+                bail!("Entry {} has text in {code}, which is not in $languages", entry.name);
+            }
+            Ok(Entry {
+                guid: guid.to_bytes_le(),
+                name: entry.name,
+                hash: entry.hash,
+                unkn: entry.unkn,
+                attributes: entry.attributes,
+                content,
+            })
+        }).collect::<anyhow::Result<Vec<_>>>()?;
+
+        for entry in &mut entries {
+            if entry.attributes.len() != attributes.len() {
+                bail!("Entry {} has {} attributes, expected {}", entry.name, entry.attributes.len(), attributes.len());
+            }
+            // floats that happen to be whole numbers can be written without the .0
+            for (value, attribute) in entry.attributes.iter_mut().zip(&attributes) {
+                *value = match (attribute.r#type, &*value) {
+                    (ATTRIBUTE_INT, AttributeValue::Int(_)) => value.clone(),
+                    (ATTRIBUTE_FLOAT, AttributeValue::Float(_)) => value.clone(),
+                    (ATTRIBUTE_FLOAT, AttributeValue::Int(v)) => AttributeValue::Float(*v as f64),
+                    (ATTRIBUTE_STRING | ATTRIBUTE_STRING_ALT, AttributeValue::String(_)) => value.clone(),
+                    // unknown types are read as numbers
+                    (t, AttributeValue::Int(_)) if !matches!(t, ATTRIBUTE_FLOAT | ATTRIBUTE_STRING | ATTRIBUTE_STRING_ALT) => value.clone(),
+                    (t, value) => bail!("Entry {} attribute {}: expected type {t}, got {value:?}", entry.name, attribute.name),
+                };
+            }
+        }
+        Ok(Msg { version, languages, attributes, entries })
+    }
+
+    pub fn write<F: Write + Seek>(&self, mut file: F) -> anyhow::Result<()> {
+        let lang_count = self.languages.len() as u64;
+        let type_count = self.attributes.len() as u64;
+        let entry_list_offset = 0x48;
+        let p_offset = entry_list_offset + self.entries.len() as u64 * 8;
+        let lang_offset = p_offset + 8;
+        let type_offset = align_up(lang_offset + lang_count * 4, 8);
+        let type_name_offset = align_up(type_offset + type_count * 4, 8);
+        let entry_offset = type_name_offset + type_count * 8;
+        let entry_size = 40 + lang_count * 8;
+        let attribute_offset = entry_offset + self.entries.len() as u64 * entry_size;
+        let data_offset = attribute_offset + self.entries.len() as u64 * type_count * 8;
+
+        // the same string is only stored once
+        let mut data = vec![];
+        let mut strings = HashMap::new();
+        let mut string_offset = |s: &str| -> anyhow::Result<u64> {
+            if let Some(offset) = strings.get(s) {
+                return Ok(*offset);
+            }
+            let offset = data_offset + data.len() as u64;
+            data.write_u16str(s)?;
+            strings.insert(s.to_string(), offset);
+            Ok(offset)
+        };
+        let attribute_names = self.attributes.iter()
+            .map(|attribute| string_offset(&attribute.name))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut entry_strings = vec![];
+        for entry in &self.entries {
+            let name = string_offset(&entry.name)?;
+            let content = entry.content.iter().map(|s| string_offset(s)).collect::<anyhow::Result<Vec<_>>>()?;
+            let attributes = entry.attributes.iter().map(|value| Ok(match value {
+                AttributeValue::Int(v) => *v as u64,
+                AttributeValue::Float(v) => v.to_bits(),
+                AttributeValue::String(s) => string_offset(s)?,
+            })).collect::<anyhow::Result<Vec<_>>>()?;
+            entry_strings.push((name, content, attributes));
+        }
+        encrypt(&mut data);
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_u32(self.version)?;
+        file.write_magic(b"GMSG")?;
+        file.write_u64(0x10)?;
+        file.write_u32(self.entries.len().try_into()?)?;
+        file.write_u32(type_count.try_into()?)?;
+        file.write_u32(lang_count.try_into()?)?;
+        file.write_u32(0)?;
+        file.write_u64(data_offset)?;
+        file.write_u64(p_offset)?;
+        file.write_u64(lang_offset)?;
+        file.write_u64(type_offset)?;
+        file.write_u64(type_name_offset)?;
+
+        file.write_pad_to(entry_list_offset)?;
+        for i in 0..self.entries.len() as u64 {
+            file.write_u64(entry_offset + i * entry_size)?;
+        }
+        file.write_u64(0)?;
+
+        for language in &self.languages {
+            file.write_u32(*language)?;
+        }
+        file.write_pad_to(type_offset)?;
+        for attribute in &self.attributes {
+            file.write_i32(attribute.r#type)?;
+        }
+        file.write_pad_to(type_name_offset)?;
+        for offset in attribute_names {
+            file.write_u64(offset)?;
+        }
+
+        for (i, (entry, (name, content, _))) in self.entries.iter().zip(&entry_strings).enumerate() {
+            file.write_all(&entry.guid)?;
+            file.write_u32(entry.unkn)?;
+            file.write_u32(entry.hash)?;
+            file.write_u64(*name)?;
+            file.write_u64(attribute_offset + i as u64 * type_count * 8)?;
+            for offset in content {
+                file.write_u64(*offset)?;
+            }
+        }
+        for (_, _, attributes) in &entry_strings {
+            for value in attributes {
+                file.write_u64(*value)?;
+            }
+        }
+
+        file.write_pad_to(data_offset)?;
+        file.write_all(&data)?;
+        Ok(())
+    }
+
+    // keeps the content in the given languages, all of them when there are none
+    pub fn retain_languages(&mut self, languages: &[u32]) {
+        if languages.is_empty() {
+            return;
+        }
+        let keep = self.languages.iter().map(|id| languages.contains(id)).collect::<Vec<_>>();
+        for entry in &mut self.entries {
+            let mut keep = keep.iter();
+            entry.content.retain(|_| *keep.next().unwrap());
+        }
+        self.languages.retain(|id| languages.contains(id));
+    }
+
+    pub fn entry_names(&self) -> impl Iterator<Item = (Uuid, &str)> {
+        self.entries.iter().map(|entry| (Uuid::from_bytes_le(entry.guid), entry.name.as_str()))
+    }
+
     pub fn save(&self, writer: &mut dyn Write) {
-        #[derive(Serialize)]
-        struct EntryInfo {
-            name: String,
-            hash: u32,
-            content: Vec<String>
-        }
-        let map: HashMap<_, _> = self.entries.iter()
-            .map(|entry| {
-                let uuid = Uuid::from_bytes_le(entry.guid).to_string();
-                ( uuid, EntryInfo {
-                    name: entry.name.clone(),
-                    hash: entry.hash,
-                    content: entry.content.clone(),
-                })
-            }).collect();
-        let json_map = json!(map);
-        serde_json::to_writer_pretty(writer, &json_map).unwrap();
+        serde_json::to_writer_pretty(writer, self).unwrap();
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap()
+    }
+}
+
+// The header values needed to write the file back, then the entries keyed by guid in file order
+impl Serialize for Msg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(4))?;
+        map.serialize_entry("$version", &self.version)?;
+        map.serialize_entry("$languages", &self.languages)?;
+        map.serialize_entry("$attributes", &self.attributes)?;
+        map.serialize_entry("entries", &EntriesJson(self))?;
+        map.end()
+    }
+}
+
+struct EntriesJson<'a>(&'a Msg);
+
+impl Serialize for EntriesJson<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let Msg { languages, entries, .. } = self.0;
+        serializer.collect_map(entries.iter().map(|entry| {
+            let content = ByLanguage(languages.iter().copied().zip(entry.content.iter().map(String::as_str)).collect());
+            let entry_json = EntryJson {
+                name: entry.name.clone(),
+                hash: entry.hash,
+                unkn: entry.unkn,
+                attributes: entry.attributes.clone(),
+                content,
+            };
+            (Uuid::from_bytes_le(entry.guid).to_string(), entry_json)
+        }))
+    }
+}
+
+// The entries of a set of msg files by guid, to dump their text next to guid fields in user files
+#[derive(Debug, Default)]
+pub struct MsgText {
+    // entry name and its text in the chosen languages
+    entries: HashMap<Uuid, (String, Vec<(u32, String)>)>,
+    languages: Vec<u32>,
+}
+
+impl MsgText {
+    // english when no languages are given
+    pub fn new(languages: &[u32]) -> MsgText {
+        let languages = match languages.is_empty() {
+            true => vec![ENGLISH],
+            false => languages.to_vec(),
+        };
+        MsgText { entries: HashMap::new(), languages }
+    }
+
+    // entries already added from another file are kept
+    pub fn add(&mut self, msg: Msg) -> usize {
+        let count = self.entries.len();
+        for entry in msg.entries {
+            let text = msg.languages.iter().copied().zip(entry.content)
+                .filter(|(id, _)| self.languages.contains(id))
+                .collect();
+            self.entries.entry(Uuid::from_bytes_le(entry.guid)).or_insert((entry.name, text));
+        }
+        self.entries.len() - count
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // entry name and text in the chosen languages
+    pub fn get(&self, guid: &Uuid) -> Option<(&str, ByLanguage<'_>)> {
+        let (name, text) = self.entries.get(guid)?;
+        Some((name.as_str(), ByLanguage(text.iter().map(|(id, text)| (*id, text.as_str())).collect())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn import_round_trip() {
+        let attributes = vec![
+            Attribute { name: "Count".to_string(), r#type: ATTRIBUTE_INT },
+            Attribute { name: "Scale".to_string(), r#type: ATTRIBUTE_FLOAT },
+            Attribute { name: "Label".to_string(), r#type: ATTRIBUTE_STRING },
+            Attribute { name: "Other".to_string(), r#type: ATTRIBUTE_STRING_ALT },
+        ];
+        let entry = |name: &str, n: u8, content: [&str; 2]| Entry {
+            name: name.to_string(),
+            guid: [n; 16],
+            unkn: n as u32,
+            hash: 0x1000 + n as u32,
+            attributes: vec![
+                AttributeValue::Int(-(n as i64)),
+                AttributeValue::Float(n as f64 * 0.5),
+                AttributeValue::String(format!("label {n}")),
+                AttributeValue::String("shared".to_string()),
+            ],
+            content: content.map(str::to_string).to_vec(),
+        };
+        let msg = Msg {
+            version: 0x5c,
+            languages: vec![0, ENGLISH],
+            attributes,
+            entries: vec![
+                entry("ITEM_000", 1, ["\u{30a2}\u{30a4}\u{30c6}\u{30e0}", "Item"]),
+                entry("ITEM_001", 2, ["", "Second\r\nline"]),
+            ],
+        };
+        let json = msg.to_json();
+
+        let imported = Msg::import_json(&json.to_string()).unwrap();
+        let mut bytes = Cursor::new(vec![]);
+        imported.write(&mut bytes).unwrap();
+        let read = Msg::from_bytes(bytes.get_ref()).unwrap();
+        assert_eq!(read.to_json(), json);
+
+        let mut rewritten = Cursor::new(vec![]);
+        read.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten.get_ref(), bytes.get_ref());
+    }
+
+    #[test]
+    fn bad_offsets_skip_the_entry_or_string() {
+        let mut msg = text_msg(vec![0, ENGLISH], &[(1, "A"), (2, "B"), (3, "C")]);
+        msg.attributes.push(Attribute { name: "Count".to_string(), r#type: ATTRIBUTE_INT });
+        for (n, entry) in msg.entries.iter_mut().enumerate() {
+            entry.attributes.push(AttributeValue::Int(n as i64));
+        }
+        let mut bytes = Cursor::new(vec![]);
+        msg.write(&mut bytes).unwrap();
+        let mut bytes = bytes.into_inner();
+        let u64_at = |bytes: &[u8], at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        let entry_offset = |bytes: &[u8], i: usize| u64_at(bytes, 0x48 + i * 8);
+
+        // english text of A before the data, B pointing past the end of the file, an unknown attribute type
+        let a = entry_offset(&bytes, 0);
+        bytes[a + 48..a + 56].copy_from_slice(&0u64.to_le_bytes());
+        bytes[0x48 + 8..0x48 + 16].copy_from_slice(&0xffff_0000u64.to_le_bytes());
+        let type_offset = u64_at(&bytes, 0x38);
+        bytes[type_offset..type_offset + 4].copy_from_slice(&7i32.to_le_bytes());
+
+        let read = Msg::from_bytes(&bytes).unwrap();
+        let json = read.to_json();
+        let entries = &json["entries"];
+        let a = &entries[Uuid::from_bytes_le([1; 16]).to_string()];
+        assert_eq!(a["content"], serde_json::json!({ "ja": "A ja", "en": "" }));
+        assert!(entries.get(Uuid::from_bytes_le([2; 16]).to_string()).is_none());
+        let c = &entries[Uuid::from_bytes_le([3; 16]).to_string()];
+        assert_eq!(c["content"], serde_json::json!({ "ja": "C ja", "en": "C en" }));
+        assert_eq!(c["attributes"], serde_json::json!([2]));
+        // unknown attribute types still import
+        let imported = Msg::import_json(&json.to_string()).unwrap();
+        assert_eq!(imported.attributes[0].r#type, 7);
+        assert_eq!(imported.entries.len(), 2);
+
+        // the header still has to be there
+        assert!(Msg::from_bytes(&bytes[..0x30]).is_err());
+    }
+
+    #[test]
+    fn retain_languages() {
+        let languages = ["en", "JA", "40"].map(|code| language_id(code).unwrap());
+        assert_eq!(languages, [ENGLISH, 0, 40]);
+        assert!(language_id("xx").is_err());
+
+        let mut msg = text_msg(vec![0, ENGLISH, 2, 40], &[(1, "A")]);
+        msg.retain_languages(&languages);
+        let json = msg.to_json();
+        assert_eq!(json["$languages"], serde_json::json!([0, 1, 40]));
+        let content = &json["entries"][Uuid::from_bytes_le([1; 16]).to_string()]["content"];
+        assert_eq!(content, &serde_json::json!({ "ja": "A ja", "en": "A en", "40": "A 40" }));
+        // the file is written back with only those languages
+        let mut bytes = Cursor::new(vec![]);
+        Msg::import_json(&json.to_string()).unwrap().write(&mut bytes).unwrap();
+        assert_eq!(Msg::from_bytes(bytes.get_ref()).unwrap().to_json(), json);
+
+        // no languages keeps all of them
+        let mut msg = text_msg(vec![0, ENGLISH], &[(1, "A")]);
+        msg.retain_languages(&[]);
+        assert_eq!(msg.languages, [0, ENGLISH]);
+        assert_eq!(msg.entries[0].content, ["A ja", "A en"]);
+    }
+
+    fn text_msg(languages: Vec<u32>, entries: &[(u8, &str)]) -> Msg {
+        let entries = entries.iter().map(|&(n, name)| Entry {
+            name: name.to_string(),
+            guid: [n; 16],
+            unkn: 0,
+            hash: 0,
+            attributes: vec![],
+            content: languages.iter().map(|id| format!("{name} {}", language_code(*id))).collect(),
+        }).collect();
+        Msg { version: 0x5c, languages, attributes: vec![], entries }
+    }
+
+    #[test]
+    fn msg_text_keeps_the_first_entry() {
+        let text = |msg_text: &MsgText, n: u8| msg_text.get(&Uuid::from_bytes_le([n; 16]))
+            .map(|(name, text)| (name.to_string(), serde_json::to_value(text).unwrap()));
+
+        let mut msg_text = MsgText::new(&[]);
+        assert_eq!(msg_text.add(text_msg(vec![0, ENGLISH], &[(1, "A"), (2, "B")])), 2);
+        assert_eq!(msg_text.add(text_msg(vec![0, ENGLISH], &[(1, "C"), (3, "D")])), 1);
+        assert_eq!(msg_text.len(), 3);
+        assert_eq!(text(&msg_text, 1), Some(("A".to_string(), serde_json::json!({ "en": "A en" }))));
+        assert_eq!(text(&msg_text, 3), Some(("D".to_string(), serde_json::json!({ "en": "D en" }))));
+        assert_eq!(text(&msg_text, 4), None);
+
+        // languages missing from a file are left out
+        let mut msg_text = MsgText::new(&[0, 2]);
+        msg_text.add(text_msg(vec![ENGLISH, 2], &[(1, "A")]));
+        assert_eq!(text(&msg_text, 1), Some(("A".to_string(), serde_json::json!({ "fr": "A fr" }))));
     }
 }
@@ -0,0 +1,532 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::dersz::{DeRsz, RszType, RszTypeWithInfo, RszValue, RszValueWithInfo};
+use crate::minifloat::{self, F16_FORMAT, F8_FORMAT};
+use crate::rsz::int_from_rsz;
+
+// Selects values from deserialized user files.
+//
+// app.user_data.SkillCommonData._Values[*]._SkillId
+// app.Root._Values[?_Rare == 8]._Item
+// *._Values[?_Rare >= RARE_2 && _Flag == true]
+// app.Root._Values[3].*
+//
+// The root is a root struct name (or * for any), followed by fields (or * for all fields),
+// array indices, [*] for every element and [?...] filters comparing a relative path to a value.
+// Enums compare against their number or their name.
+#[derive(Debug)]
+pub struct Query {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug)]
+enum Token {
+    Name(String),
+    Step(Step),
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Field(String),
+    AnyField,
+    Index(usize),
+    AnyIndex,
+    Filter(Vec<Condition>),
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    path: Vec<Step>,
+    op: Op,
+    value: Literal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Value(&'a RszValue<'a>, &'a DeRsz<'a>),
+    Type(&'a RszType<'a>, &'a DeRsz<'a>),
+}
+
+// What a leaf compares as, enums have both a number and a name
+#[derive(Debug, Default)]
+struct Scalar {
+    number: Option<f64>,
+    text: Option<String>,
+    bool: Option<bool>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query> {
+        let tokens = parse_path(query).with_context(|| format!("Invalid query {query:?}"))?;
+        if !matches!(tokens.first(), Some(Token::Name(_))) {
+            bail!("Query {query:?} should start with a root struct name or *")
+        }
+        Ok(Query { tokens })
+    }
+
+    // (path, json value) of everything the query selects
+    pub fn run(&self, rsz: &DeRsz) -> Result<Vec<(String, Value)>> {
+        let mut results = vec![];
+        for root in rsz.root_values() {
+            let Some(steps) = self.steps_for_root(root.name(rsz.db)) else {
+                continue;
+            };
+            let mut nodes = vec![(root.name(rsz.db).to_string(), Node::Value(root, rsz))];
+            for step in &steps {
+                nodes = nodes.into_iter()
+                    .flat_map(|(path, node)| apply_step(step, node, &path))
+                    .collect();
+            }
+            for (path, node) in nodes {
+                let value = match node {
+                    Node::Value(value, rsz) => serde_json::to_value(RszValueWithInfo(value, rsz, None)),
+                    Node::Type(r#type, rsz) => serde_json::to_value(RszTypeWithInfo(r#type, rsz)),
+                }.with_context(|| format!("Could not serialize {path}"))?;
+                results.push((path, value));
+            }
+        }
+        Ok(results)
+    }
+
+    // Root names have dots in them, so the leading names are matched against the whole root name
+    fn steps_for_root(&self, root_name: &str) -> Option<Vec<Step>> {
+        let name_count = match &self.tokens[0] {
+            Token::Name(name) if name == "*" => 1,
+            _ => root_name.split('.').count(),
+        };
+        let names = self.tokens.iter()
+            .take(name_count)
+            .map(|token| match token {
+                Token::Name(name) => Some(name.as_str()),
+                Token::Step(_) => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if names.len() != name_count || (names != ["*"] && names.join(".") != root_name) {
+            return None;
+        }
+        Some(self.tokens[name_count..].iter()
+            .map(|token| match token {
+                Token::Name(name) if name == "*" => Step::AnyField,
+                Token::Name(name) => Step::Field(name.clone()),
+                Token::Step(step) => step.clone(),
+            })
+            .collect())
+    }
+}
+
+// Names before the first bracket stay names until the root they belong to is known
+fn parse_path(path: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = path.char_indices().peekable();
+    let mut seen_bracket = false;
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+            }
+            '[' => {
+                seen_bracket = true;
+                let mut depth = 0;
+                let mut in_string = false;
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '"' => in_string = !in_string,
+                        '[' if !in_string => depth += 1,
+                        ']' if !in_string => {
+                            depth -= 1;
+                            if depth == 0 {
+                                end = Some(i);
+                                break;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                let end = end.with_context(|| format!("Unclosed [ at {start}"))?;
+                tokens.push(Token::Step(parse_bracket(path[start + 1..end].trim())?));
+            }
+            _ => {
+                let mut end = path.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+                let name = path[start..end].trim().to_string();
+                if name.is_empty() {
+                    bail!("Empty name at {start}")
+                }
+                tokens.push(match (seen_bracket, name.as_str()) {
+                    (false, _) => Token::Name(name),
+                    (true, "*") => Token::Step(Step::AnyField),
+                    (true, _) => Token::Step(Step::Field(name)),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_bracket(content: &str) -> Result<Step> {
+    if content == "*" {
+        return Ok(Step::AnyIndex);
+    }
+    if let Some(conditions) = content.strip_prefix('?') {
+        let conditions = split_conditions(conditions)
+            .into_iter()
+            .map(parse_condition)
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Step::Filter(conditions));
+    }
+    let index = content.parse().with_context(|| format!("Expected index, * or ?filter, got [{content}]"))?;
+    Ok(Step::Index(index))
+}
+
+// Characters outside of "string literals" and nested [brackets], the only places && and comparisons can be
+fn top_level(s: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut depth = 0;
+    let mut in_string = false;
+    s.char_indices().filter(move |&(_, c)| match c {
+        '"' => {
+            in_string = !in_string;
+            false
+        }
+        '[' if !in_string => {
+            depth += 1;
+            false
+        }
+        ']' if !in_string => {
+            depth -= 1;
+            false
+        }
+        _ => !in_string && depth == 0,
+    })
+}
+
+fn split_conditions(conditions: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut last_amp = None;
+    for (i, c) in top_level(conditions) {
+        if c == '&' && last_amp == Some(i - 1) {
+            parts.push(&conditions[start..i - 1]);
+            start = i + 1;
+            last_amp = None;
+        } else {
+            last_amp = (c == '&').then_some(i);
+        }
+    }
+    parts.push(&conditions[start..]);
+    parts
+}
+
+fn parse_condition(condition: &str) -> Result<Condition> {
+    let (op_start, _) = top_level(condition)
+        .find(|(_, c)| matches!(c, '=' | '!' | '<' | '>'))
+        .with_context(|| format!("No comparison in filter {condition:?}"))?;
+    let (path, rest) = condition.split_at(op_start);
+    let (op, value) = [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)]
+        .into_iter()
+        .find_map(|(token, op)| rest.strip_prefix(token).map(|value| (op, value.trim())))
+        .with_context(|| format!("Unknown comparison in filter {condition:?}"))?;
+
+    let path = parse_path(path.trim())?
+        .into_iter()
+        .map(|token| match token {
+            Token::Name(name) if name == "*" => Step::AnyField,
+            Token::Name(name) => Step::Field(name),
+            Token::Step(step) => step,
+        })
+        .collect();
+    let value = if let Some(s) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Literal::String(s.to_string())
+    } else if let Ok(b) = value.parse() {
+        Literal::Bool(b)
+    } else if let Ok(n) = value.parse() {
+        Literal::Number(n)
+    } else if value.is_empty() {
+        bail!("No value in filter {condition:?}")
+    } else {
+        // bare words are enum names
+        Literal::String(value.to_string())
+    };
+    Ok(Condition { path, op, value })
+}
+
+// Follows object pointers so steps always see the instance
+fn resolve(node: Node) -> Node {
+    match node {
+        Node::Type(RszType::Object(_, ptr), rsz) => {
+            if rsz.externs.contains_key(ptr) {
+                match rsz.inline_externs.get(ptr).and_then(|ext| Some((ext.root_values().next()?, ext))) {
+                    Some((root, ext)) => Node::Value(root, ext),
+                    None => node,
+                }
+            } else {
+                match rsz.structs.get(*ptr as usize) {
+                    Some(value) => Node::Value(value, rsz),
+                    None => node,
+                }
+            }
+        }
+        Node::Type(RszType::Struct(value), rsz) => Node::Value(value, rsz),
+        _ => node,
+    }
+}
+
+fn apply_step<'a>(step: &Step, node: Node<'a>, path: &str) -> Vec<(String, Node<'a>)> {
+    let node = resolve(node);
+    match (step, node) {
+        (Step::Field(name), Node::Value(value, rsz)) => fields(value, rsz)
+            .filter(|(field_name, _)| field_name == name)
+            .map(|(field_name, field)| (format!("{path}.{field_name}"), field))
+            .collect(),
+        (Step::AnyField, Node::Value(value, rsz)) => fields(value, rsz)
+            .map(|(field_name, field)| (format!("{path}.{field_name}"), field))
+            .collect(),
+        (Step::Index(i), Node::Type(RszType::Array(values), rsz)) => values.get(*i)
+            .map(|value| vec![(format!("{path}[{i}]"), Node::Type(value, rsz))])
+            .unwrap_or_default(),
+        (Step::AnyIndex, Node::Type(RszType::Array(values), rsz)) => values.iter()
+            .enumerate()
+            .map(|(i, value)| (format!("{path}[{i}]"), Node::Type(value, rsz)))
+            .collect(),
+        (Step::Filter(conditions), Node::Type(RszType::Array(values), rsz)) => values.iter()
+            .enumerate()
+            .map(|(i, value)| (format!("{path}[{i}]"), Node::Type(value, rsz)))
+            .filter(|(_, node)| conditions.iter().all(|condition| condition.matches(*node)))
+            .collect(),
+        (Step::Filter(conditions), node) => {
+            if conditions.iter().all(|condition| condition.matches(node)) {
+                vec![(path.to_string(), node)]
+            } else {
+                vec![]
+            }
+        }
+        _ => vec![],
+    }
+}
+
+fn fields<'a>(value: &'a RszValue, rsz: &'a DeRsz<'a>) -> impl Iterator<Item = (&'a str, Node<'a>)> + 'a {
+    let info = rsz.db.structs.get(&value.hash);
+    info.into_iter()
+        .flat_map(|info| info.fields.iter().zip(&value.fields))
+        .map(move |(field, value)| (field.name.as_str(), Node::Type(value, rsz)))
+}
+
+impl Condition {
+    // true if any value the path reaches compares true
+    fn matches(&self, node: Node) -> bool {
+        let mut nodes = vec![(String::new(), node)];
+        for step in &self.path {
+            nodes = nodes.into_iter()
+                .flat_map(|(path, node)| apply_step(step, node, &path))
+                .collect();
+        }
+        nodes.into_iter().any(|(_, node)| self.compare(&scalar(node), node))
+    }
+
+    fn compare(&self, scalar: &Scalar, node: Node) -> bool {
+        let equal = match &self.value {
+            Literal::Number(n) => scalar.number == Some(*n),
+            Literal::String(s) => scalar.text.as_deref() == Some(s.as_str()),
+            Literal::Bool(b) => scalar.bool == Some(*b),
+        };
+        match self.op {
+            Op::Eq => equal,
+            Op::Ne => !equal,
+            _ => {
+                let Some(number) = scalar.number else {
+                    return false;
+                };
+                // enum names on the right compare by their number
+                let value = match (&self.value, node) {
+                    (Literal::Number(n), _) => Some(*n),
+                    (Literal::String(s), Node::Type(RszType::Enum(_, name), rsz)) => rsz.db
+                        .get_enum_value(name, s)
+                        .and_then(|v| v.parse().ok()),
+                    _ => None,
+                };
+                let Some(value) = value else {
+                    return false;
+                };
+                match self.op {
+                    Op::Lt => number < value,
+                    Op::Le => number <= value,
+                    Op::Gt => number > value,
+                    Op::Ge => number >= value,
+                    Op::Eq | Op::Ne => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+fn scalar(node: Node) -> Scalar {
+    let Node::Type(r#type, rsz) = node else {
+        return Scalar::default();
+    };
+    let number = |n: f64| Scalar { number: Some(n), ..Default::default() };
+    match r#type {
+        RszType::Int8(_) | RszType::Int16(_) | RszType::Int32(_) | RszType::Int64(_)
+        | RszType::UInt8(_) | RszType::UInt16(_) | RszType::UInt32(_) | RszType::UInt64(_) => {
+            number(int_from_rsz(r#type, rsz).unwrap_or_default() as f64)
+        }
+        RszType::Enum(underlying, name) => match int_from_rsz(underlying, rsz) {
+            Ok(v) => Scalar {
+                number: Some(v as f64),
+                text: rsz.db.get_enum_name(name, &v.to_string()),
+                bool: None,
+            },
+            Err(_) => Scalar::default(),
+        },
+        RszType::F32(v) => number(*v as f64),
+        RszType::F64(v) => number(*v),
+        RszType::F8(v) => number(minifloat::decode(F8_FORMAT, *v as u32) as f64),
+        RszType::F16(v) => number(minifloat::decode(F16_FORMAT, *v as u32) as f64),
+        RszType::Bool(v) => Scalar { bool: Some(*v), ..Default::default() },
+        RszType::String(v) => Scalar { text: Some(v.to_string().trim_end_matches('\0').to_string()), ..Default::default() },
+        RszType::Guid(v) => Scalar { text: Some(Uuid::from_bytes_le(*v).to_string()), ..Default::default() },
+        _ => Scalar::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::import::RszImporter;
+    use crate::typedb::TypeDatabase;
+    use crate::user::User;
+
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Values", "type": "Object", "original_type": "app.Item[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Item", "crc": "bb", "fields": [
+            { "name": "_Rare", "type": "S32", "original_type": "app.Rare_Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Flag", "type": "Bool", "original_type": "System.Boolean", "align": 1, "size": 1, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.Item[]", "crc": "cc", "fields": [] }
+    }"#;
+
+    const ENUMS: &str = r#"{ "app.Rare_Fixed": { "1": "RARE_1", "2": "RARE_2", "3": "RARE_3" } }"#;
+
+    const USER_JSON: &str = r#"{
+        "app.Root": {
+            "_Values": [
+                { "_Rare": "RARE_1", "_Name": "a&&b", "_Flag": true },
+                { "_Rare": "RARE_2", "_Name": "x]y", "_Flag": false },
+                { "_Rare": "RARE_3", "_Name": "c", "_Flag": true }
+            ],
+            "_Name": "root"
+        }
+    }"#;
+
+    // (path, value) of every match of each query
+    fn run_all(queries: &[&str]) -> Vec<Vec<(String, Value)>> {
+        let db = TypeDatabase::from_json(RSZ_DUMP, ENUMS);
+        let mut cursor = Cursor::new(vec![]);
+        RszImporter::import_user(USER_JSON, &db).unwrap().write(&mut cursor).unwrap();
+        let bytes = cursor.into_inner();
+        let user = User::from_bytes(&bytes).unwrap();
+        let rsz = user.rsz.deserializev2(&db, false).unwrap();
+        queries.iter()
+            .map(|query| Query::parse(query).unwrap().run(&rsz).unwrap())
+            .collect()
+    }
+
+    fn paths(results: &[(String, Value)]) -> Vec<&str> {
+        results.iter().map(|(path, _)| path.as_str()).collect()
+    }
+
+    #[test]
+    fn paths_and_wildcards() {
+        let results = run_all(&[
+            "app.Root._Name",
+            "*._Name",
+            "app.Other._Name",
+            "app.Root._Values[1]._Name",
+            "app.Root._Values[5]._Name",
+            "app.Root._Values[*]._Rare",
+            "app.Root._Values[2].*",
+        ]);
+        assert_eq!(results[0], [("app.Root._Name".to_string(), Value::from("root"))]);
+        assert_eq!(results[1], results[0]);
+        assert!(results[2].is_empty());
+        assert_eq!(results[3], [("app.Root._Values[1]._Name".to_string(), Value::from("x]y"))]);
+        assert!(results[4].is_empty());
+        let rares = results[5].iter().map(|(_, value)| value.as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(rares, ["RARE_1", "RARE_2", "RARE_3"]);
+        assert_eq!(paths(&results[6]), ["app.Root._Values[2]._Rare", "app.Root._Values[2]._Name", "app.Root._Values[2]._Flag"]);
+    }
+
+    #[test]
+    fn filters_and_comparisons() {
+        let results = run_all(&[
+            "app.Root._Values[?_Rare == 2]._Name",
+            "app.Root._Values[?_Rare == RARE_3]._Name",
+            "app.Root._Values[?_Rare >= RARE_2]._Name",
+            "app.Root._Values[?_Rare < 2]._Name",
+            "app.Root._Values[?_Rare != 2 && _Flag == true]._Name",
+            "app.Root._Values[?_Rare > 1 && _Rare <= 2]._Name",
+            "app.Root[?_Values[?_Flag == false]._Name == \"x]y\"]._Name",
+        ]);
+        assert_eq!(paths(&results[0]), ["app.Root._Values[1]._Name"]);
+        assert_eq!(paths(&results[1]), ["app.Root._Values[2]._Name"]);
+        assert_eq!(paths(&results[2]), ["app.Root._Values[1]._Name", "app.Root._Values[2]._Name"]);
+        assert_eq!(paths(&results[3]), ["app.Root._Values[0]._Name"]);
+        assert_eq!(paths(&results[4]), ["app.Root._Values[0]._Name", "app.Root._Values[2]._Name"]);
+        assert_eq!(paths(&results[5]), ["app.Root._Values[1]._Name"]);
+        // the comparison inside the nested filter isn't the one of the outer filter
+        assert_eq!(paths(&results[6]), ["app.Root._Name"]);
+    }
+
+    #[test]
+    fn string_literals_keep_operators() {
+        let results = run_all(&[
+            "app.Root._Values[?_Name==\"a&&b\"]._Rare",
+            "app.Root._Values[?_Name == \"x]y\" && _Flag == false]._Rare",
+            "app.Root._Values[?_Name == \"a\"]._Rare",
+        ]);
+        assert_eq!(results[0], [("app.Root._Values[0]._Rare".to_string(), Value::from("RARE_1"))]);
+        assert_eq!(results[1], [("app.Root._Values[1]._Rare".to_string(), Value::from("RARE_2"))]);
+        assert!(results[2].is_empty());
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert!(Query::parse("[0]._Name").is_err());
+        assert!(Query::parse("app.Root._Values[0").is_err());
+        assert!(Query::parse("app.Root._Values[x]").is_err());
+        assert!(Query::parse("app.Root._Values[?_Rare]").is_err());
+        assert!(Query::parse("app.Root._Values[?_Rare ==]").is_err());
+        assert!(Query::parse("app.Root._Values[?_Rare ~ 1]").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+
+use crate::byte_reader::map_file;
+use crate::dersz::{DeRsz, DumpOptions};
+use crate::typedb::LazyTypeDatabase;
+use crate::user::{user_file_path, User};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExternMode {
+    /// Dump referenced files next to the others and leave {"$extern": path} links
+    Link,
+    /// Dump referenced files next to the link, as {"$extern": path, "$value": root}
+    Inline,
+}
+
+// Loads user files along with the .user files they reference (extern slots and children),
+// each file is only loaded once and references back into a file being loaded stay links
+pub struct UserLoader<'a> {
+    root_dir: PathBuf,
+    mode: ExternMode,
+    options: DumpOptions<'a>,
+    db: &'a LazyTypeDatabase,
+    loaded: RefCell<HashMap<PathBuf, Rc<DeRsz<'a>>>>,
+    loading: RefCell<Vec<PathBuf>>,
+    // files loaded as a reference that still need to be dumped in link mode
+    pending: RefCell<Vec<(PathBuf, Rc<DeRsz<'a>>)>>,
+    // files dumped so far, a file of the input list that was already dumped as a reference is skipped
+    dumped: RefCell<HashSet<PathBuf>>,
+    // references that could not be loaded since the last check_references
+    failed: RefCell<Vec<String>>,
+}
+
+impl<'a> UserLoader<'a> {
+    pub fn new<P: Into<PathBuf>>(root_dir: P, mode: ExternMode, options: DumpOptions<'a>, db: &'a LazyTypeDatabase) -> UserLoader<'a> {
+        UserLoader {
+            root_dir: root_dir.into(),
+            mode,
+            options,
+            db,
+            loaded: RefCell::new(HashMap::new()),
+            loading: RefCell::new(vec![]),
+            pending: RefCell::new(vec![]),
+            dumped: RefCell::new(HashSet::new()),
+            failed: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    pub fn load(&self, file_path: &Path) -> Result<Rc<DeRsz<'a>>> {
+        if let Some(rsz) = self.loaded.borrow().get(file_path) {
+            return Ok(rsz.clone());
+        }
+
+        let map = map_file(file_path).with_context(|| format!("Could not open user file {file_path:?}"))?;
+        let user = User::from_bytes(&map)?;
+        // kept for as long as the loader, after the file is closed
+        let mut rsz = user.rsz.deserialize_owned(self.db.get()?, self.options)?;
+
+        let mut references = rsz.externs.iter()
+            .map(|(&slot, path)| (Some(slot), path.clone()))
+            .collect::<Vec<_>>();
+        references.sort();
+        for child in &user.children {
+            if !references.iter().any(|(_, path)| *path == child.name) {
+                references.push((None, child.name.clone()));
+            }
+        }
+
+        self.loading.borrow_mut().push(file_path.to_path_buf());
+        for (slot, path) in references {
+            let extern_path = user_file_path(&self.root_dir, &path);
+            if self.loading.borrow().contains(&extern_path) {
+                eprintln!("[WARN] Cyclic reference to {path} from {file_path:?}, keeping it as a link");
+                continue;
+            }
+            let extern_rsz = match self.load(&extern_path) {
+                Ok(extern_rsz) => extern_rsz,
+                Err(e) => {
+                    self.failed.borrow_mut().push(format!("{path} referenced from {file_path:?}: {e:?}"));
+                    continue;
+                }
+            };
+            if let (Some(slot), ExternMode::Inline) = (slot, self.mode) {
+                rsz.inline_externs.insert(slot, extern_rsz);
+            }
+        }
+        self.loading.borrow_mut().pop();
+
+        let rsz = Rc::new(rsz);
+        self.loaded.borrow_mut().insert(file_path.to_path_buf(), rsz.clone());
+        if !self.loading.borrow().is_empty() && self.mode == ExternMode::Link {
+            self.pending.borrow_mut().push((file_path.to_path_buf(), rsz.clone()));
+        }
+        Ok(rsz)
+    }
+
+    // the referenced files that were not dumped yet
+    pub fn take_pending(&self) -> Vec<(PathBuf, Rc<DeRsz<'a>>)> {
+        let pending = std::mem::take(&mut *self.pending.borrow_mut());
+        let mut dumped = self.dumped.borrow_mut();
+        pending.into_iter().filter(|(file_path, _)| dumped.insert(file_path.clone())).collect()
+    }
+
+    // false when the file was already dumped as a reference of an earlier file
+    pub fn start_dump(&self, file_path: &Path) -> bool {
+        self.dumped.borrow_mut().insert(file_path.to_path_buf())
+    }
+
+    // the files are still dumped with links where a reference failed to load, this makes it an error after that
+    pub fn check_references(&self) -> Result<()> {
+        let failed = std::mem::take(&mut *self.failed.borrow_mut());
+        if !failed.is_empty() {
+            bail!("Could not load {} reference(s):\n{}", failed.len(), failed.join("\n"))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::import::RszImporter;
+    use crate::typedb::TypeDatabase;
+
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Node", "crc": "aa", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Next", "type": "UserData", "original_type": "app.Node", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    // A and B link to each other, C links to B and to D which is missing
+    fn write_root_dir(name: &str) -> (PathBuf, LazyTypeDatabase) {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let root_dir = std::env::temp_dir().join(format!("mhwsgen_resolve_{name}_{}", std::process::id()));
+        let files = [("A", 1, "B"), ("B", 2, "A"), ("C", 3, "B")];
+        for (file, n, next) in files {
+            let json = format!(r#"{{ "app.Node": {{ "_N": {n}, "_Next": {{ "$extern": "GameDesign/{next}.user", "$type": "app.Node" }} }} }}"#);
+            let mut user = RszImporter::import_user(&json, &db).unwrap();
+            if file == "C" {
+                user.children.push(crate::user::UserChild { hash: 0, name: "GameDesign/D.user".to_string() });
+            }
+            let mut cursor = Cursor::new(vec![]);
+            user.write(&mut cursor).unwrap();
+            let path = user_file_path(&root_dir, &format!("GameDesign/{file}.user"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, cursor.into_inner()).unwrap();
+        }
+        (root_dir, LazyTypeDatabase::from_db(db))
+    }
+
+    fn file(root_dir: &Path, name: &str) -> PathBuf {
+        user_file_path(root_dir, &format!("GameDesign/{name}.user"))
+    }
+
+    #[test]
+    fn link_mode_dumps_references_once() {
+        let (root_dir, db) = write_root_dir("link");
+        let loader = UserLoader::new(&root_dir, ExternMode::Link, DumpOptions::default(), &db);
+        assert!(loader.start_dump(&file(&root_dir, "A")));
+        let a = serde_json::to_value(&*loader.load(&file(&root_dir, "A")).unwrap()).unwrap();
+        let pending = loader.take_pending().into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+        // B is in the input list after A, it was already dumped as a reference of A
+        let dump_b = loader.start_dump(&file(&root_dir, "B"));
+        loader.load(&file(&root_dir, "B")).unwrap();
+        let pending_after_b = loader.take_pending();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        assert_eq!(a["app.Node"]["_Next"]["$extern"], "GameDesign/B.user");
+        assert!(a["app.Node"]["_Next"].get("$value").is_none());
+        assert_eq!(pending, [file(&root_dir, "B")]);
+        assert!(!dump_b);
+        assert!(pending_after_b.is_empty());
+    }
+
+    #[test]
+    fn inline_mode_keeps_cycles_as_links() {
+        let (root_dir, db) = write_root_dir("inline");
+        let loader = UserLoader::new(&root_dir, ExternMode::Inline, DumpOptions::default(), &db);
+        let a = serde_json::to_value(&*loader.load(&file(&root_dir, "A")).unwrap()).unwrap();
+        let pending = loader.take_pending();
+        let checked = loader.check_references();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        let b = &a["app.Node"]["_Next"];
+        assert_eq!((&b["$extern"], &b["$value"]["_N"]), (&Value::from("GameDesign/B.user"), &Value::from(2)));
+        // B links back to A, which was still being loaded
+        assert_eq!(b["$value"]["_Next"]["$extern"], "GameDesign/A.user");
+        assert!(b["$value"]["_Next"].get("$value").is_none());
+        assert!(pending.is_empty());
+        checked.unwrap();
+    }
+
+    #[test]
+    fn failed_reference_is_an_error() {
+        let (root_dir, db) = write_root_dir("failed");
+        let loader = UserLoader::new(&root_dir, ExternMode::Link, DumpOptions::default(), &db);
+        let c = loader.load(&file(&root_dir, "C")).map(|_| ());
+        let checked = loader.check_references();
+        let checked_again = loader.check_references();
+        std::fs::remove_dir_all(&root_dir).unwrap();
+
+        c.unwrap();
+        let e = checked.unwrap_err().to_string();
+        assert!(e.contains("Could not load 1 reference(s)") && e.contains("GameDesign/D.user"), "{e}");
+        checked_again.unwrap();
+    }
+}
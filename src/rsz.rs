@@ -1,13 +1,17 @@
 
 use crate::dersz::*;
 
+use crate::align::*;
+use crate::diagnostic::ParseDiagnostic;
 use crate::file_ext::*;
+use crate::typedb::TypeDatabase;
 use anyhow::{bail, Context, Result};
-use serde::*;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt::Debug;
-use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
 #[derive(Debug)]
 pub struct Extern {
@@ -22,11 +26,12 @@ pub struct TypeDescriptor {
 }
 
 #[derive(Debug)]
-pub struct Rsz {
+pub struct Rsz<'data> {
     pub roots: Vec<u32>,
     pub extern_slots: HashMap<u32, Extern>,
     pub type_descriptors: Vec<TypeDescriptor>,
-    pub data: Vec<u8>,
+    // borrowed from the file when parsed with from_bytes
+    pub data: Cow<'data, [u8]>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,7 +39,7 @@ pub struct Rsz {
 enum NodeSlot {
     None,
     Extern(String),
-    Instance(RszValue),
+    Instance(RszValue<'static>),
 }
 
 #[allow(dead_code)]
@@ -46,14 +51,14 @@ impl NodeSlot {
         }
     }
 
-    fn get_instance(&self) -> Result<&RszValue> {
+    fn get_instance(&self) -> Result<&RszValue<'static>> {
         match self {
             NodeSlot::Instance(rsz) => Ok(rsz),
             _ => bail!("The node slot doesn't contain instance: {:?}", self),
         }
     }
 
-    fn take_instance(&mut self) -> Result<RszValue> {
+    fn take_instance(&mut self) -> Result<RszValue<'static>> {
         if matches!(self, NodeSlot::Instance(_)) {
             let NodeSlot::Instance(rsz) = std::mem::replace(self, NodeSlot::None) else {
                 unreachable!()
@@ -65,8 +70,18 @@ impl NodeSlot {
     }
 }
 
-impl Rsz {
-    pub fn new<F: Read + Seek>(mut file: F, base: u64) -> Result<Rsz> {
+impl<'data> Rsz<'data> {
+    // the data block is borrowed instead of copied
+    pub fn from_bytes(data: &'data [u8], base: u64) -> Result<Rsz<'data>> {
+        let mut cursor = Cursor::new(data);
+        let mut rsz = Self::read_header(&mut cursor, base)
+            .map_err(|e| ParseDiagnostic::from_reader("RSZ", &mut cursor).attach(e))?;
+        rsz.data = Cow::Borrowed(data.get(cursor.position() as usize..).unwrap_or_default());
+        Ok(rsz)
+    }
+
+    // Everything before the data block, leaves the reader at the start of the data
+    fn read_header<F: Read + Seek>(mut file: F, base: u64) -> Result<Rsz<'data>> {
         file.seek(SeekFrom::Start(base))?;
         let magic = file.read_magic()?;
         if &magic != b"RSZ\0" {
@@ -144,22 +159,21 @@ impl Rsz {
         file.seek_assert_align_up(base + data_offset, 16)
             .context("Undiscovered data before data")?;
 
-        let mut data = vec![];
-        file.read_to_end(&mut data)?;
-        
         Ok(Rsz {
             roots,
             extern_slots,
             type_descriptors,
-            data,
+            data: Cow::default(),
         })
     }
 
 
-    pub fn deserializev2(&self) -> Result<DeRsz> {
+    // lenient keeps fields of unknown types as raw bytes instead of failing.
+    // Strings and raw data borrow from the rsz data, so the dump can't outlive it
+    pub fn deserializev2<'a>(&'a self, db: &'a TypeDatabase, lenient: bool) -> Result<DeRsz<'a>> {
         let mut node_buf: Vec<NodeSlot> = vec![NodeSlot::None];
         //println!("{:?}", &self.data[0..128]);
-        let mut cursor = Cursor::new(&self.data);
+        let mut cursor = Cursor::new(&self.data[..]);
         let mut structs: Vec<RszValue> = Vec::new();
 
         for (i, &TypeDescriptor { hash, crc }) in self.type_descriptors.iter().enumerate() {
@@ -169,27 +183,36 @@ impl Rsz {
                 }
                 node_buf.push(NodeSlot::Extern(slot_extern.path.clone()));
                 //println!("{:?}", node_buf);
+                // keep an empty instance in the slot so object indices still line up
+                structs.push(RszValue {
+                    crc,
+                    hash,
+                    fields: vec![],
+                });
                 continue;
             }
 
             //println!("{hash:08x}, {crc:08x}");
-            let something = RszDump::parse_struct(&mut cursor, TypeDescriptor{hash, crc})?;
+            let something = RszDump::parse_struct(&mut cursor, TypeDescriptor{hash, crc}, db, lenient)
+                .map_err(|mut e| {
+                    if e.downcast_ref::<ParseDiagnostic>().is_none() {
+                        e = ParseDiagnostic::from_reader("RSZ data", &mut cursor).attach(e);
+                    }
+                    if let Some(diagnostic) = e.downcast_mut::<ParseDiagnostic>() {
+                        let name = db.structs.get(&hash).map(|s| s.name.clone()).unwrap_or_default();
+                        diagnostic.instance = Some((u32::try_from(i).unwrap_or(u32::MAX), hash, name));
+                    }
+                    e
+                })?;
             //println!("{something:?}");
             structs.push(something);
         }
  
-        let mut roots = Vec::new();
-        for root in &self.roots {
-            //println!("{root}");
-            // check for object index and return that too
-            match structs.get(*root as usize) {
-                None => (),
-                Some(obj) => roots.push(obj.to_owned())
-            }
-        }
-        //let results = self.roots.iter().map(|root| {
-        //    structs.get(*root as usize).unwrap()
-        //}).collect::<Vec<RszStruct<RszType>>>();
+        // roots pointing past the instances are dropped
+        let roots = self.roots.iter()
+            .copied()
+            .filter(|&root| (root as usize) < structs.len())
+            .collect();
 
         let mut leftover = vec![];
         cursor.read_to_end(&mut leftover)?;
@@ -198,77 +221,151 @@ impl Rsz {
             println!("Left over data {leftover:?}");
         }
 
+        let externs = self.extern_slots.iter()
+            .map(|(&slot, Extern { path, .. })| (slot, path.clone()))
+            .collect();
+
         Ok(DeRsz{
             roots,
-            structs
+            structs,
+            externs,
+            inline_externs: HashMap::new(),
+            object_refs: false,
+            emitted: RefCell::new(HashSet::new()),
+            msg_text: None,
+            db,
         })
     }
 
-    /*pub fn verify_crc(&self, crc_mismatches: &mut BTreeMap<&str, u32>, print_all: bool) {
-        for td in &self.type_descriptors {
-            if let Some(type_info) = RSZ_TYPE_MAP.get(&td.hash) {
-                if print_all
-                    || (!type_info.versions.contains_key(&td.crc) && !type_info.versions.is_empty())
-                {
-                    crc_mismatches.insert(type_info.symbol, td.crc);
-                }
+    pub fn deserialize_with<'a>(&'a self, db: &'a TypeDatabase, options: DumpOptions<'a>) -> Result<DeRsz<'a>> {
+        let mut rsz = self.deserializev2(db, options.lenient)?;
+        rsz.object_refs = options.object_refs;
+        rsz.msg_text = options.msg_text;
+        Ok(rsz)
+    }
+
+    // A dump that owns its strings, for keeping it after the file is closed
+    pub fn deserialize_owned<'a>(&self, db: &'a TypeDatabase, options: DumpOptions<'a>) -> Result<DeRsz<'a>> {
+        let rsz = self.deserialize_with(db, options)?;
+        Ok(DeRsz {
+            roots: rsz.roots,
+            structs: rsz.structs.into_iter().map(RszValue::into_owned).collect(),
+            externs: rsz.externs,
+            inline_externs: HashMap::new(),
+            object_refs: options.object_refs,
+            emitted: RefCell::new(HashSet::new()),
+            msg_text: options.msg_text,
+            db,
+        })
+    }
+
+    pub fn serializev2(&self, dersz: &DeRsz) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut structs = dersz.structs.iter();
+        for (i, TypeDescriptor { hash, .. }) in self.type_descriptors.iter().enumerate() {
+            let value = structs.next().context("Not enough instances for type descriptors")?;
+            if value.hash != *hash {
+                bail!("Instance {i} hash mismatch {:08x} != {:08x}", value.hash, hash)
+            }
+            // extern instances live in their own file
+            if self.extern_slots.contains_key(&u32::try_from(i)?) {
+                continue;
             }
+            RszDump::write_struct(&mut cursor, value, dersz.db)
+                .with_context(|| format!("Instance {i}"))?;
         }
-    }*/
-}
+        if structs.next().is_some() {
+            bail!("More instances than type descriptors")
+        }
+        Ok(cursor.into_inner())
+    }
 
+    pub fn write<F: Write + Seek>(&self, mut file: F, base: u64) -> Result<()> {
+        file.seek(SeekFrom::Start(base))?;
 
-#[derive(Debug, Serialize, Clone)]
-#[allow(dead_code)]
-pub enum ExternUser<T> {
-    Path(String),
-    Loaded(T),
-}
+        let type_descriptor_offset = 0x30 + self.roots.len() as u64 * 4;
+        let string_table_offset =
+            align_up(type_descriptor_offset + self.type_descriptors.len() as u64 * 8, 16);
+
+        // the strings directly follow the slot info, in slot order
+        let mut externs = self.extern_slots.iter().collect::<Vec<_>>();
+        externs.sort_by_key(|(slot, _)| **slot);
+        let mut string_offset = string_table_offset + externs.len() as u64 * 16;
+        let mut extern_offsets = vec![];
+        for (_, Extern { path, .. }) in &externs {
+            extern_offsets.push(string_offset);
+            string_offset += (path.encode_utf16().count() as u64 + 1) * 2;
+        }
+        let data_offset = align_up(string_offset, 16);
+
+        file.write_magic(b"RSZ\0")?;
+        file.write_u32(0x10)?;
+        file.write_u32(self.roots.len().try_into()?)?;
+        file.write_u32(self.type_descriptors.len().try_into()?)?;
+        file.write_u32(externs.len().try_into()?)?;
+        file.write_u32(0)?;
+        file.write_u64(type_descriptor_offset)?;
+        file.write_u64(data_offset)?;
+        file.write_u64(string_table_offset)?;
 
-/*impl<T: FromUser> ExternUser<T> {
-    pub fn load<'a>(
-        &'a mut self,
-        pak: &'_ mut crate::pak::PakReader<impl Read + Seek>,
-        version_hint: Option<u32>,
-    ) -> Result<&'a mut T> {
-        match self {
-            ExternUser::Path(path) => {
-                let index = pak.find_file(path)?;
-                let file = pak.read_file(index)?;
-                let user = crate::user::User::new(Cursor::new(file))?;
-                *self = ExternUser::Loaded(user.rsz.deserialize_single(version_hint)?);
-                if let ExternUser::Loaded(t) = self {
-                    Ok(t)
-                } else {
-                    unreachable!()
-                }
-            }
-            ExternUser::Loaded(t) => Ok(t),
+        for root in &self.roots {
+            file.write_u32(*root)?;
         }
-    }
 
-    pub fn unwrap(&self) -> &T {
-        match self {
-            ExternUser::Path(_) => {
-                panic!("ExternUser not loaded")
-            }
-            ExternUser::Loaded(t) => t,
+        for TypeDescriptor { hash, crc } in &self.type_descriptors {
+            file.write_u32(*hash)?;
+            file.write_u32(*crc)?;
         }
-    }
-}*/
 
-/*impl<T> FieldFromRsz for ExternUser<T> {
-    fn field_from_rsz(rsz: &mut RszDeserializer) -> Result<Self> {
-        rsz.cursor.seek_align_up(4)?;
-        let extern_path = rsz.get_extern()?.to_owned();
-        Ok(ExternUser::Path(extern_path))
+        file.write_pad_to(base + string_table_offset)?;
+        for ((slot, Extern { hash, .. }), offset) in externs.iter().zip(&extern_offsets) {
+            file.write_u32(**slot)?;
+            file.write_u32(*hash)?;
+            file.write_u64(*offset)?;
+        }
+        for (_, Extern { path, .. }) in &externs {
+            file.write_u16str(path)?;
+        }
+
+        file.write_pad_to(base + data_offset)?;
+        file.write_all(&self.data)?;
+        Ok(())
     }
-}*/
 
-/*impl<T> FieldFromRsz for Option<ExternUser<T>> {
-    fn field_from_rsz(rsz: &mut RszDeserializer) -> Result<Self> {
-        rsz.cursor.seek_align_up(4)?;
-        let extern_path = rsz.get_extern_opt()?;
-        Ok(extern_path.map(|p| ExternUser::Path(p.to_owned())))
+    // Type descriptors whose hash is missing from the dump (None) or whose crc differs from it
+    pub fn verify_crc<'a>(
+        &'a self,
+        db: &'a TypeDatabase,
+    ) -> impl Iterator<Item = (&'a TypeDescriptor, Option<&'a RszStruct<RszField>>)> + 'a {
+        self.type_descriptors
+            .iter()
+            .filter(|td| td.hash != 0)
+            .filter_map(|td| match db.structs.get(&td.hash) {
+                Some(type_info) if type_info.crc == td.crc => None,
+                type_info => Some((td, type_info)),
+            })
     }
-}*/
+}
+
+
+pub fn int_from_rsz(field: &RszType, rsz: &DeRsz) -> Result<i128> {
+    let v = match field {
+        RszType::Int8(v) => *v as i128,
+        RszType::Int16(v) => *v as i128,
+        RszType::Int32(v) => *v as i128,
+        RszType::Int64(v) => *v as i128,
+        RszType::UInt8(v) => *v as i128,
+        RszType::UInt16(v) => *v as i128,
+        RszType::UInt32(v) => *v as i128,
+        RszType::UInt64(v) => *v as i128,
+        RszType::Enum(underlying, _) => int_from_rsz(underlying, rsz)?,
+        // enum objects like _Serializable hold their value in the first field
+        RszType::Object(_, ptr) => {
+            let value = rsz.structs.get(*ptr as usize)
+                .with_context(|| format!("Object index {ptr} out of bounds"))?;
+            int_from_rsz(value.fields.first().context("Enum object without value")?, rsz)?
+        }
+        _ => bail!("Expected integer, got {field:?}"),
+    };
+    Ok(v)
+}
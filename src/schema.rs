@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::dersz::RszField;
+use crate::typedb::TypeDatabase;
+
+// The json shape a field gets dumped as by RszTypeWithInfo, written out as json schema or typescript
+enum Shape {
+    Integer,
+    Number,
+    // F8/F16, a hex string of the bits when inf or nan
+    MiniFloat,
+    Bool,
+    String,
+    Guid,
+    // nalgebra vectors, tuples and raw data all end up as arrays
+    Tuple(Vec<Shape>),
+    // trailing tuple item that can be left out
+    Optional(Box<Shape>),
+    Array(Box<Shape>),
+    Struct(String),
+    // a struct, a link to another user file, {"$ref": id} or {} for a null pointer
+    Object(String),
+    // None when the enum isn't in the enum names
+    Enum(Option<String>),
+    // list of flag names and leftover bits in hex
+    Flags(String),
+    Unsupported,
+}
+
+fn repeat(shape: fn() -> Shape, n: usize) -> Shape {
+    Shape::Tuple((0..n).map(|_| shape()).collect())
+}
+
+fn element_type(original_type: &str) -> &str {
+    original_type.strip_suffix("[]").unwrap_or(original_type)
+}
+
+// Same checks as RszType::from_field, structs are named by their output name from `Exported`
+fn field_shape(field: &RszField, exported: &Exported) -> Shape {
+    let db = exported.db;
+    let original_type = &field.original_type;
+    let is_enum = ["Serializable", "Fixed", "Serializable[]", "Fixed[]"]
+        .iter()
+        .any(|suffix| original_type.ends_with(suffix));
+    let shape = if is_enum {
+        let name = original_type.replace("[]", "").replace("_Serializable", "_Fixed");
+        if db.flag_enums.contains(&name) {
+            Shape::Flags(name)
+        } else {
+            Shape::Enum(db.enums.contains_key(&name).then_some(name))
+        }
+    } else {
+        match field.r#type.as_str() {
+            "S8" | "S16" | "S32" | "S64" | "U8" | "U16" | "U32" | "U64" => Shape::Integer,
+            "F8" | "F16" => Shape::MiniFloat,
+            "F32" | "F64" => Shape::Number,
+            "Uint2" | "Int2" | "Range" | "RangeI" => repeat(|| Shape::Integer, 2),
+            "Uint3" | "Int3" => repeat(|| Shape::Integer, 3),
+            "Uint4" | "Int4" | "Color" | "Rect" => repeat(|| Shape::Integer, 4),
+            "Vec2" | "Float2" => repeat(|| Shape::Number, 2),
+            "Vec3" | "Float3" => repeat(|| Shape::Number, 3),
+            "Vec4" | "Float4" | "Quaternion" => repeat(|| Shape::Number, 4),
+            "Mat4" => repeat(|| Shape::Number, 16),
+            "AABB" => repeat(|| repeat(|| Shape::Number, 3), 2),
+            "Capsule" => repeat(|| repeat(|| Shape::Number, 3), 3),
+            // the bytes after the extent are only there when they aren't padding
+            "OBB" => Shape::Tuple(vec![
+                repeat(|| Shape::Number, 16),
+                repeat(|| Shape::Number, 3),
+                Shape::Optional(Box::new(Shape::Array(Box::new(Shape::Integer)))),
+            ]),
+            "Data" => repeat(|| Shape::Integer, field.size as usize),
+            "Guid" => Shape::Guid,
+            "Bool" => Shape::Bool,
+            "String" | "Resource" | "RuntimeType" => Shape::String,
+            "Struct" => Shape::Struct(exported.output_name(element_type(original_type))),
+            "Object" | "UserData" => Shape::Object(exported.output_name(element_type(original_type))),
+            _ => Shape::Unsupported,
+        }
+    };
+    if field.array {
+        Shape::Array(Box::new(shape))
+    } else {
+        shape
+    }
+}
+
+// The structs that get a schema and an interface, with the name used for both.
+// Array types ("app.Foo[]") and the null type have no fields of their own
+struct Exported<'a> {
+    db: &'a TypeDatabase,
+    structs: BTreeMap<&'a str, (String, &'a [RszField])>,
+}
+
+impl<'a> Exported<'a> {
+    // Names that clean up to the same file or typescript name ("app.A_B" and "app.A.B") get their hash appended,
+    // compared case insensitive for case insensitive file systems
+    fn new(db: &'a TypeDatabase) -> Self {
+        let structs = db.structs.values()
+            .filter(|s| !s.name.is_empty() && !s.name.ends_with("[]"))
+            .collect::<Vec<_>>();
+        let mut counts = HashMap::<String, usize>::new();
+        for s in &structs {
+            *counts.entry(ts_name(&s.name).to_lowercase()).or_default() += 1;
+        }
+        let structs = structs.into_iter()
+            .map(|s| {
+                let output_name = if counts[&ts_name(&s.name).to_lowercase()] > 1 {
+                    format!("{}_{:08x}", file_name(&s.name), s.hash)
+                } else {
+                    file_name(&s.name)
+                };
+                (s.name.as_str(), (output_name, s.fields.as_slice()))
+            })
+            .collect();
+        Self { db, structs }
+    }
+
+    // refs to structs missing from the dump still get a cleaned up name
+    fn output_name(&self, name: &str) -> String {
+        self.structs.get(name).map(|(output_name, _)| output_name.clone()).unwrap_or_else(|| file_name(name))
+    }
+}
+
+fn enum_names(db: &TypeDatabase, name: &str) -> Vec<String> {
+    let mut names = db.enums[name].values().cloned().collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Struct names can have generics and nested types in them
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn ts_name(name: &str) -> String {
+    file_name(name).replace('.', "_")
+}
+
+fn schema_of(shape: &Shape) -> Value {
+    match shape {
+        Shape::Integer => json!({ "type": "integer" }),
+        Shape::Number => json!({ "type": "number" }),
+        Shape::MiniFloat => json!({ "anyOf": [{ "type": "number" }, { "type": "string", "pattern": "^0x[0-9a-f]+$" }] }),
+        Shape::Bool => json!({ "type": "boolean" }),
+        Shape::String => json!({ "type": "string" }),
+        Shape::Guid => json!({
+            "anyOf": [
+                { "type": "string", "format": "uuid" },
+                {
+                    "type": "object",
+                    "properties": {
+                        "$guid": { "type": "string", "format": "uuid" },
+                        "$msg": { "type": "string" },
+                        "$text": { "type": "object", "additionalProperties": { "type": "string" } },
+                    },
+                    "required": ["$guid"],
+                    "additionalProperties": false,
+                },
+            ]
+        }),
+        Shape::Tuple(shapes) => json!({
+            "type": "array",
+            "prefixItems": shapes.iter().map(schema_of).collect::<Vec<_>>(),
+            "items": false,
+            "minItems": shapes.iter().filter(|shape| !matches!(shape, Shape::Optional(_))).count(),
+        }),
+        Shape::Optional(shape) => schema_of(shape),
+        Shape::Array(shape) => json!({ "type": "array", "items": schema_of(shape) }),
+        Shape::Struct(name) => json!({ "$ref": format!("{name}.schema.json") }),
+        Shape::Object(name) => json!({
+            "anyOf": [
+                { "$ref": format!("{name}.schema.json") },
+                {
+                    "type": "object",
+                    "properties": {
+                        "$extern": { "type": "string" },
+                        "$type": { "type": "string" },
+                        "$value": { "$ref": format!("{name}.schema.json") },
+                    },
+                    "required": ["$extern"],
+                    "additionalProperties": false,
+                },
+                {
+                    "type": "object",
+                    "properties": { "$ref": { "type": "integer" } },
+                    "required": ["$ref"],
+                    "additionalProperties": false,
+                },
+                { "type": "object", "maxProperties": 0 },
+            ]
+        }),
+        Shape::Enum(Some(name)) => json!({ "$ref": format!("enums.schema.json#/$defs/{}", file_name(name)) }),
+        Shape::Enum(None) => json!({ "type": "string" }),
+        Shape::Flags(name) => json!({
+            "type": "array",
+            "items": {
+                "anyOf": [
+                    { "$ref": format!("enums.schema.json#/$defs/{}", file_name(name)) },
+                    { "type": "string", "pattern": "^0x[0-9a-f]+$" },
+                ]
+            }
+        }),
+        Shape::Unsupported => json!({
+            "type": "object",
+            "properties": {
+                "$unsupported": { "type": "string" },
+                "$data": { "type": "array", "items": { "type": "integer" } },
+            },
+            "required": ["$unsupported", "$data"],
+        }),
+    }
+}
+
+fn ts_of(shape: &Shape) -> String {
+    match shape {
+        Shape::Integer | Shape::Number => "number".to_string(),
+        Shape::MiniFloat => "number | `0x${string}`".to_string(),
+        Shape::Bool => "boolean".to_string(),
+        Shape::String => "string".to_string(),
+        Shape::Guid => "string | MsgGuid".to_string(),
+        Shape::Tuple(shapes) => format!("[{}]", shapes.iter().map(ts_of).collect::<Vec<_>>().join(", ")),
+        Shape::Optional(shape) => format!("{}?", ts_of(shape)),
+        Shape::Array(shape) => format!("({})[]", ts_of(shape)),
+        Shape::Struct(name) => name.replace('.', "_"),
+        Shape::Object(name) => format!("{0} | ExternLink<{0}> | ObjectRef | NullObject", name.replace('.', "_")),
+        Shape::Enum(Some(name)) => format!("Enums.{}", ts_name(name)),
+        Shape::Enum(None) => "string".to_string(),
+        Shape::Flags(name) => format!("(Enums.{} | `0x${{string}}`)[]", ts_name(name)),
+        Shape::Unsupported => "Unsupported".to_string(),
+    }
+}
+
+// One <struct>.schema.json per struct and enums.schema.json with the enums, refs between them are relative
+pub fn save_json_schemas(db: &TypeDatabase, out_dir: &Path) -> Result<usize> {
+    fs::create_dir_all(out_dir)?;
+    let exported = Exported::new(db);
+    for (name, (output_name, fields)) in &exported.structs {
+        let properties = fields.iter()
+            .map(|field| (field.name.clone(), schema_of(&field_shape(field, &exported))))
+            .chain([("$id".to_string(), json!({ "type": "integer" }))])
+            .collect::<serde_json::Map<_, _>>();
+        let schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": name,
+            "type": "object",
+            "properties": properties,
+            "required": fields.iter().map(|field| &field.name).collect::<Vec<_>>(),
+            "additionalProperties": false,
+        });
+        let f = fs::File::create(out_dir.join(format!("{output_name}.schema.json")))?;
+        serde_json::to_writer_pretty(f, &schema)?;
+    }
+
+    // values missing from the enum names are dumped as "<value> // Could not find enum value in map <enum>"
+    let defs = db.enums.keys()
+        .map(|name| (file_name(name), json!({
+            "anyOf": [
+                { "enum": enum_names(db, name) },
+                { "type": "string", "pattern": "^-?[0-9]+ // " },
+            ]
+        })))
+        .collect::<BTreeMap<_, _>>();
+    let enums = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$defs": defs,
+    });
+    let f = fs::File::create(out_dir.join("enums.schema.json"))?;
+    serde_json::to_writer_pretty(f, &enums)?;
+    Ok(exported.structs.len())
+}
+
+pub fn typescript(db: &TypeDatabase) -> String {
+    let mut ts = String::new();
+    ts.push_str("// Generated from the rsz dump, the shape of user files dumped to json\n\n");
+    ts.push_str("export type ExternLink<T> = { $extern: string; $type?: string; $value?: T };\n");
+    ts.push_str("export type ObjectRef = { $ref: number };\n");
+    ts.push_str("export type NullObject = Record<string, never>;\n");
+    ts.push_str("export type MsgGuid = { $guid: string; $msg: string; $text: Record<string, string> };\n");
+    ts.push_str("export type Unsupported = { $unsupported: string; $data: number[] };\n\n");
+
+    ts.push_str("export declare namespace Enums {\n");
+    let enums = db.enums.keys().collect::<std::collections::BTreeSet<_>>();
+    for name in enums {
+        let names = enum_names(db, name).iter()
+            .map(|value| serde_json::to_string(value).unwrap())
+            .chain(["`${number} // ${string}`".to_string()])
+            .collect::<Vec<_>>();
+        let _ = writeln!(ts, "    /** {name} */");
+        let _ = writeln!(ts, "    export type {} = {};", ts_name(name), names.join(" | "));
+    }
+    ts.push_str("}\n");
+
+    let exported = Exported::new(db);
+    for (name, (output_name, fields)) in &exported.structs {
+        let _ = writeln!(ts, "\n/** {name} */");
+        let _ = writeln!(ts, "export interface {} {{", output_name.replace('.', "_"));
+        ts.push_str("    $id?: number;\n");
+        for field in fields.iter() {
+            let _ = writeln!(ts, "    {}: {};", serde_json::to_string(&field.name).unwrap(), ts_of(&field_shape(field, &exported)));
+        }
+        ts.push_str("}\n");
+    }
+    ts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // names that all sanitize to app.Foo_A.B_ or app.A_B
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "10": { "name": "app.A_B", "crc": "0", "fields": [] },
+        "11": { "name": "app.A.B", "crc": "0", "fields": [] },
+        "12": { "name": "app.Foo<A.B>", "crc": "0", "fields": [] },
+        "13": { "name": "app.Foo_A.B_", "crc": "0", "fields": [] },
+        "14": { "name": "app.Uses", "crc": "0", "fields": [
+            { "name": "_Foo", "type": "Object", "original_type": "app.Foo<A.B>", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "20": { "name": "app.Root", "crc": "0", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    #[test]
+    fn colliding_names_get_hash() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+
+        let exported = Exported::new(&db);
+        assert_eq!(exported.output_name("app.A_B"), "app.A_B_00000010");
+        assert_eq!(exported.output_name("app.A.B"), "app.A.B_00000011");
+        assert_eq!(exported.output_name("app.Foo<A.B>"), "app.Foo_A.B__00000012");
+        assert_eq!(exported.output_name("app.Foo_A.B_"), "app.Foo_A.B__00000013");
+        assert_eq!(exported.output_name("app.Uses"), "app.Uses");
+        assert_eq!(exported.output_name("app.Root"), "app.Root");
+
+        let ts = typescript(&db);
+        let interfaces = ts.lines().filter(|line| line.starts_with("export interface ")).collect::<Vec<_>>();
+        let mut unique = interfaces.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(interfaces.len(), unique.len());
+        assert!(ts.contains("_Foo\": app_Foo_A_B__00000012 | ExternLink<app_Foo_A_B__00000012>"));
+
+        let dir = std::env::temp_dir().join("schema_colliding_names_get_hash");
+        let _ = fs::remove_dir_all(&dir);
+        let count = save_json_schemas(&db, &dir).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, count + 1);
+        let uses: Value = serde_json::from_reader(fs::File::open(dir.join("app.Uses.schema.json")).unwrap()).unwrap();
+        assert_eq!(uses["properties"]["_Foo"]["anyOf"][0]["$ref"], "app.Foo_A.B__00000012.schema.json");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
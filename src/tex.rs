@@ -1,10 +1,11 @@
-use crate::byte_reader::BytesFile;
+use crate::byte_reader::{map_file, BytesFile};
 use crate::bitfield::BitField;
+use crate::diagnostic::ParseDiagnostic;
 use crate::compression::{
     Bc1Unorm, Bc3Unorm, Bc4Unorm, Bc5Unorm, Bc7Unorm, R8G8B8A8Unorm, R8G8Unorm, R8Unorm, TexCodec
 };
 
-use std::{fmt, io::Result};
+use std::{fmt, io::{Error, ErrorKind, Result}};
 
 pub struct RGBAImage {
     pub data: Vec<u8>,
@@ -40,14 +41,23 @@ impl fmt::Display for TexInfo {
 }
 
 impl Tex {
-    pub fn new(file_name: String) -> Result<Tex> {
-        let mut data = BytesFile::new(file_name)?;
+    pub fn new(file_name: String) -> anyhow::Result<Tex> {
+        Self::from_bytes(&map_file(file_name)?)
+    }
+
+    // the compressed textures are decompressed straight from the buffer
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Tex> {
+        let mut data = BytesFile::new(data);
+        Self::read(&mut data)
+            .map_err(|e| ParseDiagnostic::from_bytes("TEX", &data.data, data.index as u64).attach(e.into()))
+    }
 
+    fn read(data: &mut BytesFile<'_>) -> Result<Tex> {
         let magic = data.readn::<u8, 4>()?;
         let m = ['T', 'E', 'X', '\0'];
         for i in 0..4 {
             if magic[i] != m[i] as u8 {
-                panic!("Invalid Magic");
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid Magic"));
             }
         }
         let name = core::str::from_utf8(&magic);
@@ -127,22 +137,26 @@ impl Tex {
                 println!("{tex_info:?}");
                 println!("{section:?}");
                 data.index = base + section.offset as usize;
-                let in_buf = data.read_bytes_to_vec(in_size).unwrap();
+                let in_buf = data.read_bytes(in_size)?;
                 let mut out_buf: Vec<u8> = Vec::new();
                 out_buf.resize(out_size, 0);
                 println!("in_size {}, out_size {}", in_size, out_size);
-                match libdeflater::GDeflateDecompressor::gdeflate_decompress(&in_buf, &mut out_buf) {
+                match libdeflater::GDeflateDecompressor::gdeflate_decompress(in_buf, &mut out_buf) {
                     Ok(x) => {
                         bytes_read += x;
                         println!("bytes read: {x}");
                     },
-                    Err(e) => panic!("Error in gdeflate decompression: {e}"),
+                    Err(e) => return Err(Error::new(ErrorKind::InvalidData, format!("Error in gdeflate decompression: {e}"))),
                 }
-                out_buf
-            }).collect::<Vec<_>>();
+                Ok(out_buf)
+            }).collect::<Result<Vec<_>>>()?;
 
 
-        assert_eq!(bytes_read, decompressed_size, "Bytes read should be the same as the decompressed size");
+        if bytes_read != decompressed_size {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Bytes read should be the same as the decompressed size, {bytes_read} != {decompressed_size}"
+            )));
+        }
         let tex = Tex {
             width: width as u32,
             height: height as u32,
@@ -0,0 +1,477 @@
+use std::{
+    collections::{HashMap, HashSet}, fmt, fs::File, io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf}, time::UNIX_EPOCH,
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use crate::dersz::{RszField, RszStruct};
+use crate::file_ext::*;
+
+// The rsz type dump (rszmhwilds.json) and the enum names (gen/enums.json) the
+// (de)serializers look types up in. Several of these can be loaded at once.
+pub struct TypeDatabase {
+    pub structs: HashMap<u32, RszStruct<RszField>>,
+    pub names: HashMap<String, u32>,
+    pub enums: HashMap<String, HashMap<String, String>>,
+    // enums dumped as a list of flag names instead of a single name
+    pub flag_enums: HashSet<String>,
+}
+
+impl fmt::Debug for TypeDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypeDatabase")
+            .field("structs", &self.structs.len())
+            .field("enums", &self.enums.len())
+            .finish()
+    }
+}
+
+// key in an enum of the enum file to mark it as a flag enum ("true") or not ("false")
+pub(crate) const FLAGS_KEY: &str = "$flags";
+
+const CACHE_MAGIC: &[u8; 4] = b"RSZC";
+const CACHE_VERSION: u32 = 1;
+
+type StructMaps = (HashMap<u32, RszStruct<RszField>>, HashMap<String, u32>);
+
+impl TypeDatabase {
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(rsz_dump: P, enums: Q, use_cache: bool) -> Result<TypeDatabase> {
+        let (structs, names) = Self::load_rsz_dump(rsz_dump.as_ref(), use_cache)?;
+        let mut enums = Self::load_enums(enums.as_ref())?;
+        let flag_enums = Self::find_flag_enums(&mut enums);
+        Ok(TypeDatabase {
+            structs,
+            names,
+            enums,
+            flag_enums,
+        })
+    }
+
+    // The json dump is big and slow to parse, so a binary copy of it is kept next to it
+    // (rszmhwilds.json.cache), it gets rebuilt when the size or modification time of the json changes
+    fn load_rsz_dump(path: &Path, use_cache: bool) -> Result<StructMaps> {
+        if !use_cache {
+            return Self::parse_rsz_dump(path);
+        }
+
+        let cache_path = Self::cache_path(path);
+        let stamp = Self::json_stamp(path)?;
+        match Self::read_cache(&cache_path, stamp) {
+            Ok(Some(maps)) => return Ok(maps),
+            Ok(None) => (),
+            Err(e) => eprintln!("[WARN] Could not read type cache {cache_path:?}: {e}"),
+        }
+
+        let maps = Self::parse_rsz_dump(path)?;
+        if let Err(e) = Self::write_cache(&cache_path, stamp, &maps.0) {
+            eprintln!("[WARN] Could not write type cache {cache_path:?}: {e}");
+        }
+        Ok(maps)
+    }
+
+    fn parse_rsz_dump(path: &Path) -> Result<StructMaps> {
+        let file = File::open(path).with_context(|| format!("Could not open rsz dump {path:?}"))?;
+        Self::read_rsz_dump(BufReader::new(file), path)
+    }
+
+    fn read_rsz_dump<R: Read>(reader: R, path: &Path) -> Result<StructMaps> {
+        #[derive(Debug, Clone, Deserialize)]
+        struct RszStructTemp<T> {
+            name: String,
+            crc: String,
+            fields: Vec<T>,
+        }
+
+        let stream = serde_json::Deserializer::from_reader(reader)
+            .into_iter::<HashMap<String, RszStructTemp<RszField>>>();
+
+        let mut structs = HashMap::new();
+        let mut names = HashMap::new();
+        for value in stream {
+            let map = value.with_context(|| format!("Error parsing rsz dump {path:?}"))?;
+            // This is where each struct is actually parsed
+            for (key, mut rsz_struct) in map {
+                for field in &mut rsz_struct.fields {
+                    if field.original_type == "ace.user_data.ExcelUserData.cData[]" {
+                        field.original_type = rsz_struct.name.clone() + ".cData[]"
+                    }
+                }
+                let rsz_struct: RszStruct<RszField> = RszStruct {
+                    crc: u32::from_str_radix(&rsz_struct.crc, 16)
+                        .with_context(|| format!("Invalid crc {:?} for struct {key}", rsz_struct.crc))?,
+                    hash: u32::from_str_radix(&key, 16)
+                        .with_context(|| format!("Invalid struct hash {key:?}"))?,
+                    name: rsz_struct.name,
+                    fields: rsz_struct.fields,
+                };
+                names.insert(rsz_struct.name.clone(), rsz_struct.hash);
+                structs.insert(rsz_struct.hash, rsz_struct);
+            }
+        }
+        Ok((structs, names))
+    }
+
+    fn cache_path(path: &Path) -> PathBuf {
+        let mut cache_path = path.as_os_str().to_owned();
+        cache_path.push(".cache");
+        PathBuf::from(cache_path)
+    }
+
+    // (size, modification time in ns) of the json the cache was made from
+    fn json_stamp(path: &Path) -> Result<(u64, u64)> {
+        let metadata = std::fs::metadata(path).with_context(|| format!("Could not open rsz dump {path:?}"))?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        Ok((metadata.len(), u64::try_from(modified)?))
+    }
+
+    fn read_cache(cache_path: &Path, stamp: (u64, u64)) -> Result<Option<StructMaps>> {
+        let file = match File::open(cache_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut file = BufReader::new(file);
+        if &file.read_magic()? != CACHE_MAGIC || file.read_u32()? != CACHE_VERSION {
+            return Ok(None);
+        }
+        if (file.read_u64()?, file.read_u64()?) != stamp {
+            return Ok(None);
+        }
+
+        let struct_count = file.read_u32()?;
+        let mut structs = HashMap::with_capacity(struct_count as usize);
+        let mut names = HashMap::with_capacity(struct_count as usize);
+        for _ in 0..struct_count {
+            let hash = file.read_u32()?;
+            let crc = file.read_u32()?;
+            let name = file.read_u8str()?;
+            let field_count = file.read_u32()?;
+            let fields = (0..field_count)
+                .map(|_| {
+                    Ok(RszField {
+                        align: file.read_u32()?,
+                        array: file.read_bool()?,
+                        name: file.read_u8str()?,
+                        native: file.read_bool()?,
+                        original_type: file.read_u8str()?,
+                        size: file.read_u32()?,
+                        r#type: file.read_u8str()?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            names.insert(name.clone(), hash);
+            structs.insert(hash, RszStruct { name, crc, hash, fields });
+        }
+        Ok(Some((structs, names)))
+    }
+
+    fn write_cache(cache_path: &Path, stamp: (u64, u64), structs: &HashMap<u32, RszStruct<RszField>>) -> Result<()> {
+        // written to a temporary file first so other processes never see half a cache
+        let mut tmp_path = cache_path.as_os_str().to_owned();
+        tmp_path.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        file.write_magic(CACHE_MAGIC)?;
+        file.write_u32(CACHE_VERSION)?;
+        file.write_u64(stamp.0)?;
+        file.write_u64(stamp.1)?;
+        file.write_u32(structs.len().try_into()?)?;
+        for rsz_struct in structs.values() {
+            file.write_u32(rsz_struct.hash)?;
+            file.write_u32(rsz_struct.crc)?;
+            file.write_u8str(&rsz_struct.name)?;
+            file.write_u32(rsz_struct.fields.len().try_into()?)?;
+            for field in &rsz_struct.fields {
+                file.write_u32(field.align)?;
+                file.write_bool(field.array)?;
+                file.write_u8str(&field.name)?;
+                file.write_bool(field.native)?;
+                file.write_u8str(&field.original_type)?;
+                file.write_u32(field.size)?;
+                file.write_u8str(&field.r#type)?;
+            }
+        }
+        file.flush()?;
+        drop(file);
+        std::fs::rename(&tmp_path, cache_path)?;
+        Ok(())
+    }
+
+    fn load_enums(path: &Path) -> Result<HashMap<String, HashMap<String, String>>> {
+        let json_data = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not open enum file {path:?}"))?;
+        serde_json::from_str(&json_data).with_context(|| format!("Error parsing enum file {path:?}"))
+    }
+
+    // An enum is a flag enum when its entry in the enum file has "$flags": "true", or without that when
+    // it has at least 3 single bit values and its other values are masks of 3 or more of those bits.
+    // Plain enums counting up from 0 have values like 3 that are 2 bits, so they don't pass.
+    fn find_flag_enums(enums: &mut HashMap<String, HashMap<String, String>>) -> HashSet<String> {
+        let mut flag_enums = HashSet::new();
+        for (name, values) in enums.iter_mut() {
+            let is_flags = match values.remove(FLAGS_KEY) {
+                Some(marker) => marker == "true",
+                None => {
+                    let values = values.keys().filter_map(|v| v.parse::<i128>().ok()).collect::<Vec<_>>();
+                    let bits = values.iter().filter(|v| v.count_ones() == 1).fold(0, |bits, v| bits | v);
+                    bits.count_ones() >= 3 && values.iter().all(|&v| {
+                        v == 0 || v == -1 || v.count_ones() == 1 || (v.count_ones() >= 3 && v & !bits == 0)
+                    })
+                }
+            };
+            if is_flags {
+                flag_enums.insert(name.clone());
+            }
+        }
+        flag_enums
+    }
+
+    fn flag_enum_name(&self, name: &str) -> Option<String> {
+        let name = name.replace("[]", "").replace("_Serializable", "_Fixed");
+        self.flag_enums.contains(&name).then_some(name)
+    }
+
+    pub fn is_flag_enum(&self, name: &str) -> bool {
+        self.flag_enum_name(name).is_some()
+    }
+
+    // The names of the single bit flags set in value, and the bits left over as hex, None if it's not a flag enum
+    pub fn get_flag_names(&self, name: &str, value: i128) -> Option<Vec<String>> {
+        let name = self.flag_enum_name(name)?;
+        // negative values come from signed fields, flags in those don't go past 32 bits
+        let mut bits = if value < 0 && value >= i32::MIN as i128 { value as i32 as u32 as u64 } else { value as u64 };
+        let mut flags = self.enums[&name].iter()
+            .filter_map(|(v, n)| Some((v.parse::<i128>().ok()?, n)))
+            .filter(|(v, _)| *v > 0 && v.count_ones() == 1 && bits & *v as u64 != 0)
+            .collect::<Vec<_>>();
+        flags.sort();
+        let mut names = flags.into_iter().map(|(v, n)| {
+            bits &= !(v as u64);
+            n.clone()
+        }).collect::<Vec<_>>();
+        if bits != 0 {
+            names.push(format!("{bits:#x}"));
+        }
+        Some(names)
+    }
+
+    // reverse of get_flag_names, names of masks are allowed too. Negative masks like -1 "ALL" are cut to
+    // the size of the field so they don't set bits past it
+    pub fn get_flag_value(&self, name: &str, flags: &[&str], size: u32) -> Result<u64> {
+        let mask = if size >= 8 { u64::MAX } else { (1 << (size * 8)) - 1 };
+        flags.iter().try_fold(0, |bits, flag| {
+            let value = match flag.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => self.get_enum_value(name, flag).and_then(|v| v.parse::<i128>().ok()).map(|v| v as u64 & mask),
+            };
+            value.map(|value| bits | value).with_context(|| format!("unknown flag {flag:?} for {name}"))
+        })
+    }
+
+    pub fn get_enum_name(&self, name: &str, value: &str) -> Option<String> {
+        let name = name.replace("[]", "").replace("_Serializable", "_Fixed");
+        self.enums.get(&name)?.get(value).cloned()
+    }
+
+    // reverse of get_enum_name, gives back the value as a string
+    pub fn get_enum_value(&self, name: &str, value_name: &str) -> Option<String> {
+        let name = name.replace("[]", "").replace("_Serializable", "_Fixed");
+        self.enums.get(&name)?
+            .iter()
+            .find(|(_value, n)| *n == value_name)
+            .map(|(value, _n)| value.to_string())
+    }
+}
+
+// Only loads the database the first time it is needed, dumping msg and tex files doesn't need it
+pub struct LazyTypeDatabase {
+    rsz_dump: PathBuf,
+    enums: PathBuf,
+    use_cache: bool,
+    db: OnceCell<TypeDatabase>,
+}
+
+impl LazyTypeDatabase {
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(rsz_dump: P, enums: Q, use_cache: bool) -> LazyTypeDatabase {
+        LazyTypeDatabase {
+            rsz_dump: rsz_dump.into(),
+            enums: enums.into(),
+            use_cache,
+            db: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self) -> Result<&TypeDatabase> {
+        self.db.get_or_try_init(|| TypeDatabase::load(&self.rsz_dump, &self.enums, self.use_cache))
+    }
+}
+
+// for tests to make a database out of their own small dump
+#[cfg(test)]
+impl TypeDatabase {
+    pub(crate) fn from_json(rsz_dump: &str, enums: &str) -> TypeDatabase {
+        let (structs, names) = Self::read_rsz_dump(rsz_dump.as_bytes(), Path::new("test")).unwrap();
+        let mut enums = serde_json::from_str(enums).unwrap();
+        let flag_enums = Self::find_flag_enums(&mut enums);
+        TypeDatabase { structs, names, enums, flag_enums }
+    }
+}
+
+#[cfg(test)]
+impl LazyTypeDatabase {
+    pub(crate) fn from_db(db: TypeDatabase) -> LazyTypeDatabase {
+        LazyTypeDatabase {
+            rsz_dump: PathBuf::new(),
+            enums: PathBuf::new(),
+            use_cache: false,
+            db: OnceCell::with_value(db),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A few made up types for the cache to round trip
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Values", "type": "Object", "original_type": "app.Root.cData[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Name", "type": "String", "original_type": "System.String", "align": 4, "size": 8, "array": false, "native": false },
+            { "name": "_Id", "type": "Guid", "original_type": "System.Guid", "align": 8, "size": 16, "array": false, "native": false },
+            { "name": "_Scale", "type": "F16", "original_type": "System.Half", "align": 2, "size": 2, "array": false, "native": false },
+            { "name": "_Pos", "type": "Vec3", "original_type": "via.vec3", "align": 16, "size": 16, "array": false, "native": false },
+            { "name": "_Flags", "type": "U32", "original_type": "app.Flags_Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Other", "type": "UserData", "original_type": "app.Other", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Root.cData", "crc": "bb", "fields": [
+            { "name": "_Rare", "type": "S32", "original_type": "app.RareTypes.Fixed", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Flag", "type": "Bool", "original_type": "System.Boolean", "align": 1, "size": 1, "array": false, "native": false },
+            { "name": "_Pos", "type": "Vec3", "original_type": "via.vec3", "align": 16, "size": 16, "array": false, "native": false },
+            { "name": "_Item", "type": "Object", "original_type": "app.ItemID_Serializable", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Res", "type": "Resource", "original_type": "via.Prefab", "align": 4, "size": 8, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.ItemID_Serializable", "crc": "cc", "fields": [
+            { "name": "_Value", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "4000": { "name": "app.Root.cData[]", "crc": "dd", "fields": [] },
+        "5000": { "name": "app.Other", "crc": "ee", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Data", "type": "Object", "original_type": "app.Root.cData", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    const ENUMS: &str = r#"{
+        "app.RareTypes.Fixed": { "0": "RARE_0", "5": "RARE_5", "8": "RARE_8" },
+        "app.ItemID_Fixed": { "1": "ITEM_A", "2": "ITEM_B" },
+        "app.Flags_Fixed": { "1": "FLAG_A", "2": "FLAG_B", "4": "FLAG_C", "-1": "ALL" }
+    }"#;
+
+    fn test_db() -> TypeDatabase {
+        TypeDatabase::from_json(RSZ_DUMP, ENUMS)
+    }
+
+    #[test]
+    fn flag_value_masks_negative() {
+        let db = test_db();
+        assert_eq!(db.get_flag_value("app.Flags_Fixed", &["ALL"], 4).unwrap(), 0xffffffff);
+        assert_eq!(db.get_flag_value("app.Flags_Fixed", &["ALL"], 2).unwrap(), 0xffff);
+        assert_eq!(db.get_flag_value("app.Flags_Fixed", &["ALL"], 8).unwrap(), u64::MAX);
+        assert_eq!(db.get_flag_value("app.Flags_Fixed", &["FLAG_A", "FLAG_C", "0x40"], 4).unwrap(), 0x45);
+        assert!(db.get_flag_value("app.Flags_Fixed", &["FLAG_D"], 4).is_err());
+    }
+
+    // the dump and an empty enum file written to a fresh directory, for the cache tests
+    fn write_dump(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("mhwsgen_typedb_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("rsz.json");
+        let enums = dir.join("enums.json");
+        std::fs::write(&json, RSZ_DUMP).unwrap();
+        std::fs::write(&enums, "{}").unwrap();
+        (dir, json, enums)
+    }
+
+    fn set_modified(path: &Path, modified: std::time::SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn cache_is_used_while_json_is_unchanged() {
+        let (dir, json, enums) = write_dump("hit");
+        let db = TypeDatabase::load(&json, &enums, true).unwrap();
+        assert!(TypeDatabase::cache_path(&json).exists());
+
+        // same size and modification time, so only the cache still has the types
+        let modified = std::fs::metadata(&json).unwrap().modified().unwrap();
+        std::fs::write(&json, " ".repeat(RSZ_DUMP.len())).unwrap();
+        set_modified(&json, modified);
+        let cached = TypeDatabase::load(&json, &enums, true).unwrap();
+        let uncached = TypeDatabase::load(&json, &enums, false).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(cached.names, db.names);
+        let fields = |db: &TypeDatabase| serde_json::to_value(&db.structs[&0x2000].fields).unwrap();
+        assert_eq!(fields(&cached), fields(&db));
+        assert!(uncached.names.is_empty());
+    }
+
+    #[test]
+    fn cache_is_rebuilt_when_json_changes() {
+        let (dir, json, enums) = write_dump("stamp");
+        TypeDatabase::load(&json, &enums, true).unwrap();
+
+        // same size, only the modification time tells them apart
+        let modified = std::fs::metadata(&json).unwrap().modified().unwrap();
+        std::fs::write(&json, RSZ_DUMP.replace("app.Other", "app.Extra")).unwrap();
+        set_modified(&json, modified + std::time::Duration::from_secs(1));
+        let db = TypeDatabase::load(&json, &enums, true).unwrap();
+        let stamp = TypeDatabase::json_stamp(&json).unwrap();
+        let rebuilt = TypeDatabase::read_cache(&TypeDatabase::cache_path(&json), stamp).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(db.names.get("app.Extra"), Some(&0x5000));
+        assert!(!db.names.contains_key("app.Other"));
+        assert_eq!(rebuilt.unwrap().1, db.names);
+    }
+
+    #[test]
+    fn cache_of_other_version_is_ignored() {
+        let (dir, json, enums) = write_dump("version");
+        TypeDatabase::load(&json, &enums, true).unwrap();
+        let cache_path = TypeDatabase::cache_path(&json);
+        let stamp = TypeDatabase::json_stamp(&json).unwrap();
+        assert!(TypeDatabase::read_cache(&cache_path, stamp).unwrap().is_some());
+
+        let mut cache = std::fs::read(&cache_path).unwrap();
+        cache[4..8].copy_from_slice(&(CACHE_VERSION + 1).to_le_bytes());
+        std::fs::write(&cache_path, cache).unwrap();
+        let read = TypeDatabase::read_cache(&cache_path, stamp).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(read.is_none());
+    }
+
+    #[test]
+    fn corrupt_cache_falls_back_to_json() {
+        let (dir, json, enums) = write_dump("corrupt");
+        let db = TypeDatabase::load(&json, &enums, true).unwrap();
+        let cache_path = TypeDatabase::cache_path(&json);
+
+        // cut off in the middle of the first struct
+        let cache = std::fs::read(&cache_path).unwrap();
+        std::fs::write(&cache_path, &cache[..32]).unwrap();
+        let stamp = TypeDatabase::json_stamp(&json).unwrap();
+        assert!(TypeDatabase::read_cache(&cache_path, stamp).is_err());
+
+        let reloaded = TypeDatabase::load(&json, &enums, true).unwrap();
+        let rewritten = std::fs::read(&cache_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded.names, db.names);
+        assert_eq!(rewritten.len(), cache.len());
+    }
+}
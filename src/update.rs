@@ -0,0 +1,94 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+// Files that were added, removed or changed between two versions of the game files.
+// Paths are relative to the root dirs so the changed list can be fed back in with -l.
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    added: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    // number of changed values for user and msg files
+    changed: Vec<(PathBuf, Option<usize>)>,
+    unchanged: usize,
+}
+
+fn content_hash(path: &Path) -> Result<Option<u64>> {
+    match fs::read(path) {
+        Ok(data) => {
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+            Ok(Some(hasher.finish()))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow!("File: {path:?} Reason: {e}")),
+    }
+}
+
+impl UpdateReport {
+    pub fn compare(
+        &mut self,
+        relative: &Path,
+        old: &Path,
+        new: &Path,
+        count_changes: impl FnOnce() -> Option<Result<usize>>,
+    ) -> Result<()> {
+        match (content_hash(old)?, content_hash(new)?) {
+            (None, None) => return Err(anyhow!("{relative:?} is in neither root dir")),
+            (Some(_), None) => self.removed.push(relative.to_path_buf()),
+            (None, Some(_)) => self.added.push(relative.to_path_buf()),
+            (Some(old_hash), Some(new_hash)) if old_hash == new_hash => self.unchanged += 1,
+            (Some(_), Some(_)) => {
+                let changes = match count_changes() {
+                    Some(Ok(changes)) => Some(changes),
+                    Some(Err(e)) => {
+                        eprintln!("[WARN] Could not compare values of {relative:?}: {e:?}");
+                        None
+                    }
+                    None => None,
+                };
+                self.changed.push((relative.to_path_buf(), changes));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        for path in &self.added {
+            println!("[ADDED] {}", path.display());
+        }
+        for path in &self.removed {
+            println!("[REMOVED] {}", path.display());
+        }
+        for (path, changes) in &self.changed {
+            match changes {
+                Some(changes) => println!("[CHANGED] {} ({changes} value(s))", path.display()),
+                None => println!("[CHANGED] {}", path.display()),
+            }
+        }
+        println!(
+            "[INFO] {} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        );
+    }
+
+    // Added and changed files, one per line like the -l list
+    pub fn save_changed_list(&self, out_dir: &str) -> Result<()> {
+        let output_path = Path::new(out_dir).join("changed.txt");
+        fs::create_dir_all(out_dir)?;
+        let mut f = fs::File::create(&output_path)?;
+        let changed = self.changed.iter().map(|(path, _)| path);
+        for path in self.added.iter().chain(changed) {
+            writeln!(f, "{}", path.display())?;
+        }
+        println!("[INFO] Saved File {:?}", &output_path);
+        Ok(())
+    }
+}
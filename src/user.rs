@@ -93,10 +93,18 @@ impl User {
     }
 }*/
 
+use crate::align::*;
+use crate::diagnostic::ParseDiagnostic;
 use crate::file_ext::*;
 use crate::rsz::*;
 use anyhow::{bail, Context, Result};
-use std::io::{Read, Seek};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Path of a .user referenced from rsz data (e.g. GameDesign/Common/Foo.user) inside the natives dir
+pub fn user_file_path(root_dir: &Path, path: &str) -> PathBuf {
+    root_dir.join("stm").join(path.to_lowercase() + ".3")
+}
 
 #[derive(Debug)]
 pub struct UserChild {
@@ -105,14 +113,21 @@ pub struct UserChild {
 }
 
 #[derive(Debug)]
-pub struct User {
+pub struct User<'data> {
     pub resource_names: Vec<String>,
     pub children: Vec<UserChild>,
-    pub rsz: Rsz,
+    pub rsz: Rsz<'data>,
 }
 
-impl User {
-    pub fn new<F: Read + Seek>(mut file: F) -> Result<User> {
+impl<'data> User<'data> {
+    // for a memory map or a buffer from an archive, the rsz data borrows from it
+    pub fn from_bytes(data: &'data [u8]) -> Result<User<'data>> {
+        let mut cursor = Cursor::new(data);
+        Self::read(&mut cursor, |_, rsz_offset| Rsz::from_bytes(data, rsz_offset))
+            .map_err(|e| ParseDiagnostic::from_reader("USR", &mut cursor).attach(e))
+    }
+
+    fn read<F: Read + Seek>(file: &mut F, read_rsz: impl FnOnce(&mut F, u64) -> Result<Rsz<'data>>) -> Result<User<'data>> {
         let magic = file.read_magic()?;
         if &magic != b"USR\0" {
             bail!("Wrong magic for USER file");
@@ -173,7 +188,7 @@ impl User {
             })
             .collect::<Result<Vec<_>>>()?;
 
-        let rsz = Rsz::new(file, rsz_offset)?;
+        let rsz = read_rsz(file, rsz_offset)?;
 
         Ok(User {
             resource_names,
@@ -181,6 +196,136 @@ impl User {
             rsz,
         })
     }
+
+    pub fn write<F: Write + Seek>(&self, mut file: F) -> Result<()> {
+        let resource_list_offset = 0x30;
+        let child_list_offset = align_up(resource_list_offset + self.resource_names.len() as u64 * 8, 16);
+
+        // resource names come first in the string table, then the child names
+        let mut string_offset = child_list_offset + self.children.len() as u64 * 16;
+        let mut name_offset = |name: &str| {
+            let offset = string_offset;
+            string_offset += (name.encode_utf16().count() as u64 + 1) * 2;
+            offset
+        };
+        let resource_name_offsets = self.resource_names.iter()
+            .map(|name| name_offset(name))
+            .collect::<Vec<_>>();
+        let child_name_offsets = self.children.iter()
+            .map(|child| name_offset(&child.name))
+            .collect::<Vec<_>>();
+        let rsz_offset = align_up(string_offset, 16);
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_magic(b"USR\0")?;
+        file.write_u32(self.resource_names.len().try_into()?)?;
+        file.write_u32(self.children.len().try_into()?)?;
+        file.write_u32(0)?;
+        file.write_u64(resource_list_offset)?;
+        file.write_u64(child_list_offset)?;
+        file.write_u64(rsz_offset)?;
+
+        file.write_pad_to(resource_list_offset)?;
+        for offset in resource_name_offsets {
+            file.write_u64(offset)?;
+        }
+
+        file.write_pad_to(child_list_offset)?;
+        for (child, offset) in self.children.iter().zip(child_name_offsets) {
+            file.write_u32(child.hash)?;
+            file.write_u32(0)?;
+            file.write_u64(offset)?;
+        }
+
+        for name in &self.resource_names {
+            file.write_u16str(name)?;
+        }
+        for child in &self.children {
+            file.write_u16str(&child.name)?;
+        }
+
+        file.write_pad_to(rsz_offset)?;
+        self.rsz.write(file, rsz_offset)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::dersz::{RszType, RszValue};
+    use crate::typedb::TypeDatabase;
+
+    const RSZ_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_Other", "type": "UserData", "original_type": "app.Other", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Other", "crc": "bb", "fields": [] }
+    }"#;
+
+    // the null instance, a link to Other.user in slot 1 and the root pointing at it
+    fn test_user() -> User<'static> {
+        let mut data = 5i32.to_le_bytes().to_vec();
+        data.extend(1u32.to_le_bytes());
+        User {
+            resource_names: vec!["Art/Model/a.pfb".to_string()],
+            children: vec![UserChild { hash: 0x2000, name: "GameDesign/Other.user".to_string() }],
+            rsz: Rsz {
+                roots: vec![2],
+                extern_slots: HashMap::from([(1, Extern { hash: 0x2000, path: "GameDesign/Other.user".to_string() })]),
+                type_descriptors: vec![
+                    TypeDescriptor { hash: 0, crc: 0 },
+                    TypeDescriptor { hash: 0x2000, crc: 0xbb },
+                    TypeDescriptor { hash: 0x1000, crc: 0xaa },
+                ],
+                data: Cow::Owned(data),
+            },
+        }
+    }
+
+    fn write(user: &User) -> Vec<u8> {
+        let mut cursor = Cursor::new(vec![]);
+        user.write(&mut cursor).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn write_reads_back() {
+        let user = test_user();
+        let bytes = write(&user);
+        let read = User::from_bytes(&bytes).unwrap();
+
+        assert_eq!(read.resource_names, user.resource_names);
+        assert_eq!(read.children.len(), 1);
+        assert_eq!((read.children[0].hash, read.children[0].name.as_str()), (0x2000, "GameDesign/Other.user"));
+        assert_eq!(read.rsz.roots, [2]);
+        assert_eq!(read.rsz.type_descriptors, user.rsz.type_descriptors);
+        let externs = read.rsz.extern_slots.iter().map(|(slot, e)| (*slot, e.hash, e.path.as_str())).collect::<Vec<_>>();
+        assert_eq!(externs, [(1, 0x2000, "GameDesign/Other.user")]);
+        assert_eq!(read.rsz.data, user.rsz.data);
+        assert_eq!(write(&read), bytes);
+    }
+
+    #[test]
+    fn instances_serialize_back_to_data() {
+        let db = TypeDatabase::from_json(RSZ_DUMP, "{}");
+        let user = test_user();
+        let rsz = user.rsz.deserializev2(&db, false).unwrap();
+        assert!(matches!(rsz.structs[2].fields[..], [RszType::Int32(5), RszType::Object(0x2000, 1)]));
+        assert_eq!(rsz.externs[&1], "GameDesign/Other.user");
+        assert_eq!(user.rsz.serializev2(&rsz).unwrap(), *user.rsz.data);
 
+        let mut swapped = user.rsz.deserializev2(&db, false).unwrap();
+        swapped.structs.swap(1, 2);
+        let e = user.rsz.serializev2(&swapped).unwrap_err().to_string();
+        assert!(e.contains("Instance 1 hash mismatch"), "{e}");
+        swapped.structs.push(RszValue { hash: 0, crc: 0, fields: vec![] });
+        swapped.structs.swap(1, 2);
+        let e = user.rsz.serializev2(&swapped).unwrap_err().to_string();
+        assert!(e.contains("More instances than type descriptors"), "{e}");
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::byte_reader::map_file;
+use crate::typedb::TypeDatabase;
+use crate::user::User;
+
+#[derive(Debug)]
+struct Mismatch {
+    hash: u32,
+    // None when the hash is not in the dump at all
+    dump_crc: Option<u32>,
+    file_crcs: BTreeSet<u32>,
+    files: Vec<PathBuf>,
+}
+
+// Structs whose layout in the files doesn't match the rsz dump, grouped by struct name.
+// Run after a game patch to see which dumps can't be trusted until the dump is updated.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    files_checked: usize,
+    mismatches: BTreeMap<String, Mismatch>,
+}
+
+impl VerifyReport {
+    pub fn verify_user(&mut self, path: &Path, db: &TypeDatabase) -> Result<()> {
+        let map = map_file(path)?;
+        let user = User::from_bytes(&map)?;
+        self.files_checked += 1;
+
+        for (td, type_info) in user.rsz.verify_crc(db) {
+            let name = match type_info {
+                Some(type_info) => type_info.name.clone(),
+                None => format!("<unknown {:08x}>", td.hash),
+            };
+            let mismatch = self.mismatches.entry(name).or_insert_with(|| Mismatch {
+                hash: td.hash,
+                dump_crc: type_info.map(|type_info| type_info.crc),
+                file_crcs: BTreeSet::new(),
+                files: vec![],
+            });
+            mismatch.file_crcs.insert(td.crc);
+            // there is a type descriptor per instance, so a type comes up once for every instance of it in the file
+            if mismatch.files.last().map(PathBuf::as_path) != Some(path) {
+                mismatch.files.push(path.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        for (name, mismatch) in &self.mismatches {
+            let file_crcs = mismatch.file_crcs.iter()
+                .map(|crc| format!("{crc:08x}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match mismatch.dump_crc {
+                Some(dump_crc) => println!(
+                    "[MISMATCH] {name} (hash {:08x}): dump crc {dump_crc:08x}, file crc {file_crcs}, {} file(s)",
+                    mismatch.hash, mismatch.files.len()
+                ),
+                None => println!(
+                    "[MISSING] {name}: not in rsz dump, file crc {file_crcs}, {} file(s)",
+                    mismatch.files.len()
+                ),
+            }
+            for file in &mismatch.files {
+                println!("    {}", file.display());
+            }
+        }
+        let missing = self.mismatches.values().filter(|m| m.dump_crc.is_none()).count();
+        println!(
+            "[INFO] Checked {} file(s): {} struct(s) with a different crc, {} missing from the rsz dump",
+            self.files_checked,
+            self.mismatches.len() - missing,
+            missing
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::import::RszImporter;
+
+    // the types as they were when the file was written
+    const FILE_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Items", "type": "Object", "original_type": "app.Item[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Tag", "type": "Object", "original_type": "app.Tag", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Item", "crc": "bb", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "3000": { "name": "app.Tag", "crc": "cc", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    // after a patch app.Item changed and app.Tag is gone
+    const NEW_DUMP: &str = r#"{
+        "0": { "name": "", "crc": "0", "fields": [] },
+        "1000": { "name": "app.Root", "crc": "aa", "fields": [
+            { "name": "_Items", "type": "Object", "original_type": "app.Item[]", "align": 4, "size": 4, "array": true, "native": false },
+            { "name": "_Tag", "type": "Object", "original_type": "app.Tag", "align": 4, "size": 4, "array": false, "native": false }
+        ] },
+        "2000": { "name": "app.Item", "crc": "99", "fields": [
+            { "name": "_N", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false },
+            { "name": "_M", "type": "S32", "original_type": "System.Int32", "align": 4, "size": 4, "array": false, "native": false }
+        ] }
+    }"#;
+
+    #[test]
+    fn crc_mismatch_is_reported() {
+        let file_db = TypeDatabase::from_json(FILE_DUMP, "{}");
+        let json = r#"{ "app.Root": { "_Items": [{ "_N": 1 }, { "_N": 2 }], "_Tag": { "_N": 3 } } }"#;
+        let mut cursor = Cursor::new(vec![]);
+        RszImporter::import_user(json, &file_db).unwrap().write(&mut cursor).unwrap();
+        let path = std::env::temp_dir().join(format!("mhwsgen_verify_{}.user.3", std::process::id()));
+        std::fs::write(&path, cursor.into_inner()).unwrap();
+
+        let mut report = VerifyReport::default();
+        let result = report.verify_user(&path, &TypeDatabase::from_json(NEW_DUMP, "{}"));
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(report.files_checked, 1);
+        assert_eq!(report.mismatches.keys().collect::<Vec<_>>(), ["<unknown 00003000>", "app.Item"]);
+        let item = &report.mismatches["app.Item"];
+        assert_eq!((item.hash, item.dump_crc), (0x2000, Some(0x99)));
+        assert_eq!(item.file_crcs, BTreeSet::from([0xbb]));
+        // both instances are in the same file
+        assert_eq!(item.files, std::slice::from_ref(&path));
+        let tag = &report.mismatches["<unknown 00003000>"];
+        assert_eq!((tag.dump_crc, &tag.file_crcs), (None, &BTreeSet::from([0xcc])));
+    }
+}